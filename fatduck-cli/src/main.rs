@@ -1,3 +1,101 @@
-fn main() {
-    println!("Hello, world!");
+use fatduck_core::{
+    chess::{verify_perft_suite, GameState},
+    neural::WeightFile,
+};
+use shakmaty::uci::Uci;
+use std::{env, path::PathBuf, process::ExitCode, time::Instant};
+
+const USAGE: &str = "Usage:
+  fatduck netinfo <file> [--json]
+  fatduck perft <fen|startpos> <depth>
+  fatduck perft suite [max-nodes]";
+
+/// Nodes per position of `perft suite` unless given.
+const DEFAULT_SUITE_NODES: u64 = 1_000_000;
+
+fn netinfo(args: &[String]) -> Result<(), String> {
+    let json = args.iter().any(|arg| arg == "--json");
+    let file = args
+        .iter()
+        .find(|arg| !arg.starts_with("--"))
+        .ok_or_else(|| USAGE.to_string())?;
+
+    // Broken nets are reported, not rejected.
+    let weight_file = WeightFile::from_filepath_unchecked(PathBuf::from(file))
+        .map_err(|e| format!("{file}: {e}"))?;
+    let info = weight_file.info();
+
+    if json {
+        println!("{}", info.to_json());
+    } else {
+        print!("{info}");
+    }
+    Ok(())
+}
+
+fn perft(args: &[String]) -> Result<(), String> {
+    if args.first().map(String::as_str) == Some("suite") {
+        let max_nodes = match args.get(1) {
+            Some(nodes) => nodes.parse().map_err(|_| USAGE.to_string())?,
+            None => DEFAULT_SUITE_NODES,
+        };
+        let start = Instant::now();
+        let checked = verify_perft_suite(max_nodes).map_err(|e| e.to_string())?;
+        println!(
+            "{checked} perft results verified in {:.1}s",
+            start.elapsed().as_secs_f64()
+        );
+        return Ok(());
+    }
+
+    let (fen, depth) = match args {
+        [fen, depth] => (fen, depth),
+        _ => return Err(USAGE.to_string()),
+    };
+    let state = if fen == "startpos" {
+        GameState::new()
+    } else {
+        GameState::from_fen(fen).map_err(|e| e.to_string())?
+    };
+    let depth: usize = depth.parse().map_err(|_| USAGE.to_string())?;
+
+    let start = Instant::now();
+    let divide = state.divide(depth).map_err(|e| e.to_string())?;
+    let elapsed = start.elapsed();
+
+    for (m, nodes) in &divide {
+        println!("{}: {nodes}", Uci::from_move(m, state.castling_mode()));
+    }
+    // At depth 0 there are no moves to split by, the position itself is the only node.
+    let total: u64 = if depth == 0 {
+        1
+    } else {
+        divide.iter().map(|(_, nodes)| nodes).sum()
+    };
+    println!();
+    println!("Nodes: {total}");
+    println!(
+        "Time:  {} ms ({:.0} nps)",
+        elapsed.as_millis(),
+        total as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+    );
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    let result = match args.first().map(String::as_str) {
+        Some("netinfo") => netinfo(&args[1..]),
+        Some("perft") => perft(&args[1..]),
+        _ => Err(USAGE.to_string()),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        }
+    }
 }
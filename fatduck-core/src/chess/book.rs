@@ -0,0 +1,354 @@
+use crate::{chess::GameState, utils::Rng};
+use shakmaty::{
+    uci::Uci,
+    zobrist::{Zobrist64, ZobristHash},
+    EnPassantMode, Move, Position, Role, Square,
+};
+use std::{fs, io, path::Path};
+use thiserror::Error;
+
+/// Size of an entry in a Polyglot book: key, move, weight and learn data, all big endian.
+const ENTRY_SIZE: usize = 16;
+
+#[derive(Error, Debug)]
+pub enum BookError {
+    #[error("Book size of {0} bytes isn't a multiple of the entry size")]
+    InvalidSize(usize),
+    #[error(transparent)]
+    IoError(#[from] io::Error),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BookSelection {
+    /// Random move, with probabilities proportional to the entry weights.
+    #[default]
+    WeightedRandom,
+    /// Move with the highest weight.
+    Best,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BookEntry {
+    pub key: u64,
+    /// Polyglot move encoding, castling as the king taking its own rook.
+    pub raw_move: u16,
+    pub weight: u16,
+    pub learn: u32,
+}
+
+impl BookEntry {
+    /// The move of the entry if it's legal in `state`.
+    pub fn to_move(&self, state: &GameState) -> Option<Move> {
+        // Rank and file of a square, three bits each.
+        let square = |bits: u16| Square::new(u32::from(bits & 63));
+        let promotion = match (self.raw_move >> 12) & 7 {
+            1 => Some(Role::Knight),
+            2 => Some(Role::Bishop),
+            3 => Some(Role::Rook),
+            4 => Some(Role::Queen),
+            _ => None,
+        };
+
+        Uci::Normal {
+            from: square(self.raw_move >> 6),
+            to: square(self.raw_move),
+            promotion,
+        }
+        .to_move(state.position())
+        .ok()
+    }
+}
+
+/// Polyglot opening book, kept in memory sorted by key.
+#[derive(Debug, Clone, Default)]
+pub struct PolyglotBook {
+    entries: Vec<BookEntry>,
+}
+
+impl PolyglotBook {
+    /// # Errors
+    ///
+    /// If the file can't be read or isn't made of whole entries.
+    pub fn from_file(path: &Path) -> Result<Self, BookError> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+
+    /// # Errors
+    ///
+    /// If `bytes` aren't made of whole entries.
+    // The slices of an entry have the lengths the conversions expect.
+    #[allow(clippy::missing_panics_doc)]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BookError> {
+        if !bytes.len().is_multiple_of(ENTRY_SIZE) {
+            return Err(BookError::InvalidSize(bytes.len()));
+        }
+
+        let mut entries: Vec<BookEntry> = bytes
+            .chunks_exact(ENTRY_SIZE)
+            .map(|entry| BookEntry {
+                key: u64::from_be_bytes(entry[0..8].try_into().expect("8 bytes")),
+                raw_move: u16::from_be_bytes([entry[8], entry[9]]),
+                weight: u16::from_be_bytes([entry[10], entry[11]]),
+                learn: u32::from_be_bytes(entry[12..16].try_into().expect("4 bytes")),
+            })
+            .collect();
+        // Books are sorted already, but lookups rely on it.
+        entries.sort_by_key(|entry| entry.key);
+
+        Ok(Self { entries })
+    }
+
+    pub const fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// All entries with the given key.
+    pub fn entries(&self, key: u64) -> &[BookEntry] {
+        let start = self.entries.partition_point(|entry| entry.key < key);
+        let end = self.entries.partition_point(|entry| entry.key <= key);
+        &self.entries[start..end]
+    }
+
+    /// Legal book moves of `state` with their weights.
+    pub fn moves(&self, state: &GameState) -> Vec<(Move, u16)> {
+        self.entries(state.polyglot_hash())
+            .iter()
+            .filter_map(|entry| Some((entry.to_move(state)?, entry.weight)))
+            .collect()
+    }
+}
+
+/// A book with the rules for playing from it.
+#[derive(Debug, Clone)]
+pub struct OpeningBook {
+    book: PolyglotBook,
+    pub selection: BookSelection,
+    /// Plies of the game, counted from the standard starting position, the book is used for.
+    pub max_depth: usize,
+    /// Cleared to play a game without the book.
+    pub enabled: bool,
+    rng: Rng,
+}
+
+impl OpeningBook {
+    pub const fn new(
+        book: PolyglotBook,
+        selection: BookSelection,
+        max_depth: usize,
+        seed: u64,
+    ) -> Self {
+        Self {
+            book,
+            selection,
+            max_depth,
+            enabled: true,
+            rng: Rng::new(seed),
+        }
+    }
+
+    pub const fn book(&self) -> &PolyglotBook {
+        &self.book
+    }
+
+    /// Book move for `state`, or `None` to search.
+    pub fn probe(&mut self, state: &GameState) -> Option<Move> {
+        let position = state.position();
+        let ply =
+            (position.fullmoves().get() as usize - 1) * 2 + usize::from(position.turn().is_black());
+        if !self.enabled || ply >= self.max_depth {
+            return None;
+        }
+
+        let moves = self.book.moves(state);
+        match self.selection {
+            BookSelection::Best => moves
+                .into_iter()
+                .rev()
+                .max_by_key(|(_, weight)| *weight)
+                .map(|(m, _)| m),
+            BookSelection::WeightedRandom => {
+                let total: u64 = moves.iter().map(|(_, weight)| u64::from(*weight)).sum();
+                if total == 0 {
+                    return None;
+                }
+
+                let mut pick = self.rng.below(total);
+                moves.into_iter().find_map(|(m, weight)| {
+                    if pick < u64::from(weight) {
+                        Some(m)
+                    } else {
+                        pick -= u64::from(weight);
+                        None
+                    }
+                })
+            }
+        }
+    }
+}
+
+impl GameState {
+    /// Polyglot key of the position. The en passant file only counts if a pawn stands next to
+    /// the double-pushed one, legal capture or not.
+    pub fn polyglot_hash(&self) -> u64 {
+        self.position
+            .zobrist_hash::<Zobrist64>(EnPassantMode::PseudoLegal)
+            .0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn play(moves: &[&str]) -> GameState {
+        let mut state = GameState::new();
+        for m in moves {
+            let m = Uci::from_ascii(m.as_bytes())
+                .unwrap()
+                .to_move(state.position())
+                .unwrap();
+            state = state.make_move(&m);
+        }
+        state
+    }
+
+    fn raw_move(from: Square, to: Square) -> u16 {
+        (u16::from(from) << 6) | u16::from(to)
+    }
+
+    fn book_bytes(entries: &[(u64, u16, u16)]) -> Vec<u8> {
+        entries
+            .iter()
+            .flat_map(|&(key, raw_move, weight)| {
+                let mut entry = key.to_be_bytes().to_vec();
+                entry.extend(raw_move.to_be_bytes());
+                entry.extend(weight.to_be_bytes());
+                entry.extend(0_u32.to_be_bytes());
+                entry
+            })
+            .collect()
+    }
+
+    fn startpos_book() -> PolyglotBook {
+        let key = GameState::new().polyglot_hash();
+        // Unsorted, as `from_bytes` must not rely on the order.
+        PolyglotBook::from_bytes(&book_bytes(&[
+            (key + 1, raw_move(Square::A2, Square::A3), 100),
+            (key, raw_move(Square::E2, Square::E4), 30),
+            (key, raw_move(Square::D2, Square::D4), 10),
+            // Illegal in the start position, skipped.
+            (key, raw_move(Square::E2, Square::E5), 1_000),
+            (key - 1, raw_move(Square::H2, Square::H3), 100),
+        ]))
+        .unwrap()
+    }
+
+    #[test]
+    fn polyglot_keys_match_the_reference() {
+        // From the Polyglot book format specification.
+        let keys = [
+            (&[][..], 0x463b_9618_1691_fc9c),
+            (&["e2e4"][..], 0x823c_9b50_fd11_4196),
+            (&["e2e4", "d7d5"][..], 0x0756_b944_61c5_0fb0),
+            (&["e2e4", "d7d5", "e4e5"][..], 0x662f_afb9_65db_29d4),
+            (&["e2e4", "d7d5", "e4e5", "f7f5"][..], 0x22a4_8b5a_8e47_ff78),
+            (
+                &["e2e4", "d7d5", "e4e5", "f7f5", "e1e2"][..],
+                0x652a_607c_a3f2_42c1,
+            ),
+            (
+                &["e2e4", "d7d5", "e4e5", "f7f5", "e1e2", "e8f7"][..],
+                0x00fd_d303_c946_bdd9,
+            ),
+            (
+                &["a2a4", "b7b5", "h2h4", "b5b4", "c2c4"][..],
+                0x3c81_23ea_7b06_7637,
+            ),
+            (
+                &["a2a4", "b7b5", "h2h4", "b5b4", "c2c4", "b4c3", "a1a3"][..],
+                0x5c3f_9b82_9b27_9560,
+            ),
+        ];
+        for (moves, key) in keys {
+            assert_eq!(play(moves).polyglot_hash(), key, "{moves:?}");
+        }
+    }
+
+    #[test]
+    fn entries_are_read_big_endian_and_sorted() {
+        let book = startpos_book();
+        assert_eq!(book.len(), 5);
+
+        let key = GameState::new().polyglot_hash();
+        let entries = book.entries(key);
+        assert_eq!(entries.len(), 3);
+        assert!(entries.iter().all(|entry| entry.key == key));
+        assert_eq!(entries[0].raw_move, raw_move(Square::E2, Square::E4));
+        assert_eq!(entries[0].weight, 30);
+
+        let moves: Vec<String> = book
+            .moves(&GameState::new())
+            .iter()
+            .map(|(m, _)| Uci::from_standard(m).to_string())
+            .collect();
+        assert_eq!(moves, ["e2e4", "d2d4"]);
+    }
+
+    #[test]
+    fn truncated_books_are_rejected() {
+        let mut bytes = book_bytes(&[(1, 2, 3)]);
+        bytes.pop();
+        assert!(matches!(
+            PolyglotBook::from_bytes(&bytes),
+            Err(BookError::InvalidSize(15))
+        ));
+    }
+
+    #[test]
+    fn castling_is_read_as_the_king_taking_its_rook() {
+        let state = play(&["e2e4", "e7e5", "g1f3", "b8c6", "f1c4", "g8f6"]);
+        let entry = BookEntry {
+            key: state.polyglot_hash(),
+            raw_move: raw_move(Square::E1, Square::H1),
+            weight: 1,
+            learn: 0,
+        };
+        assert!(entry.to_move(&state).unwrap().is_castle());
+    }
+
+    #[test]
+    fn best_selection_plays_the_heaviest_move() {
+        let mut book = OpeningBook::new(startpos_book(), BookSelection::Best, 10, 0);
+        let m = book.probe(&GameState::new()).unwrap();
+        assert_eq!(Uci::from_standard(&m).to_string(), "e2e4");
+        assert_eq!(book.probe(&play(&["e2e4"])), None);
+    }
+
+    #[test]
+    fn weighted_selection_follows_the_weights() {
+        let mut book = OpeningBook::new(startpos_book(), BookSelection::WeightedRandom, 10, 7);
+        let e4 = (0..1_000)
+            .filter(|_| {
+                let m = book.probe(&GameState::new()).unwrap();
+                Uci::from_standard(&m).to_string() == "e2e4"
+            })
+            .count();
+        // 3 in 4
+        assert!((700..800).contains(&e4), "{e4}");
+    }
+
+    #[test]
+    fn depth_and_enabled_limit_the_book() {
+        let mut book = OpeningBook::new(startpos_book(), BookSelection::Best, 0, 0);
+        assert_eq!(book.probe(&GameState::new()), None);
+
+        book.max_depth = 1;
+        assert!(book.probe(&GameState::new()).is_some());
+        book.enabled = false;
+        assert_eq!(book.probe(&GameState::new()), None);
+    }
+}
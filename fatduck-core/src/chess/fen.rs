@@ -0,0 +1,434 @@
+use crate::chess::GameState;
+use shakmaty::{
+    fen::{Epd, Fen, ParseFenError},
+    san::{ParseSanError, San, SanError},
+    CastlingMode, Chess, EnPassantMode, FromSetup, Move, Position, PositionError, Setup,
+};
+use std::{fmt, num::NonZeroU32};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum FenError {
+    #[error("Invalid FEN: {0}")]
+    Syntax(#[from] ParseFenError),
+    /// Boxed, the rejected position makes the error large.
+    #[error("Illegal position: {0}")]
+    IllegalPosition(#[source] Box<PositionError<Chess>>),
+    #[error("Invalid EPD: missing position fields")]
+    MissingFields,
+    #[error("Invalid EPD: unterminated string in operation '{0}'")]
+    UnterminatedString(String),
+    #[error("Invalid EPD: operation '{opcode}' has invalid operand '{operand}'")]
+    InvalidOperand { opcode: String, operand: String },
+    #[error("Invalid EPD: move '{san}' in operation '{opcode}': {reason}")]
+    InvalidMove {
+        opcode: String,
+        san: String,
+        reason: String,
+    },
+}
+
+impl From<PositionError<Chess>> for FenError {
+    fn from(error: PositionError<Chess>) -> Self {
+        Self::IllegalPosition(Box::new(error))
+    }
+}
+
+/// An EPD line: a position followed by operations like `bm Nf3; id "test 1";`.
+#[derive(Clone)]
+pub struct EpdRecord {
+    pub state: GameState,
+    /// Operands of `bm`
+    pub best_moves: Vec<Move>,
+    /// Operands of `am`
+    pub avoid_moves: Vec<Move>,
+    /// Operand of `id`
+    pub id: Option<String>,
+    /// All other operations, in order. `hmvc` and `fmvn` are applied to the position instead.
+    pub operations: Vec<(String, Vec<String>)>,
+}
+
+impl GameState {
+    /// Parses a FEN. A single position has no history, so it starts without repetitions.
+    ///
+    /// Castling rights may be given as X-FEN (`KQkq`, with file letters only where ambiguous) or
+    /// Shredder-FEN (`HAha`). Positions whose castling rooks aren't on the standard squares are
+    /// set up as Chess960.
+    ///
+    /// # Errors
+    ///
+    /// If the FEN is malformed or the position is illegal.
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        let setup = fen.parse::<Fen>()?.into_setup();
+        let mode = CastlingMode::detect(&setup);
+        Ok(Self::from_position(Chess::from_setup(setup, mode)?))
+    }
+
+    /// Castling rights are written as X-FEN, which is the classical notation for standard chess.
+    pub fn to_fen(&self) -> String {
+        Fen::from_position(self.position.clone(), EnPassantMode::Legal).to_string()
+    }
+
+    /// Parses an EPD line with its operations.
+    ///
+    /// # Errors
+    ///
+    /// If the position is malformed or illegal, or an operation can't be parsed, including
+    /// moves of `bm` and `am` that aren't legal in the position.
+    pub fn from_epd(epd: &str) -> Result<EpdRecord, FenError> {
+        // Board, turn, castling and en passant, the operations follow.
+        let mut rest = epd.trim();
+        let mut position_fields = Vec::with_capacity(4);
+        for _ in 0..4 {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            if end == 0 {
+                return Err(FenError::MissingFields);
+            }
+            position_fields.push(&rest[..end]);
+            rest = rest[end..].trim_start();
+        }
+        let operations = parse_operations(rest)?;
+
+        let mut setup: Setup = position_fields.join(" ").parse::<Epd>()?.into_setup();
+        for (opcode, operands) in &operations {
+            let number = || -> Result<u32, FenError> {
+                operands
+                    .first()
+                    .and_then(|operand| operand.parse().ok())
+                    .ok_or_else(|| FenError::InvalidOperand {
+                        opcode: opcode.clone(),
+                        operand: operands.join(" "),
+                    })
+            };
+            match opcode.as_str() {
+                "hmvc" => setup.halfmoves = number()?,
+                "fmvn" => setup.fullmoves = NonZeroU32::new(number()?).unwrap_or(NonZeroU32::MIN),
+                _ => {}
+            }
+        }
+
+        let mode = CastlingMode::detect(&setup);
+        let position = Chess::from_setup(setup, mode)?;
+        let moves = |opcode: &str, operands: &[String]| {
+            operands
+                .iter()
+                .map(|san| parse_san(&position, opcode, san))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        let mut record = EpdRecord {
+            state: Self::from_position(position.clone()),
+            best_moves: Vec::new(),
+            avoid_moves: Vec::new(),
+            id: None,
+            operations: Vec::new(),
+        };
+        for (opcode, operands) in operations {
+            match opcode.as_str() {
+                "bm" => record.best_moves = moves(&opcode, &operands)?,
+                "am" => record.avoid_moves = moves(&opcode, &operands)?,
+                "id" => record.id = Some(operands.join(" ")),
+                "hmvc" | "fmvn" => {}
+                _ => record.operations.push((opcode, operands)),
+            }
+        }
+
+        Ok(record)
+    }
+
+    /// The position part of an EPD, without operations.
+    pub fn to_epd(&self) -> String {
+        Epd::from_position(self.position.clone(), EnPassantMode::Legal).to_string()
+    }
+}
+
+fn parse_san(position: &Chess, opcode: &str, san: &str) -> Result<Move, FenError> {
+    let invalid = |reason: String| FenError::InvalidMove {
+        opcode: opcode.to_string(),
+        san: san.to_string(),
+        reason,
+    };
+
+    san.parse::<San>()
+        .map_err(|e: ParseSanError| invalid(e.to_string()))?
+        .to_move(position)
+        .map_err(|e: SanError| invalid(e.to_string()))
+}
+
+/// Splits `bm Nf3 e4; id "a; b";` into opcodes and operands. Quoted parts of an operand may
+/// contain spaces and semicolons, and `\"` and `\\` for quotes and backslashes.
+fn parse_operations(operations: &str) -> Result<Vec<(String, Vec<String>)>, FenError> {
+    let mut result = Vec::new();
+    let mut chars = operations.chars();
+
+    loop {
+        let mut tokens: Vec<String> = Vec::new();
+        let mut token = String::new();
+        // An empty quoted string is still an operand.
+        let mut quoted = false;
+        let mut terminated = false;
+
+        while let Some(c) = chars.next() {
+            match c {
+                ';' => {
+                    terminated = true;
+                    break;
+                }
+                // Quoted parts belong to the token they're in, like `abc"d e"` is `abcd e`.
+                '"' => {
+                    quoted = true;
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some('\\') => {
+                                if let Some(c) = chars.next() {
+                                    token.push(c);
+                                }
+                            }
+                            Some(c) => token.push(c),
+                            None => {
+                                let opcode = tokens.first().cloned().unwrap_or(token);
+                                return Err(FenError::UnterminatedString(opcode));
+                            }
+                        }
+                    }
+                }
+                c if c.is_whitespace() => {
+                    if quoted || !token.is_empty() {
+                        tokens.push(std::mem::take(&mut token));
+                        quoted = false;
+                    }
+                }
+                c => token.push(c),
+            }
+        }
+        if quoted || !token.is_empty() {
+            tokens.push(token);
+        }
+
+        if !tokens.is_empty() {
+            let opcode = tokens.remove(0);
+            result.push((opcode, tokens));
+        }
+        if !terminated {
+            return Ok(result);
+        }
+    }
+}
+
+/// `operand` in quotes, as `parse_operations` reads it back.
+fn quoted(operand: &str) -> String {
+    let mut quoted = String::with_capacity(operand.len() + 2);
+    quoted.push('"');
+    for c in operand.chars() {
+        if matches!(c, '"' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+impl fmt::Display for EpdRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let position = self.state.position();
+        write!(f, "{}", self.state.to_epd())?;
+
+        let write_moves = |f: &mut fmt::Formatter<'_>, opcode: &str, moves: &[Move]| {
+            if moves.is_empty() {
+                return Ok(());
+            }
+            write!(f, " {opcode}")?;
+            for m in moves {
+                write!(f, " {}", San::from_move(position, m))?;
+            }
+            write!(f, ";")
+        };
+        write_moves(f, "bm", &self.best_moves)?;
+        write_moves(f, "am", &self.avoid_moves)?;
+
+        if let Some(id) = &self.id {
+            write!(f, " id {};", quoted(id))?;
+        }
+        for (opcode, operands) in &self.operations {
+            write!(f, " {opcode}")?;
+            for operand in operands {
+                let needs_quotes = operand.is_empty()
+                    || operand
+                        .contains(|c: char| c.is_whitespace() || matches!(c, ';' | '"' | '\\'));
+                if needs_quotes {
+                    write!(f, " {}", quoted(operand))?;
+                } else {
+                    write!(f, " {operand}")?;
+                }
+            }
+            write!(f, ";")?;
+        }
+
+        if position.halfmoves() != 0 {
+            write!(f, " hmvc {};", position.halfmoves())?;
+        }
+        if position.fullmoves().get() != 1 {
+            write!(f, " fmvn {};", position.fullmoves())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shakmaty::{uci::Uci, Square};
+
+    const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    fn operations(input: &str) -> Vec<(String, Vec<String>)> {
+        parse_operations(input).unwrap()
+    }
+
+    fn op(opcode: &str, operands: &[&str]) -> (String, Vec<String>) {
+        (
+            opcode.to_string(),
+            operands.iter().map(ToString::to_string).collect(),
+        )
+    }
+
+    fn uci(moves: &[Move]) -> Vec<String> {
+        moves
+            .iter()
+            .map(|m| Uci::from_standard(m).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn fen_round_trips() {
+        assert_eq!(GameState::from_fen(START_FEN).unwrap().to_fen(), START_FEN);
+        assert_eq!(GameState::new().to_fen(), START_FEN);
+
+        let fen = "r3k2r/8/8/3pP3/8/8/8/R3K2R w Kq d6 0 30";
+        let state = GameState::from_fen(fen).unwrap();
+        assert_eq!(state.to_fen(), fen);
+        assert_eq!(
+            state.position().ep_square(EnPassantMode::Legal),
+            Some(Square::D6)
+        );
+
+        // En passant squares without a capture are dropped.
+        let state = GameState::from_fen("4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1").unwrap();
+        assert_eq!(state.to_fen(), "4k3/8/8/8/4P3/8/8/4K3 b - - 0 1");
+    }
+
+    #[test]
+    fn chess960_castling_is_detected() {
+        // Shredder-FEN and X-FEN of the same position.
+        let shredder = "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9";
+        let xfen = "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w KQkq - 2 9";
+        for fen in [shredder, xfen] {
+            let state = GameState::from_fen(fen).unwrap();
+            assert_eq!(state.castling_mode(), CastlingMode::Chess960, "{fen}");
+            assert_eq!(state.to_fen(), xfen);
+        }
+
+        let state = GameState::from_fen(START_FEN).unwrap();
+        assert_eq!(state.castling_mode(), CastlingMode::Standard);
+    }
+
+    #[test]
+    fn fen_errors_are_typed() {
+        assert!(matches!(
+            GameState::from_fen("not a fen"),
+            Err(FenError::Syntax(_))
+        ));
+        // Two white kings.
+        assert!(matches!(
+            GameState::from_fen("4k3/8/8/8/8/8/8/3KK3 w - - 0 1"),
+            Err(FenError::IllegalPosition(_))
+        ));
+        assert!(matches!(
+            GameState::from_epd("8/8/8/8 w"),
+            Err(FenError::MissingFields)
+        ));
+        assert!(matches!(
+            GameState::from_epd("4k3/8/8/9/8/8/8/4K3 w - - id \"x\";"),
+            Err(FenError::Syntax(_))
+        ));
+        assert!(matches!(
+            GameState::from_epd("4k3/8/8/8/8/8/8/4K3 w - - id \"open"),
+            Err(FenError::UnterminatedString(opcode)) if opcode == "id"
+        ));
+        assert!(matches!(
+            GameState::from_epd("4k3/8/8/8/8/8/8/4K3 w - - hmvc x;"),
+            Err(FenError::InvalidOperand { opcode, operand }) if opcode == "hmvc" && operand == "x"
+        ));
+        assert!(matches!(
+            GameState::from_epd("4k3/8/8/8/8/8/8/4K3 w - - bm Qh5;"),
+            Err(FenError::InvalidMove { opcode, san, .. }) if opcode == "bm" && san == "Qh5"
+        ));
+    }
+
+    #[test]
+    fn operations_are_split() {
+        assert_eq!(operations(""), []);
+        assert_eq!(
+            operations("bm Nf3 e4; id \"a; b\";c0 x"),
+            [
+                op("bm", &["Nf3", "e4"]),
+                op("id", &["a; b"]),
+                op("c0", &["x"])
+            ]
+        );
+        assert_eq!(
+            operations("  noop ;; c1 \"\" ;"),
+            [op("noop", &[]), op("c1", &[""])]
+        );
+    }
+
+    #[test]
+    fn quotes_belong_to_their_token() {
+        assert_eq!(
+            operations("c0 abc\"def\" ghi;"),
+            [op("c0", &["abcdef", "ghi"])]
+        );
+        assert_eq!(operations("c0 \"a b\"c \"d\";"), [op("c0", &["a bc", "d"])]);
+        assert_eq!(
+            operations(r#"id "say \"hi\" \\o/";"#),
+            [op("id", &[r#"say "hi" \o/"#])]
+        );
+    }
+
+    #[test]
+    fn epd_operations_are_applied() {
+        let record = GameState::from_epd(
+            "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - \
+             bm Bb5 Bc4; am a3; id \"ruy; lopez\"; hmvc 2; fmvn 3; c0 \"main line\";",
+        )
+        .unwrap();
+
+        assert_eq!(uci(&record.best_moves), ["f1b5", "f1c4"]);
+        assert_eq!(uci(&record.avoid_moves), ["a2a3"]);
+        assert_eq!(record.id.as_deref(), Some("ruy; lopez"));
+        assert_eq!(record.operations, [op("c0", &["main line"])]);
+        assert_eq!(record.state.position().halfmoves(), 2);
+        assert_eq!(record.state.position().fullmoves().get(), 3);
+    }
+
+    #[test]
+    fn epd_round_trips() {
+        let epd = "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - \
+                   bm Bb5 Bc4; am a3; id \"ruy \\\"lopez\\\"\"; c0 \"main line\" x \"\"; \
+                   hmvc 2; fmvn 3;";
+        let record = GameState::from_epd(epd).unwrap();
+        assert_eq!(record.id.as_deref(), Some("ruy \"lopez\""));
+        assert_eq!(record.to_string(), epd);
+
+        let reparsed = GameState::from_epd(&record.to_string()).unwrap();
+        assert_eq!(reparsed.id, record.id);
+        assert_eq!(reparsed.operations, record.operations);
+        assert_eq!(reparsed.state.to_fen(), record.state.to_fen());
+
+        // Without operations the record is just the position.
+        let record = GameState::from_epd(&GameState::new().to_epd()).unwrap();
+        assert_eq!(record.to_string(), GameState::new().to_epd());
+    }
+}
@@ -0,0 +1,351 @@
+use crate::chess::GameState;
+use shakmaty::{
+    zobrist::{Zobrist64, ZobristHash, ZobristValue},
+    Bitboard, CastlingSide, Chess, Color, EnPassantMode, Move, Position, Square,
+};
+
+/// Number of positions the network sees, see `encode_position_for_nn`.
+pub const ENCODER_HISTORY: usize = 8;
+
+/// States of a game from its starting position, with their Zobrist hashes. Repetition counts and
+/// cycle lengths of the states are kept up to date as moves are applied.
+#[derive(Clone)]
+pub struct PositionHistory {
+    states: Vec<GameState>,
+    hashes: Vec<u64>,
+}
+
+impl Default for PositionHistory {
+    fn default() -> Self {
+        Self::new(GameState::new())
+    }
+}
+
+impl PositionHistory {
+    pub fn new(start: GameState) -> Self {
+        let hash = Self::compute_hash(start.position());
+        Self {
+            states: vec![start],
+            hashes: vec![hash],
+        }
+    }
+
+    pub fn starting_state(&self) -> &GameState {
+        &self.states[0]
+    }
+
+    // The starting state is never removed.
+    #[allow(clippy::missing_panics_doc)]
+    pub fn last(&self) -> &GameState {
+        self.states.last().expect("history is never empty")
+    }
+
+    pub fn states(&self) -> &[GameState] {
+        &self.states
+    }
+
+    /// The most recent states, oldest first, as `encode_position_for_nn` expects them.
+    pub fn encoder_history(&self) -> &[GameState] {
+        &self.states[self.states.len().saturating_sub(ENCODER_HISTORY)..]
+    }
+
+    /// The states that still matter from the last state on: those the network sees and those
+    /// since the last irreversible move, which can be repeated. Cheaper to copy than a long game.
+    #[must_use]
+    pub fn recent(&self) -> Self {
+        let keep = ENCODER_HISTORY.max(self.last().position().halfmoves() as usize + 1);
+        let start = self.states.len().saturating_sub(keep);
+        Self {
+            states: self.states[start..].to_vec(),
+            hashes: self.hashes[start..].to_vec(),
+        }
+    }
+
+    /// Number of states, including the starting one.
+    pub const fn len(&self) -> usize {
+        self.states.len()
+    }
+
+    /// Never true, there always is a starting state.
+    pub const fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    /// Zobrist hash of the last state.
+    #[allow(clippy::missing_panics_doc)]
+    pub fn hash(&self) -> u64 {
+        *self.hashes.last().expect("history is never empty")
+    }
+
+    pub fn hashes(&self) -> &[u64] {
+        &self.hashes
+    }
+
+    /// Plays the legal move `m` from the last state.
+    pub fn append(&mut self, m: &Move) {
+        let last = self.last();
+        let mut state = last.make_move(m);
+        let hash = Self::update_hash(self.hash(), last.position(), m, state.position());
+
+        // Only positions since the last irreversible move can repeat, and only every other ply
+        // has the same side to move.
+        let reversible_plies = state.position().halfmoves() as usize;
+        let len = self.states.len();
+        for cycle_length in (2..=reversible_plies.min(len)).step_by(2) {
+            let idx = len - cycle_length;
+            if self.hashes[idx] == hash
+                && Self::same_position(self.states[idx].position(), state.position())
+            {
+                state.repetition_count = self.states[idx].repetition_count + 1;
+                state.cycle_length = u8::try_from(cycle_length).unwrap_or(u8::MAX);
+                break;
+            }
+        }
+
+        self.states.push(state);
+        self.hashes.push(hash);
+    }
+
+    /// Takes back the last move. The starting state is never removed.
+    pub fn pop(&mut self) -> Option<GameState> {
+        if self.states.len() == 1 {
+            return None;
+        }
+        self.hashes.pop();
+        self.states.pop()
+    }
+
+    /// Whether any state since the last irreversible move is a repetition.
+    pub fn did_repeat_since_last_zeroing_move(&self) -> bool {
+        self.states
+            .iter()
+            .rev()
+            .take(self.last().position().halfmoves() as usize + 1)
+            .any(|state| state.repetition_count > 0)
+    }
+
+    /// Position equality for repetitions, ignoring the move counters `Chess::eq` compares.
+    fn same_position(a: &Chess, b: &Chess) -> bool {
+        a.board() == b.board()
+            && a.turn() == b.turn()
+            && a.castles().castling_rights() == b.castles().castling_rights()
+            && a.legal_ep_square() == b.legal_ep_square()
+    }
+
+    pub fn compute_hash(position: &Chess) -> u64 {
+        position.zobrist_hash::<Zobrist64>(EnPassantMode::Legal).0
+    }
+
+    /// Hash of `after`, which is `before` with `m` played, updated from the hash of `before`
+    /// instead of computed from scratch.
+    pub fn update_hash(hash: u64, before: &Chess, m: &Move, after: &Chess) -> u64 {
+        let mut hash = Zobrist64(hash);
+
+        let mut touched = Bitboard::from_square(m.to());
+        if let Some(from) = m.from() {
+            touched.add(from);
+        }
+        match m {
+            Move::Castle { king, rook } => {
+                let side = CastlingSide::from_queen_side(rook < king);
+                let color = before.turn();
+                touched.add(side.king_to(color));
+                touched.add(side.rook_to(color));
+            }
+            Move::EnPassant { from, to } => {
+                touched.add(Square::from_coords(to.file(), from.rank()));
+            }
+            _ => {}
+        }
+
+        for square in touched {
+            if let Some(piece) = before.board().piece_at(square) {
+                hash ^= Zobrist64::zobrist_for_piece(square, piece);
+            }
+            if let Some(piece) = after.board().piece_at(square) {
+                hash ^= Zobrist64::zobrist_for_piece(square, piece);
+            }
+        }
+
+        hash ^= Zobrist64::zobrist_for_white_turn();
+
+        for color in Color::ALL {
+            for side in CastlingSide::ALL {
+                if before.castles().has(color, side) != after.castles().has(color, side) {
+                    hash ^= Zobrist64::zobrist_for_castling_right(color, side);
+                }
+            }
+        }
+
+        for position in [before, after] {
+            if let Some(square) = position.ep_square(EnPassantMode::Legal) {
+                hash ^= Zobrist64::zobrist_for_en_passant_file(square.file());
+            }
+        }
+
+        hash.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chess::{EndReason, Terminal};
+    use shakmaty::{uci::Uci, Outcome};
+
+    fn play(history: &mut PositionHistory, moves: &[&str]) {
+        for m in moves {
+            let m = Uci::from_ascii(m.as_bytes())
+                .unwrap()
+                .to_move(history.last().position())
+                .unwrap();
+            history.append(&m);
+        }
+    }
+
+    fn from_fen(fen: &str) -> PositionHistory {
+        PositionHistory::new(GameState::from_fen(fen).unwrap())
+    }
+
+    fn assert_hashes_match(history: &PositionHistory) {
+        for (state, &hash) in history.states().iter().zip(history.hashes()) {
+            assert_eq!(
+                hash,
+                PositionHistory::compute_hash(state.position()),
+                "{}",
+                state.to_fen()
+            );
+        }
+    }
+
+    #[test]
+    fn incremental_hash_matches_full_rehash() {
+        // Double pushes with and without a legal en passant, en passant, both castles, a
+        // promotion by capture and captures of unmoved rooks, which lose their castling rights.
+        let mut history = PositionHistory::default();
+        play(
+            &mut history,
+            &[
+                "e2e4", "d7d5", "e4e5", "f7f5", "e5f6", "g7f6", "g1f3", "b8c6", "f1c4", "c8e6",
+                "e1g1", "d8d6", "b1c3", "e8c8", "d2d4", "h7h5", "d1e2", "h5h4", "g2g4", "h4g3",
+                "c4d5", "g3h2", "f3h2",
+            ],
+        );
+        assert_hashes_match(&history);
+
+        let mut history = from_fen("1n2k3/P7/8/8/8/8/8/4K3 w - - 0 1");
+        play(&mut history, &["a7b8q"]);
+        assert_hashes_match(&history);
+
+        let mut history = from_fen("r2nk2r/1p4p1/8/8/8/8/1P4P1/R2NK2R w KQkq - 0 1");
+        play(&mut history, &["a1a8", "h8h1"]);
+        assert_hashes_match(&history);
+        assert!(!history.last().position().castles().any());
+    }
+
+    #[test]
+    fn incremental_hash_matches_full_rehash_in_chess960() {
+        let mut history = from_fen("1r2k1r1/pppppppp/8/8/8/8/PPPPPPPP/1R2K1R1 w GBgb - 0 1");
+        play(&mut history, &["e1g1", "e8b8"]);
+        assert_hashes_match(&history);
+
+        let mut history = from_fen("1r2k1r1/pppppppp/8/8/8/8/PPPPPPPP/1R2K1R1 w GBgb - 0 1");
+        play(&mut history, &["e1b1", "e8g8"]);
+        assert_hashes_match(&history);
+    }
+
+    #[test]
+    fn repetitions_are_counted() {
+        let mut history = PositionHistory::default();
+        play(&mut history, &["g1f3", "g8f6", "f3g1"]);
+        assert_eq!(history.last().repetition_count(), 0);
+        assert_eq!(history.last().cycle_length(), 0);
+
+        play(&mut history, &["f6g8"]);
+        assert_eq!(history.last().repetition_count(), 1);
+        assert_eq!(history.last().cycle_length(), 4);
+        assert!(history.did_repeat_since_last_zeroing_move());
+
+        play(&mut history, &["g1f3"]);
+        assert_eq!(history.last().repetition_count(), 1);
+        assert_eq!(history.last().cycle_length(), 4);
+
+        play(&mut history, &["g8f6", "f3g1", "f6g8"]);
+        assert_eq!(history.last().repetition_count(), 2);
+        assert_eq!(history.last().cycle_length(), 4);
+
+        // A pawn move can't be undone, nothing before it repeats.
+        play(&mut history, &["e2e4"]);
+        assert_eq!(history.last().repetition_count(), 0);
+        assert_eq!(history.last().cycle_length(), 0);
+        assert!(!history.did_repeat_since_last_zeroing_move());
+    }
+
+    #[test]
+    fn same_board_with_other_rights_is_no_repetition() {
+        // The king returns, but castling rights are gone.
+        let mut history = PositionHistory::default();
+        play(
+            &mut history,
+            &["e2e4", "e7e5", "e1e2", "e8e7", "e2e1", "e7e8"],
+        );
+        assert_eq!(history.last().repetition_count(), 0);
+
+        play(&mut history, &["e1e2", "e8e7", "e2e1", "e7e8"]);
+        assert_eq!(history.last().repetition_count(), 1);
+        assert_eq!(history.last().cycle_length(), 4);
+    }
+
+    #[test]
+    fn third_occurrence_is_a_draw() {
+        let mut history = PositionHistory::default();
+        play(&mut history, &["g1f3", "g8f6", "f3g1", "f6g8"]);
+        assert_eq!(history.last().compute_game_result(), None);
+
+        play(&mut history, &["g1f3", "g8f6", "f3g1", "f6g8"]);
+        assert_eq!(history.last().compute_game_result(), Some(Outcome::Draw));
+        let (terminal, result) = history.last().classify_terminal(None);
+        assert_eq!(terminal, Terminal::GameOver);
+        assert_eq!(result.unwrap().reason, EndReason::ThreefoldRepetition);
+
+        history.pop();
+        assert_eq!(history.last().compute_game_result(), None);
+    }
+
+    #[test]
+    fn recent_keeps_what_can_repeat() {
+        let mut history = PositionHistory::default();
+        play(&mut history, &["e2e4", "e7e5"]);
+        play(&mut history, &["g1f3", "g8f6", "f3g1", "f6g8"].repeat(3));
+        assert_eq!(history.last().repetition_count, 3);
+
+        // Everything since e5 can still repeat.
+        let recent = history.recent();
+        assert_eq!(recent.len(), 13);
+        assert_eq!(recent.hashes(), &history.hashes()[2..]);
+
+        play(&mut history, &["g1f3"]);
+        let mut continued = recent;
+        play(&mut continued, &["g1f3"]);
+        assert_eq!(continued.last().repetition_count, 3);
+        assert_eq!(
+            continued.last().repetition_count,
+            history.last().repetition_count
+        );
+    }
+
+    #[test]
+    fn recent_keeps_the_network_history() {
+        let mut history = PositionHistory::default();
+        play(
+            &mut history,
+            &[
+                "e2e4", "e7e5", "d2d4", "d7d5", "c2c4", "c7c5", "b2b4", "b7b5", "a2a4",
+            ],
+        );
+
+        let recent = history.recent();
+        assert_eq!(recent.len(), ENCODER_HISTORY);
+        assert_eq!(recent.encoder_history().len(), ENCODER_HISTORY);
+    }
+}
@@ -1,13 +1,57 @@
-use shakmaty::{Chess, Outcome, Position};
+use shakmaty::{CastlingMode, Chess, Color, Move, Outcome, Position};
 
-#[derive(Debug, Clone, Copy)]
+mod book;
+mod fen;
+mod history;
+mod perft;
+mod pgn;
+mod tablebase;
+
+pub use book::{BookEntry, BookError, BookSelection, OpeningBook, PolyglotBook};
+pub use fen::{EpdRecord, FenError};
+pub use history::{PositionHistory, ENCODER_HISTORY};
+pub use perft::{verify_perft_suite, PerftError, PerftPosition, PERFT_SUITE};
+pub use pgn::{MoveAnnotation, PgnError, PgnGame, PgnMove};
+pub use tablebase::{SyzygyTablebase, TablebaseError};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub enum Terminal {
+    /// Draw by a repetition inside the search tree, the game itself goes on.
     TwoFold,
     GameOver,
     Tablebase,
+    #[default]
     NonTerminal,
 }
 
+/// Scoring rules of a game.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GameRules {
+    #[default]
+    Standard,
+    /// Draws count as wins for black, as in armageddon tiebreaks.
+    Armageddon,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndReason {
+    Checkmate,
+    Stalemate,
+    InsufficientMaterial,
+    FiftyMoveRule,
+    ThreefoldRepetition,
+    TwoFoldRepetition,
+    /// Result proven by endgame tablebases.
+    Tablebase,
+}
+
+/// How a terminal state ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameResult {
+    pub outcome: Outcome,
+    pub reason: EndReason,
+}
+
 #[derive(Default, Clone)]
 pub struct GameState {
     position: Chess,
@@ -15,6 +59,7 @@ pub struct GameState {
     cycle_length: u8,
     // How many repetitions this position has had before.
     repetition_count: u8,
+    rules: GameRules,
 }
 
 impl GameState {
@@ -23,23 +68,254 @@ impl GameState {
             position: Chess::default(),
             cycle_length: 0,
             repetition_count: 0,
+            rules: GameRules::Standard,
         }
     }
 
-    pub fn position(&self) -> &Chess {
+    /// State without history, e.g. set up from a FEN.
+    pub const fn from_position(position: Chess) -> Self {
+        Self {
+            position,
+            cycle_length: 0,
+            repetition_count: 0,
+            rules: GameRules::Standard,
+        }
+    }
+
+    /// The same state played under `rules`. Moves made from it keep the rules.
+    #[must_use]
+    pub const fn with_rules(mut self, rules: GameRules) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    pub const fn rules(&self) -> GameRules {
+        self.rules
+    }
+
+    pub const fn position(&self) -> &Chess {
         &self.position
     }
 
+    /// Chess960 if the castling rooks of the starting position weren't on their standard squares.
+    pub fn castling_mode(&self) -> CastlingMode {
+        self.position.castles().mode()
+    }
+
+    /// State after playing the legal move `m`.
+    #[must_use]
+    pub fn make_move(&self, m: &Move) -> Self {
+        let mut position = self.position.clone();
+        position.play_unchecked(m);
+
+        Self {
+            position,
+            cycle_length: 0,
+            repetition_count: 0,
+            rules: self.rules,
+        }
+    }
+
+    /// `outcome` as scored under the rules of the game.
+    pub const fn score_outcome(&self, outcome: Outcome) -> Outcome {
+        match (self.rules, outcome) {
+            (GameRules::Armageddon, Outcome::Draw) => Outcome::Decisive {
+                winner: Color::Black,
+            },
+            _ => outcome,
+        }
+    }
+
     pub fn compute_game_result(&self) -> Option<Outcome> {
-        // `position.outcome()` does not consider 50-move rule or 3-fold repetition.
-        if self.position.halfmoves() >= 100 || self.repetition_count >= 2 {
-            return Some(Outcome::Draw);
+        self.classify_terminal(None).1.map(|result| result.outcome)
+    }
+
+    /// Terminal kind of the state and how it ends. `plies_from_root` is the distance from the
+    /// search root, a position repeated within it already counts as a draw. Outside of search
+    /// only the rules of the game apply.
+    pub fn classify_terminal(
+        &self,
+        plies_from_root: Option<usize>,
+    ) -> (Terminal, Option<GameResult>) {
+        let game_over = |outcome, reason| {
+            (
+                Terminal::GameOver,
+                Some(GameResult {
+                    outcome: self.score_outcome(outcome),
+                    reason,
+                }),
+            )
+        };
+
+        // Mate takes precedence over the draw rules, even on the 100th reversible ply.
+        if self.position.legal_moves().is_empty() {
+            return if self.position.is_check() {
+                game_over(
+                    Outcome::Decisive {
+                        winner: !self.position.turn(),
+                    },
+                    EndReason::Checkmate,
+                )
+            } else {
+                game_over(Outcome::Draw, EndReason::Stalemate)
+            };
         }
 
-        self.position.outcome()
+        if self.position.is_insufficient_material() {
+            return game_over(Outcome::Draw, EndReason::InsufficientMaterial);
+        }
+
+        if self.position.halfmoves() >= 100 {
+            return game_over(Outcome::Draw, EndReason::FiftyMoveRule);
+        }
+
+        if self.repetition_count >= 2 {
+            return game_over(Outcome::Draw, EndReason::ThreefoldRepetition);
+        }
+
+        if let Some(plies) = plies_from_root {
+            if self.repetition_count >= 1 && usize::from(self.cycle_length) <= plies {
+                return (
+                    Terminal::TwoFold,
+                    Some(GameResult {
+                        outcome: self.score_outcome(Outcome::Draw),
+                        reason: EndReason::TwoFoldRepetition,
+                    }),
+                );
+            }
+        }
+
+        (Terminal::NonTerminal, None)
     }
 
-    pub fn repetition_count(&self) -> u8 {
+    pub const fn repetition_count(&self) -> u8 {
         self.repetition_count
     }
+
+    /// Plies since the previous occurrence of this position, 0 if it didn't occur before.
+    pub const fn cycle_length(&self) -> u8 {
+        self.cycle_length
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shakmaty::uci::Uci;
+
+    fn classify(fen: &str) -> (Terminal, Option<GameResult>) {
+        GameState::from_fen(fen).unwrap().classify_terminal(None)
+    }
+
+    fn game_over(outcome: Outcome, reason: EndReason) -> (Terminal, Option<GameResult>) {
+        (Terminal::GameOver, Some(GameResult { outcome, reason }))
+    }
+
+    /// Plays the knights out and back `times` times from the starting position.
+    fn repeated(times: usize) -> PositionHistory {
+        let mut history = PositionHistory::default();
+        for m in ["g1f3", "g8f6", "f3g1", "f6g8"].repeat(times) {
+            let m = Uci::from_ascii(m.as_bytes())
+                .unwrap()
+                .to_move(history.last().position())
+                .unwrap();
+            history.append(&m);
+        }
+        history
+    }
+
+    #[test]
+    fn checkmate() {
+        assert_eq!(
+            classify("rnb1kbnr/pppp1ppp/8/4p3/6Pq/5P2/PPPPP2P/RNBQKBNR w KQkq - 1 3"),
+            game_over(
+                Outcome::Decisive {
+                    winner: Color::Black
+                },
+                EndReason::Checkmate
+            )
+        );
+    }
+
+    #[test]
+    fn checkmate_beats_the_fifty_move_rule() {
+        assert_eq!(
+            classify("7k/6Q1/6K1/8/8/8/8/8 b - - 100 80"),
+            game_over(
+                Outcome::Decisive {
+                    winner: Color::White
+                },
+                EndReason::Checkmate
+            )
+        );
+    }
+
+    #[test]
+    fn stalemate() {
+        assert_eq!(
+            classify("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"),
+            game_over(Outcome::Draw, EndReason::Stalemate)
+        );
+    }
+
+    #[test]
+    fn fifty_move_rule() {
+        assert_eq!(
+            classify("8/8/4k3/8/8/3K4/7R/8 w - - 100 80"),
+            game_over(Outcome::Draw, EndReason::FiftyMoveRule)
+        );
+        assert_eq!(
+            classify("8/8/4k3/8/8/3K4/7R/8 w - - 99 80"),
+            (Terminal::NonTerminal, None)
+        );
+    }
+
+    #[test]
+    fn insufficient_material() {
+        for fen in [
+            "8/8/4k3/8/8/3K4/8/8 w - - 0 1",
+            "8/8/4k3/8/8/3K4/5B2/8 w - - 0 1",
+            "8/8/4k3/8/8/3K4/5N2/8 b - - 0 1",
+        ] {
+            assert_eq!(
+                classify(fen),
+                game_over(Outcome::Draw, EndReason::InsufficientMaterial),
+                "{fen}"
+            );
+        }
+        assert_eq!(
+            classify("8/8/4k3/8/8/3K4/5P2/8 w - - 0 1"),
+            (Terminal::NonTerminal, None)
+        );
+    }
+
+    #[test]
+    fn threefold_repetition() {
+        let history = repeated(2);
+        let expected = game_over(Outcome::Draw, EndReason::ThreefoldRepetition);
+        assert_eq!(history.last().classify_terminal(None), expected);
+        // Also inside the search, however far from the root.
+        assert_eq!(history.last().classify_terminal(Some(0)), expected);
+    }
+
+    #[test]
+    fn twofold_repetition_only_counts_in_search() {
+        let state = repeated(1).last().clone();
+        assert_eq!(state.classify_terminal(None), (Terminal::NonTerminal, None));
+        assert_eq!(
+            state.classify_terminal(Some(4)),
+            (
+                Terminal::TwoFold,
+                Some(GameResult {
+                    outcome: Outcome::Draw,
+                    reason: EndReason::TwoFoldRepetition,
+                })
+            )
+        );
+        // The earlier occurrence is before the root, so the game decides.
+        assert_eq!(
+            state.classify_terminal(Some(3)),
+            (Terminal::NonTerminal, None)
+        );
+    }
 }
@@ -0,0 +1,223 @@
+use crate::chess::{GameState, PositionHistory};
+use shakmaty::{uci::Uci, Move, Position};
+use thiserror::Error;
+
+/// A position with its known perft results, `nodes[d - 1]` being the count at depth `d`.
+pub struct PerftPosition {
+    pub name: &'static str,
+    pub fen: &'static str,
+    pub nodes: &'static [u64],
+}
+
+/// Positions from the Chess Programming Wiki covering castling, en passant, promotions and
+/// Chess960 castling.
+pub const PERFT_SUITE: [PerftPosition; 8] = [
+    PerftPosition {
+        name: "startpos",
+        fen: "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        nodes: &[20, 400, 8_902, 197_281, 4_865_609],
+    },
+    PerftPosition {
+        name: "kiwipete",
+        fen: "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        nodes: &[48, 2_039, 97_862, 4_085_603],
+    },
+    PerftPosition {
+        name: "position 3",
+        fen: "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        nodes: &[14, 191, 2_812, 43_238, 674_624],
+    },
+    PerftPosition {
+        name: "position 4",
+        fen: "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        nodes: &[6, 264, 9_467, 422_333],
+    },
+    PerftPosition {
+        name: "position 5",
+        fen: "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        nodes: &[44, 1_486, 62_379, 2_103_487],
+    },
+    PerftPosition {
+        name: "position 6",
+        fen: "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+        nodes: &[46, 2_079, 89_890, 3_894_594],
+    },
+    PerftPosition {
+        name: "chess960 1",
+        fen: "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9",
+        nodes: &[21, 528, 12_189, 326_672],
+    },
+    PerftPosition {
+        name: "chess960 2",
+        fen: "2nnrbkr/p1qppppp/8/1ppb4/6PP/3PP3/PPP2P2/BQNNRBKR w HEhe - 1 9",
+        nodes: &[21, 807, 18_002, 667_366],
+    },
+];
+
+#[derive(Error, Debug)]
+pub enum PerftError {
+    #[error("Zobrist hash mismatch after {moves}: incremental {incremental:#018x}, recomputed {recomputed:#018x}")]
+    HashMismatch {
+        /// Moves from the perft root, in UCI notation.
+        moves: String,
+        incremental: u64,
+        recomputed: u64,
+    },
+    #[error("{name} at depth {depth}: expected {expected} nodes, counted {actual}")]
+    WrongCount {
+        name: String,
+        depth: usize,
+        expected: u64,
+        actual: u64,
+    },
+}
+
+impl GameState {
+    /// Number of leaf nodes of the legal move tree `depth` plies deep.
+    /// The position itself is the only node at depth 0.
+    ///
+    /// # Errors
+    ///
+    /// If an incrementally updated hash differs from the recomputed one.
+    pub fn perft(&self, depth: usize) -> Result<u64, PerftError> {
+        PerftWalk::new(self, depth).count(depth)
+    }
+
+    /// Perft split by the root moves. Moves are played through a `PositionHistory`, so
+    /// repetition tracking runs on every node and each incrementally updated hash is checked
+    /// against one computed from scratch. There is nothing to split at depth 0.
+    ///
+    /// # Errors
+    ///
+    /// If an incrementally updated hash differs from the recomputed one.
+    pub fn divide(&self, depth: usize) -> Result<Vec<(Move, u64)>, PerftError> {
+        if depth == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut walk = PerftWalk::new(self, depth);
+        self.position()
+            .legal_moves()
+            .into_iter()
+            .map(|m| walk.count_after(&m, depth - 1).map(|nodes| (m, nodes)))
+            .collect()
+    }
+}
+
+/// The moves from the perft root to the current node, with their history.
+struct PerftWalk {
+    history: PositionHistory,
+    line: Vec<Move>,
+}
+
+impl PerftWalk {
+    fn new(root: &GameState, depth: usize) -> Self {
+        Self {
+            history: PositionHistory::new(root.clone()),
+            line: Vec::with_capacity(depth),
+        }
+    }
+
+    /// Leaf nodes `depth` plies below the position after `m`.
+    fn count_after(&mut self, m: &Move, depth: usize) -> Result<u64, PerftError> {
+        self.history.append(m);
+        self.line.push(m.clone());
+        let nodes = self.count(depth);
+        self.line.pop();
+        self.history.pop();
+        nodes
+    }
+
+    fn count(&mut self, depth: usize) -> Result<u64, PerftError> {
+        let incremental = self.history.hash();
+        let recomputed = PositionHistory::compute_hash(self.history.last().position());
+        if incremental != recomputed {
+            let mode = self.history.starting_state().castling_mode();
+            return Err(PerftError::HashMismatch {
+                moves: self
+                    .line
+                    .iter()
+                    .map(|m| Uci::from_move(m, mode).to_string())
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                incremental,
+                recomputed,
+            });
+        }
+
+        if depth == 0 {
+            return Ok(1);
+        }
+
+        let mut nodes = 0;
+        for m in self.history.last().position().legal_moves() {
+            nodes += self.count_after(&m, depth - 1)?;
+        }
+        Ok(nodes)
+    }
+}
+
+/// Runs `PERFT_SUITE` up to the deepest known count of at most `max_nodes` nodes per position.
+/// Returns the number of positions and depths checked.
+///
+/// # Errors
+///
+/// On the first wrong node count or hash mismatch.
+// The suite is a constant, its FENs are covered by the tests.
+#[allow(clippy::missing_panics_doc)]
+pub fn verify_perft_suite(max_nodes: u64) -> Result<usize, PerftError> {
+    let mut checked = 0;
+
+    for position in &PERFT_SUITE {
+        let state = GameState::from_fen(position.fen).expect("suite FENs are valid");
+
+        for (depth, &expected) in position.nodes.iter().enumerate() {
+            if expected > max_nodes {
+                break;
+            }
+
+            let actual = state.perft(depth + 1)?;
+            if actual != expected {
+                return Err(PerftError::WrongCount {
+                    name: position.name.to_string(),
+                    depth: depth + 1,
+                    expected,
+                    actual,
+                });
+            }
+            checked += 1;
+        }
+    }
+
+    Ok(checked)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn perft_zero_counts_the_root() {
+        let state = GameState::new();
+        assert_eq!(state.perft(0).unwrap(), 1);
+        assert!(state.divide(0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn divide_adds_up_to_perft() {
+        let state = GameState::from_fen(PERFT_SUITE[1].fen).unwrap();
+        let divide = state.divide(2).unwrap();
+
+        assert_eq!(divide.len(), 48);
+        let total: u64 = divide.iter().map(|(_, nodes)| nodes).sum();
+        assert_eq!(total, state.perft(2).unwrap());
+        assert_eq!(total, PERFT_SUITE[1].nodes[1]);
+    }
+
+    #[test]
+    fn suite_matches_known_counts() {
+        // Every position has its first two depths below this budget.
+        let checked = verify_perft_suite(20_000).unwrap();
+        assert!(checked >= 2 * PERFT_SUITE.len());
+    }
+}
@@ -0,0 +1,769 @@
+use crate::{
+    chess::{FenError, GameState, PositionHistory},
+    uci::Score,
+};
+use shakmaty::{san::SanPlus, Color, Move, Outcome, Position};
+use std::{fmt, time::Duration};
+use thiserror::Error;
+
+/// Tags every PGN game has, in the order they are written.
+const SEVEN_TAG_ROSTER: [&str; 7] = ["Event", "Site", "Date", "Round", "White", "Black", "Result"];
+
+/// Lines of movetext are wrapped at this width.
+const LINE_WIDTH: usize = 80;
+
+#[derive(Error, Debug)]
+pub enum PgnError {
+    #[error("Invalid PGN: tag '{0}' is malformed")]
+    InvalidTag(String),
+    #[error("Invalid PGN: unterminated comment")]
+    UnterminatedComment,
+    #[error("Invalid PGN: unbalanced parentheses in variation")]
+    UnbalancedVariation,
+    #[error("Invalid PGN: move '{san}' at ply {ply}: {reason}")]
+    InvalidMove {
+        ply: usize,
+        san: String,
+        reason: String,
+    },
+    #[error("Invalid PGN: no game found")]
+    NoGame,
+    #[error("Invalid PGN: FEN tag: {0}")]
+    InvalidFen(#[from] FenError),
+}
+
+/// What engines attach to a move: evaluation, search depth and the clock after the move.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MoveAnnotation {
+    /// From white's perspective, as PGN viewers expect it.
+    pub eval: Option<Score>,
+    pub depth: Option<usize>,
+    pub clock: Option<Duration>,
+}
+
+impl MoveAnnotation {
+    /// Takes over what `other` sets, e.g. from a second comment after the same move. The depth
+    /// belongs to the eval it came with.
+    pub fn merge(&mut self, other: Self) {
+        if other.eval.is_some() {
+            self.eval = other.eval;
+            self.depth = other.depth;
+        }
+        self.clock = other.clock.or(self.clock);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct PgnMove {
+    pub m: Move,
+    /// Numeric annotation glyphs, `!` and `?` suffixes included as $1 to $6.
+    pub nags: Vec<u8>,
+    pub annotation: MoveAnnotation,
+    /// Comment text after the move, without the `[%...]` commands of `annotation`.
+    pub comment: Option<String>,
+    /// Alternatives to this move, each played from the position before it.
+    pub variations: Vec<Vec<Self>>,
+}
+
+impl PgnMove {
+    pub fn new(m: Move) -> Self {
+        Self {
+            m,
+            nags: Vec::new(),
+            annotation: MoveAnnotation::default(),
+            comment: None,
+            variations: Vec::new(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct PgnGame {
+    /// Tags in the order they are written.
+    pub tags: Vec<(String, String)>,
+    pub start: GameState,
+    /// Comment before the first move.
+    pub comment: Option<String>,
+    /// Mainline moves.
+    pub moves: Vec<PgnMove>,
+    /// `None` for an unfinished game, written as `*`.
+    pub outcome: Option<Outcome>,
+}
+
+impl Default for PgnGame {
+    fn default() -> Self {
+        Self::new(GameState::new())
+    }
+}
+
+impl PgnGame {
+    /// An empty game with the seven tag roster filled with placeholders. A non-standard start is
+    /// recorded with the `SetUp` and `FEN` tags.
+    pub fn new(start: GameState) -> Self {
+        let mut tags: Vec<_> = SEVEN_TAG_ROSTER
+            .iter()
+            .map(|name| {
+                let unknown = if *name == "Date" { "????.??.??" } else { "?" };
+                (name.to_string(), unknown.to_string())
+            })
+            .collect();
+        if start.to_fen() != GameState::new().to_fen() {
+            tags.push(("SetUp".to_string(), "1".to_string()));
+            tags.push(("FEN".to_string(), start.to_fen()));
+        }
+
+        let mut game = Self {
+            tags,
+            start,
+            comment: None,
+            moves: Vec::new(),
+            outcome: None,
+        };
+        game.set_tag("Result", "*");
+        game
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags
+            .iter()
+            .find(|(tag, _)| tag == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Replaces the tag's value, or adds it at the end.
+    pub fn set_tag(&mut self, name: &str, value: &str) {
+        match self.tags.iter_mut().find(|(tag, _)| tag == name) {
+            Some((_, v)) => *v = value.to_string(),
+            None => self.tags.push((name.to_string(), value.to_string())),
+        }
+    }
+
+    /// Appends a mainline move, e.g. from self-play or a match.
+    pub fn push(&mut self, m: Move, annotation: MoveAnnotation) {
+        self.moves.push(PgnMove {
+            annotation,
+            ..PgnMove::new(m)
+        });
+    }
+
+    /// Sets the outcome and the `Result` tag.
+    pub fn set_outcome(&mut self, outcome: Option<Outcome>) {
+        self.outcome = outcome;
+        self.set_tag("Result", result_str(outcome));
+    }
+
+    /// Mainline from the starting position, with repetitions tracked.
+    pub fn history(&self) -> PositionHistory {
+        let mut history = PositionHistory::new(self.start.clone());
+        for m in &self.moves {
+            history.append(&m.m);
+        }
+        history
+    }
+
+    /// States of the mainline, the starting one first.
+    pub fn states(&self) -> Vec<GameState> {
+        self.history().states().to_vec()
+    }
+
+    /// Parses the first game of `pgn`.
+    ///
+    /// # Errors
+    ///
+    /// If `pgn` has no game or the first one can't be parsed.
+    pub fn from_pgn(pgn: &str) -> Result<Self, PgnError> {
+        Self::parse_all(pgn)?
+            .into_iter()
+            .next()
+            .ok_or(PgnError::NoGame)
+    }
+
+    /// Parses every game of a PGN file.
+    ///
+    /// # Errors
+    ///
+    /// If any game is malformed or has an illegal move.
+    // Tokens are only taken after peeking at them.
+    #[allow(clippy::missing_panics_doc)]
+    pub fn parse_all(pgn: &str) -> Result<Vec<Self>, PgnError> {
+        let tokens = tokenize(pgn)?;
+        let mut games = Vec::new();
+        let mut tokens = tokens.into_iter().peekable();
+
+        while tokens.peek().is_some() {
+            let mut tags = Vec::new();
+            while let Some(Token::Tag(name, value)) = tokens.peek() {
+                tags.push((name.clone(), value.clone()));
+                tokens.next();
+            }
+
+            // Movetext runs up to the result or the next game's tags.
+            let mut movetext = Vec::new();
+            let mut outcome = None;
+            while let Some(token) = tokens.peek() {
+                match token {
+                    Token::Tag(..) => break,
+                    Token::Result(result) => {
+                        outcome = *result;
+                        tokens.next();
+                        break;
+                    }
+                    _ => movetext.push(tokens.next().expect("peeked")),
+                }
+            }
+
+            let start = match tags.iter().find(|(name, _)| name == "FEN") {
+                Some((_, fen)) => GameState::from_fen(fen)?,
+                None => GameState::new(),
+            };
+
+            let mut movetext = movetext.into_iter().peekable();
+            let mut game = Self {
+                tags,
+                start: start.clone(),
+                comment: None,
+                moves: Vec::new(),
+                outcome,
+            };
+            if let Some(Token::Comment(_)) = movetext.peek() {
+                if let Some(Token::Comment(comment)) = movetext.next() {
+                    game.comment = Some(comment);
+                }
+            }
+            game.moves = parse_line(&mut movetext, &start, 0)?;
+            if movetext.next().is_some() {
+                return Err(PgnError::UnbalancedVariation);
+            }
+
+            if game.tags.is_empty() && game.moves.is_empty() && game.comment.is_none() {
+                continue;
+            }
+            games.push(game);
+        }
+
+        Ok(games)
+    }
+}
+
+const fn result_str(outcome: Option<Outcome>) -> &'static str {
+    match outcome {
+        Some(Outcome::Decisive {
+            winner: Color::White,
+        }) => "1-0",
+        Some(Outcome::Decisive {
+            winner: Color::Black,
+        }) => "0-1",
+        Some(Outcome::Draw) => "1/2-1/2",
+        None => "*",
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    Nag(u8),
+    Open,
+    Close,
+    Result(Option<Outcome>),
+    San(String),
+}
+
+fn tokenize(pgn: &str) -> Result<Vec<Token>, PgnError> {
+    let mut tokens = Vec::new();
+    let mut chars = pgn.chars().peekable();
+    let mut line_start = true;
+
+    while let Some(c) = chars.next() {
+        match c {
+            // Escaped lines are ignored.
+            '%' if line_start => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            ';' => {
+                let comment: String = chars.by_ref().take_while(|&c| c != '\n').collect();
+                tokens.push(Token::Comment(comment.trim().to_string()));
+            }
+            '{' => {
+                let mut comment = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => comment.push(c),
+                        None => return Err(PgnError::UnterminatedComment),
+                    }
+                }
+                tokens.push(Token::Comment(comment.trim().to_string()));
+            }
+            '[' => {
+                let mut tag = String::new();
+                let mut in_string = false;
+                loop {
+                    match chars.next() {
+                        Some('\\') if in_string => tag.extend(chars.next()),
+                        Some('"') => {
+                            in_string = !in_string;
+                            tag.push('"');
+                        }
+                        Some(']') if !in_string => break,
+                        Some(c) => tag.push(c),
+                        None => return Err(PgnError::InvalidTag(tag)),
+                    }
+                }
+                tokens.push(parse_tag(&tag)?);
+            }
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '$' => {
+                let mut number = String::new();
+                while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                    number.push(digit);
+                }
+                if let Ok(nag) = number.parse() {
+                    tokens.push(Token::Nag(nag));
+                }
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut word = String::from(c);
+                while let Some(c) =
+                    chars.next_if(|c| !c.is_whitespace() && !"{}()[];$".contains(*c))
+                {
+                    word.push(c);
+                }
+                tokens.extend(word_tokens(&word));
+            }
+        }
+
+        line_start = c == '\n';
+    }
+
+    Ok(tokens)
+}
+
+fn parse_tag(tag: &str) -> Result<Token, PgnError> {
+    let invalid = || PgnError::InvalidTag(tag.to_string());
+    let (name, value) = tag
+        .trim()
+        .split_once(char::is_whitespace)
+        .ok_or_else(invalid)?;
+    let value = value
+        .trim()
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .ok_or_else(invalid)?;
+    Ok(Token::Tag(name.to_string(), value.to_string()))
+}
+
+/// Splits a word of movetext into move number, SAN and suffix annotations.
+fn word_tokens(word: &str) -> Vec<Token> {
+    match word {
+        "1-0" => {
+            return vec![Token::Result(Some(Outcome::Decisive {
+                winner: Color::White,
+            }))]
+        }
+        "0-1" => {
+            return vec![Token::Result(Some(Outcome::Decisive {
+                winner: Color::Black,
+            }))]
+        }
+        "1/2-1/2" => return vec![Token::Result(Some(Outcome::Draw))],
+        "*" => return vec![Token::Result(None)],
+        _ => {}
+    }
+
+    // Move numbers like `12.` or `12...`, possibly glued to the move. Digits followed by anything
+    // else are part of the move, as in `0-0`.
+    let after_number = word.trim_start_matches(|c: char| c.is_ascii_digit());
+    let word = if after_number.is_empty() || after_number.starts_with('.') {
+        after_number.trim_start_matches('.')
+    } else {
+        word
+    };
+    if word.is_empty() {
+        return Vec::new();
+    }
+
+    let san = word.trim_end_matches(['!', '?']);
+    let nag = match &word[san.len()..] {
+        "!" => Some(1),
+        "?" => Some(2),
+        "!!" => Some(3),
+        "??" => Some(4),
+        "!?" => Some(5),
+        "?!" => Some(6),
+        _ => None,
+    };
+
+    // Castling is sometimes written with zeros.
+    let san = if san.starts_with("0-0") {
+        san.replace('0', "O")
+    } else {
+        san.to_string()
+    };
+
+    let mut tokens = vec![Token::San(san)];
+    tokens.extend(nag.map(Token::Nag));
+    tokens
+}
+
+/// Parses moves played from `start` up to the end of the variation. `ply` is the ply of the first
+/// move, for error messages.
+fn parse_line(
+    tokens: &mut std::iter::Peekable<std::vec::IntoIter<Token>>,
+    start: &GameState,
+    ply: usize,
+) -> Result<Vec<PgnMove>, PgnError> {
+    let mut moves: Vec<PgnMove> = Vec::new();
+    // State before the last move, variations branch off from there.
+    let mut before = start.clone();
+    let mut state = start.clone();
+
+    while let Some(token) = tokens.peek() {
+        match token {
+            Token::Close => break,
+            Token::Tag(..) | Token::Result(_) => return Err(PgnError::UnbalancedVariation),
+            _ => {}
+        }
+
+        match tokens.next().expect("peeked") {
+            Token::San(san) => {
+                let m = san
+                    .parse::<SanPlus>()
+                    .map_err(|e| e.to_string())
+                    .and_then(|san| san.san.to_move(state.position()).map_err(|e| e.to_string()))
+                    .map_err(|reason| PgnError::InvalidMove {
+                        ply: ply + moves.len(),
+                        san: san.clone(),
+                        reason,
+                    })?;
+                before = state.clone();
+                state = state.make_move(&m);
+                moves.push(PgnMove::new(m));
+            }
+            Token::Nag(nag) => {
+                if let Some(last) = moves.last_mut() {
+                    last.nags.push(nag);
+                }
+            }
+            Token::Comment(comment) => {
+                if let Some(last) = moves.last_mut() {
+                    let (annotation, text) = parse_annotation(&comment);
+                    last.annotation.merge(annotation);
+                    last.comment = match (last.comment.take(), text) {
+                        (Some(old), Some(new)) => Some(format!("{old} {new}")),
+                        (old, new) => old.or(new),
+                    };
+                }
+            }
+            Token::Open => {
+                if moves.is_empty() {
+                    return Err(PgnError::UnbalancedVariation);
+                }
+                let variation = parse_line(tokens, &before, ply + moves.len() - 1)?;
+                if tokens.next() != Some(Token::Close) {
+                    return Err(PgnError::UnbalancedVariation);
+                }
+                if let Some(last) = moves.last_mut() {
+                    last.variations.push(variation);
+                }
+            }
+            Token::Close | Token::Tag(..) | Token::Result(_) => unreachable!("handled above"),
+        }
+    }
+
+    Ok(moves)
+}
+
+/// Splits the `[%eval ...]` and `[%clk ...]` commands off a comment.
+fn parse_annotation(comment: &str) -> (MoveAnnotation, Option<String>) {
+    let mut annotation = MoveAnnotation::default();
+    let mut text = String::new();
+    let mut rest = comment;
+
+    while let Some(start) = rest.find("[%") {
+        text.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find(']') else {
+            break;
+        };
+        let command = &rest[start + 2..start + end];
+        rest = &rest[start + end + 1..];
+
+        let (name, value) = command.split_once(' ').unwrap_or((command, ""));
+        match name {
+            "eval" => {
+                let value = value.trim();
+                let (score, depth) = value.split_once(',').unwrap_or((value, ""));
+                annotation.eval = parse_eval(score);
+                annotation.depth = depth.trim().parse().ok();
+            }
+            "clk" => annotation.clock = parse_clock(value.trim()),
+            _ => {
+                text.push_str("[%");
+                text.push_str(command);
+                text.push(']');
+            }
+        }
+    }
+    text.push_str(rest);
+
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (annotation, (!text.is_empty()).then_some(text))
+}
+
+// Out of range evals saturate, real ones are far within range.
+#[allow(clippy::cast_possible_truncation)]
+fn parse_eval(eval: &str) -> Option<Score> {
+    eval.strip_prefix('#').map_or_else(
+        || {
+            eval.parse::<f32>()
+                .ok()
+                .map(|pawns| Score::Centipawns((pawns * 100.0).round() as i32))
+        },
+        |mate| mate.parse().ok().map(Score::Mate),
+    )
+}
+
+/// `h:mm:ss` with optional fractions of a second.
+fn parse_clock(clock: &str) -> Option<Duration> {
+    let mut seconds = 0.0;
+    for part in clock.split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+    }
+    (seconds >= 0.0).then(|| Duration::from_secs_f64(seconds))
+}
+
+fn format_clock(clock: Duration) -> String {
+    let secs = clock.as_secs();
+    let (h, m, s) = (secs / 3600, secs / 60 % 60, secs % 60);
+    match clock.subsec_millis() / 100 {
+        0 => format!("{h}:{m:02}:{s:02}"),
+        tenths => format!("{h}:{m:02}:{s:02}.{tenths}"),
+    }
+}
+
+impl fmt::Display for MoveAnnotation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let (Some(eval), depth) = (self.eval, self.depth) {
+            let eval = match eval {
+                Score::Centipawns(cp) => format!("{:.2}", f64::from(cp) / 100.0),
+                Score::Mate(moves) => format!("#{moves}"),
+            };
+            parts.push(depth.map_or_else(
+                || format!("[%eval {eval}]"),
+                |depth| format!("[%eval {eval},{depth}]"),
+            ));
+        }
+        if let Some(clock) = self.clock {
+            parts.push(format!("[%clk {}]", format_clock(clock)));
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+/// Movetext words, wrapped into lines when written.
+struct Movetext(Vec<String>);
+
+impl Movetext {
+    fn write_line(&mut self, start: &GameState, moves: &[PgnMove]) {
+        let mut state = start.clone();
+        // Black's moves only need a number after an interruption.
+        let mut needs_number = true;
+
+        for m in moves {
+            let position = state.position();
+            let fullmoves = position.fullmoves();
+            match position.turn() {
+                Color::White => self.0.push(format!("{fullmoves}.")),
+                Color::Black if needs_number => self.0.push(format!("{fullmoves}...")),
+                Color::Black => {}
+            }
+
+            self.0
+                .push(SanPlus::from_move(position.clone(), &m.m).to_string());
+            self.0.extend(m.nags.iter().map(|nag| format!("${nag}")));
+
+            let annotation = m.annotation.to_string();
+            let comment = [annotation.as_str(), m.comment.as_deref().unwrap_or("")]
+                .into_iter()
+                .filter(|part| !part.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ");
+            needs_number = !comment.is_empty() || !m.variations.is_empty();
+            if !comment.is_empty() {
+                self.0.push(format!("{{{comment}}}"));
+            }
+
+            for variation in &m.variations {
+                self.0.push("(".to_string());
+                self.write_line(&state, variation);
+                self.0.push(")".to_string());
+            }
+
+            state = state.make_move(&m.m);
+        }
+    }
+}
+
+impl fmt::Display for PgnGame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in &self.tags {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+            writeln!(f, "[{name} \"{value}\"]")?;
+        }
+        writeln!(f)?;
+
+        let mut movetext = Movetext(Vec::new());
+        if let Some(comment) = &self.comment {
+            movetext.0.push(format!("{{{comment}}}"));
+        }
+        movetext.write_line(&self.start, &self.moves);
+        movetext.0.push(result_str(self.outcome).to_string());
+
+        // Parentheses hug the variation's first and last word.
+        let mut line = String::new();
+        let mut previous = "";
+        for word in &movetext.0 {
+            let glue = previous == "(" || word == ")";
+            if !line.is_empty() && !glue && line.len() + 1 + word.len() > LINE_WIDTH {
+                writeln!(f, "{line}")?;
+                line.clear();
+            } else if !line.is_empty() && !glue {
+                line.push(' ');
+            }
+            line.push_str(word);
+            previous = word;
+        }
+        writeln!(f, "{line}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shakmaty::uci::Uci;
+
+    fn uci(moves: &[PgnMove]) -> Vec<String> {
+        moves
+            .iter()
+            .map(|m| Uci::from_standard(&m.m).to_string())
+            .collect()
+    }
+
+    #[test]
+    fn empty_input_is_an_error() {
+        assert!(matches!(PgnGame::from_pgn(""), Err(PgnError::NoGame)));
+        assert!(matches!(
+            PgnGame::from_pgn("  \n% escaped\n"),
+            Err(PgnError::NoGame)
+        ));
+    }
+
+    #[test]
+    fn castling_with_zeros() {
+        let game = PgnGame::from_pgn(
+            "1. e4 e5 2. Nf3 Nf6 3. Bc4 Bc5 4.0-0 d6 5. d3 Bg4 6. Nc3 Qd7 7. Be3 Nc6 8. h3 0-0-0 *",
+        )
+        .unwrap();
+        assert_eq!(game.moves.len(), 16);
+        assert!(game.moves[6].m.is_castle());
+        assert!(game.moves[15].m.is_castle());
+    }
+
+    #[test]
+    fn comments_after_a_move_are_merged() {
+        let game =
+            PgnGame::from_pgn("1. e4 {[%eval 0.25,12] good} {[%clk 0:01:30] fast} e5 *").unwrap();
+        let e4 = &game.moves[0];
+        assert_eq!(e4.annotation.eval, Some(Score::Centipawns(25)));
+        assert_eq!(e4.annotation.depth, Some(12));
+        assert_eq!(e4.annotation.clock, Some(Duration::from_secs(90)));
+        assert_eq!(e4.comment.as_deref(), Some("good fast"));
+    }
+
+    #[test]
+    fn variations_nags_and_comments_round_trip() {
+        let pgn = "[Event \"Test \\\"quoted\\\"\"]\n[Site \"?\"]\n[Result \"1-0\"]\n\n\
+                   {Start} 1. e4 $1 e5 (1... c5 2. Nf3 (2. c3) 2... d6) 2. Nf3!? \
+                   {[%eval #3] [%clk 0:00:05.5] mate soon} 2... Nc6 1-0";
+        let game = PgnGame::from_pgn(pgn).unwrap();
+        assert_eq!(game.tag("Event"), Some("Test \"quoted\""));
+        assert_eq!(game.comment.as_deref(), Some("Start"));
+        assert_eq!(uci(&game.moves), ["e2e4", "e7e5", "g1f3", "b8c6"]);
+        assert_eq!(game.moves[0].nags, [1]);
+        assert_eq!(game.moves[2].nags, [5]);
+        assert_eq!(uci(&game.moves[1].variations[0]), ["c7c5", "g1f3", "d7d6"]);
+        assert_eq!(uci(&game.moves[1].variations[0][1].variations[0]), ["c2c3"]);
+
+        let written = game.to_string();
+        let reread = PgnGame::from_pgn(&written).unwrap();
+        assert_eq!(reread.to_string(), written);
+        assert_eq!(reread.tags, game.tags);
+        assert_eq!(reread.comment, game.comment);
+        assert_eq!(uci(&reread.moves), uci(&game.moves));
+        assert_eq!(
+            uci(&reread.moves[1].variations[0]),
+            uci(&game.moves[1].variations[0])
+        );
+        assert_eq!(reread.moves[2].annotation, game.moves[2].annotation);
+        assert_eq!(reread.moves[2].comment, game.moves[2].comment);
+        assert_eq!(reread.outcome, game.outcome);
+    }
+
+    #[test]
+    fn written_games_round_trip() {
+        let start = GameState::from_fen("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1").unwrap();
+        let mut game = PgnGame::new(start.clone());
+        game.set_tag("White", "fatduck");
+        let mut state = start;
+        for (m, eval, depth) in [("e2e4", 150, 10), ("e8d7", -140, 11), ("e1d2", 160, 12)] {
+            let m = Uci::from_ascii(m.as_bytes())
+                .unwrap()
+                .to_move(state.position())
+                .unwrap();
+            state = state.make_move(&m);
+            game.push(
+                m,
+                MoveAnnotation {
+                    eval: Some(Score::Centipawns(eval)),
+                    depth: Some(depth),
+                    clock: Some(Duration::from_millis(61_200)),
+                },
+            );
+        }
+        game.set_outcome(Some(Outcome::Draw));
+
+        let reread = PgnGame::parse_all(&game.to_string()).unwrap();
+        assert_eq!(reread.len(), 1);
+        let reread = &reread[0];
+        assert_eq!(reread.tag("SetUp"), Some("1"));
+        assert_eq!(reread.tag("Result"), Some("1/2-1/2"));
+        assert_eq!(reread.start.to_fen(), game.start.to_fen());
+        assert_eq!(uci(&reread.moves), uci(&game.moves));
+        for (read, written) in reread.moves.iter().zip(&game.moves) {
+            assert_eq!(read.annotation, written.annotation);
+        }
+        assert_eq!(reread.outcome, Some(Outcome::Draw));
+        assert_eq!(reread.states().last().unwrap().to_fen(), state.to_fen());
+    }
+
+    #[test]
+    fn several_games_are_read() {
+        let pgn = "[Event \"a\"]\n\n1. d4 d5 1/2-1/2\n\n[Event \"b\"]\n\n1. e4 0-1\n";
+        let games = PgnGame::parse_all(pgn).unwrap();
+        assert_eq!(games.len(), 2);
+        assert_eq!(uci(&games[1].moves), ["e2e4"]);
+        assert_eq!(
+            games[1].outcome,
+            Some(Outcome::Decisive {
+                winner: Color::Black
+            })
+        );
+    }
+}
@@ -0,0 +1,354 @@
+use crate::chess::{EndReason, GameResult, GameRules, GameState};
+use shakmaty::{Chess, Color, Move, Outcome, Position};
+use shakmaty_syzygy::{Dtz, MaybeRounded, Tablebase, Wdl};
+use std::{
+    io,
+    path::Path,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum TablebaseError {
+    #[error("No Syzygy tables found in '{0}'")]
+    NoTables(String),
+    #[error(transparent)]
+    IoError(#[from] io::Error),
+}
+
+/// Syzygy WDL and DTZ tables from local directories. Counts successful probes for `tbhits`.
+pub struct SyzygyTablebase {
+    tables: Tablebase<Chess>,
+    hits: AtomicUsize,
+}
+
+impl SyzygyTablebase {
+    /// Loads the tables from a list of directories separated like `PATH`, as in the
+    /// `SyzygyPath` UCI option.
+    ///
+    /// # Errors
+    ///
+    /// If a directory can't be read or none of them contains any tables.
+    pub fn from_paths(paths: &str) -> Result<Self, TablebaseError> {
+        let mut tables = Tablebase::new();
+        let mut added = 0;
+
+        for path in std::env::split_paths(paths) {
+            added += tables.add_directory(&path)?;
+        }
+
+        if added == 0 {
+            return Err(TablebaseError::NoTables(paths.to_string()));
+        }
+
+        Ok(Self {
+            tables,
+            hits: AtomicUsize::new(0),
+        })
+    }
+
+    /// # Errors
+    ///
+    /// If the directory can't be read or contains no tables.
+    pub fn from_directory(path: &Path) -> Result<Self, TablebaseError> {
+        Self::from_paths(&path.to_string_lossy())
+    }
+
+    /// Most pieces of any loaded table.
+    pub fn max_pieces(&self) -> usize {
+        self.tables.max_pieces()
+    }
+
+    /// Number of successful probes so far.
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn reset_hits(&self) {
+        self.hits.store(0, Ordering::Relaxed);
+    }
+
+    fn probeable(&self, position: &Chess) -> bool {
+        position.castles().is_empty() && position.board().occupied().count() <= self.max_pieces()
+    }
+
+    fn hit<T>(&self, result: Option<T>) -> Option<T> {
+        if result.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        result
+    }
+
+    /// WDL from the perspective of the side to move, assuming the last move zeroed the 50-move
+    /// counter. Only exact when it did.
+    pub fn probe_wdl(&self, state: &GameState) -> Option<Wdl> {
+        let position = state.position();
+        if !self.probeable(position) {
+            return None;
+        }
+
+        self.hit(self.tables.probe_wdl_after_zeroing(position).ok())
+    }
+
+    /// Distance to the next zeroing move, positive when the side to move wins.
+    pub fn probe_dtz(&self, state: &GameState) -> Option<MaybeRounded<Dtz>> {
+        let position = state.position();
+        if !self.probeable(position) {
+            return None;
+        }
+
+        self.hit(self.tables.probe_dtz(position).ok())
+    }
+
+    /// Proven result of a position reached by a zeroing move, scored under the rules of the game.
+    /// Cursed wins and blessed losses are draws under the 50-move rule.
+    pub fn probe_result(&self, state: &GameState) -> Option<GameResult> {
+        if state.position().halfmoves() != 0 {
+            return None;
+        }
+
+        let turn = state.position().turn();
+        let outcome = match self.probe_wdl(state)? {
+            Wdl::Win => Outcome::Decisive { winner: turn },
+            Wdl::Loss => Outcome::Decisive { winner: !turn },
+            Wdl::CursedWin | Wdl::Draw | Wdl::BlessedLoss => Outcome::Draw,
+        };
+
+        Some(GameResult {
+            outcome: state.score_outcome(outcome),
+            reason: EndReason::Tablebase,
+        })
+    }
+
+    /// The legal moves of `state` that preserve its tablebase result under the 50-move rule.
+    /// Among winning moves, ones that reach a zeroing move in time are kept. `None` if the
+    /// position can't be probed.
+    pub fn root_moves(&self, state: &GameState) -> Option<Vec<Move>> {
+        let position = state.position();
+        if !self.probeable(position) {
+            return None;
+        }
+
+        // Rank every move by the DTZ of the opponent after it, negated to our perspective.
+        let mut ranked = Vec::new();
+        for m in position.legal_moves() {
+            let mut after = position.clone();
+            after.play_unchecked(&m);
+
+            let rank = if after.is_checkmate() {
+                i32::MAX
+            } else {
+                let dtz = self.tables.probe_dtz(&after).ok()?.ignore_rounding().0;
+                Self::move_rank(-dtz, after.halfmoves())
+            };
+            ranked.push((m, rank));
+        }
+        self.hits.fetch_add(1, Ordering::Relaxed);
+
+        // In armageddon a draw is as good as a win for black and as bad as any loss for white.
+        if state.rules() == GameRules::Armageddon {
+            let draw_rank = match position.turn() {
+                Color::White => -1000,
+                Color::Black => 1,
+            };
+            for (_, rank) in ranked.iter_mut().filter(|(_, rank)| *rank == 0) {
+                *rank = draw_rank;
+            }
+        }
+
+        let best = ranked.iter().map(|(_, rank)| *rank).max()?;
+        // Every win within the 50-move rule is as good as any other, same for losses.
+        let keep = |rank: i32| match best {
+            b if b > 0 => rank > 0,
+            b if b < 0 => rank == b,
+            _ => rank == 0,
+        };
+
+        Some(
+            ranked
+                .into_iter()
+                .filter(|(_, rank)| keep(*rank))
+                .map(|(m, _)| m)
+                .collect(),
+        )
+    }
+
+    /// 1 for a win, 0 for a draw, and for losses the negated distance to the zeroing move, so
+    /// losing moves that resist longest rank highest. `dtz` is from the moving side's
+    /// perspective, `halfmoves` the counter after the move.
+    fn move_rank(dtz: i32, halfmoves: u32) -> i32 {
+        let halfmoves = i32::try_from(halfmoves).unwrap_or(i32::MAX);
+        if dtz == 0 {
+            0
+        } else if dtz > 0 {
+            // A win is only a win if it can be converted before the 50-move rule kicks in.
+            i32::from(dtz + halfmoves <= 100)
+        } else if -dtz + halfmoves > 100 {
+            // Blessed loss: saved by the 50-move rule.
+            0
+        } else {
+            -1000 - dtz
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shakmaty::uci::Uci;
+    use std::{env, fs, process};
+
+    /// Filled by `test-data/syzygy/download.sh`.
+    const TABLES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/test-data/syzygy");
+    const NEEDS_TABLES: &str = "needs the 3-piece Syzygy tables from test-data/syzygy/download.sh";
+
+    fn tablebase() -> SyzygyTablebase {
+        SyzygyTablebase::from_paths(TABLES).expect(NEEDS_TABLES)
+    }
+
+    fn state(fen: &str) -> GameState {
+        GameState::from_fen(fen).unwrap()
+    }
+
+    fn uci(moves: &[Move]) -> Vec<String> {
+        let mut moves: Vec<String> = moves
+            .iter()
+            .map(|m| Uci::from_standard(m).to_string())
+            .collect();
+        moves.sort();
+        moves
+    }
+
+    #[test]
+    fn directories_without_tables_are_rejected() {
+        let empty = env::temp_dir().join(format!("fatduck-no-tables-{}", process::id()));
+        fs::create_dir_all(&empty).unwrap();
+        let result = SyzygyTablebase::from_directory(&empty);
+        fs::remove_dir(&empty).unwrap();
+        assert!(matches!(result, Err(TablebaseError::NoTables(_))));
+    }
+
+    #[test]
+    fn move_ranks_respect_the_fifty_move_rule() {
+        assert_eq!(SyzygyTablebase::move_rank(0, 0), 0);
+        assert_eq!(SyzygyTablebase::move_rank(10, 0), 1);
+        // Cursed win.
+        assert_eq!(SyzygyTablebase::move_rank(60, 50), 0);
+        assert_eq!(SyzygyTablebase::move_rank(-10, 0), -990);
+        assert!(SyzygyTablebase::move_rank(-20, 0) > SyzygyTablebase::move_rank(-10, 0));
+        // Blessed loss.
+        assert_eq!(SyzygyTablebase::move_rank(-60, 50), 0);
+    }
+
+    #[test]
+    #[ignore = "needs the 3-piece Syzygy tables from test-data/syzygy/download.sh"]
+    fn kpk_wdl_and_dtz() {
+        let tb = tablebase();
+        assert_eq!(tb.max_pieces(), 3);
+
+        // The pawn promotes next move.
+        let promotion = state("8/4P3/8/8/8/8/k7/4K3 w - - 0 1");
+        assert_eq!(tb.probe_wdl(&promotion), Some(Wdl::Win));
+        assert_eq!(
+            tb.probe_dtz(&promotion).map(MaybeRounded::ignore_rounding),
+            Some(Dtz(1))
+        );
+        // King on the sixth in front of the pawn, whoever is to move.
+        assert_eq!(
+            tb.probe_wdl(&state("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1")),
+            Some(Wdl::Win)
+        );
+        assert_eq!(
+            tb.probe_wdl(&state("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1")),
+            Some(Wdl::Loss)
+        );
+        // Rook pawn with the defending king in the corner.
+        let corner = state("7k/8/8/8/8/8/7P/7K w - - 0 1");
+        assert_eq!(tb.probe_wdl(&corner), Some(Wdl::Draw));
+        assert_eq!(
+            tb.probe_dtz(&corner).map(MaybeRounded::ignore_rounding),
+            Some(Dtz(0))
+        );
+        assert_eq!(tb.hits(), 6);
+
+        // Castling rights and positions with more pieces than the tables aren't probed.
+        assert_eq!(
+            tb.probe_wdl(&state("4k3/8/8/8/8/8/4P3/4K2R w K - 0 1")),
+            None
+        );
+        assert_eq!(tb.probe_wdl(&GameState::new()), None);
+        assert_eq!(tb.hits(), 6);
+        tb.reset_hits();
+        assert_eq!(tb.hits(), 0);
+    }
+
+    #[test]
+    #[ignore = "needs the 3-piece Syzygy tables from test-data/syzygy/download.sh"]
+    fn krk_wdl_and_dtz() {
+        let tb = tablebase();
+
+        let mated = state("4k3/8/8/8/8/8/8/R3K3 b - - 0 1");
+        assert_eq!(tb.probe_wdl(&mated), Some(Wdl::Loss));
+        let dtz = tb.probe_dtz(&mated).unwrap().ignore_rounding();
+        assert!(dtz < Dtz(0), "{dtz:?}");
+        assert_eq!(
+            tb.probe_wdl(&state("4k3/8/8/8/8/8/8/R3K3 w - - 0 1")),
+            Some(Wdl::Win)
+        );
+
+        // The undefended rook is taken.
+        let hanging = state("8/8/8/8/8/6k1/6R1/K7 b - - 0 1");
+        assert_eq!(tb.probe_wdl(&hanging), Some(Wdl::Draw));
+    }
+
+    #[test]
+    #[ignore = "needs the 3-piece Syzygy tables from test-data/syzygy/download.sh"]
+    fn results_only_for_zeroed_positions() {
+        let tb = tablebase();
+
+        let result = tb
+            .probe_result(&state("4k3/8/8/8/8/8/8/R3K3 b - - 0 1"))
+            .unwrap();
+        assert_eq!(
+            result.outcome,
+            Outcome::Decisive {
+                winner: Color::White
+            }
+        );
+        assert_eq!(result.reason, EndReason::Tablebase);
+        assert!(tb
+            .probe_result(&state("4k3/8/8/8/8/8/8/R3K3 b - - 12 40"))
+            .is_none());
+
+        // Draws are black's in armageddon.
+        let draw = state("7k/8/8/8/8/8/7P/7K w - - 0 1").with_rules(GameRules::Armageddon);
+        assert_eq!(
+            tb.probe_result(&draw).unwrap().outcome,
+            Outcome::Decisive {
+                winner: Color::Black
+            }
+        );
+    }
+
+    #[test]
+    #[ignore = "needs the 3-piece Syzygy tables from test-data/syzygy/download.sh"]
+    fn root_moves_keep_the_win() {
+        let tb = tablebase();
+
+        // Underpromotions to a minor piece only draw.
+        let moves = tb
+            .root_moves(&state("8/4P3/8/8/8/8/k7/4K3 w - - 0 1"))
+            .unwrap();
+        let moves = uci(&moves);
+        assert!(moves.contains(&"e7e8q".to_string()));
+        assert!(moves.contains(&"e7e8r".to_string()));
+        assert!(!moves.contains(&"e7e8n".to_string()));
+        assert!(!moves.contains(&"e7e8b".to_string()));
+
+        // Taking the rook is the only move that doesn't lose.
+        let moves = tb
+            .root_moves(&state("8/8/8/8/8/6k1/6R1/K7 b - - 0 1"))
+            .unwrap();
+        assert_eq!(uci(&moves), ["g3g2"]);
+    }
+}
@@ -8,8 +8,8 @@
     non_ascii_idents,
     nonstandard_style,
     noop_method_call,
-    pointer_structural_match,
-    private_in_public,
+    private_bounds,
+    private_interfaces,
     rust_2018_idioms,
     unused_qualifications
 )]
@@ -23,11 +23,13 @@
     clippy::print_stdout
 )]
 #![allow(clippy::module_name_repetitions, clippy::must_use_candidate)]
-mod chess;
-mod neural;
-mod search;
-mod time;
-mod utils;
+pub mod chess;
+pub mod neural;
+pub mod search;
+pub mod time;
+pub mod uci;
+pub mod utils;
+#[allow(clippy::all, clippy::pedantic, clippy::nursery)]
 pub(crate) mod pblczero {
     include!(concat!(env!("OUT_DIR"), "/pblczero.rs"));
 }
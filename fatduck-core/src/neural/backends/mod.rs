@@ -1 +1,5 @@
 mod onnx;
+mod precision;
+
+pub use onnx::OnnxNetwork;
+pub use precision::{Int8Calibration, Precision, PrecisionDrift};
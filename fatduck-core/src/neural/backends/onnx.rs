@@ -1,42 +1,236 @@
 use crate::{
-    neural::network::{
-        InputStack, Network, NetworkCapabilities, NetworkComputation, NUM_INPUT_PLANES,
+    neural::{
+        backends::precision::{self, Int8Calibration, Precision},
+        network::{
+            InputStack, Network, NetworkCapabilities, NetworkComputation, NetworkError,
+            NUM_INPUT_PLANES,
+        },
+        policy::POLICY_SIZE,
     },
     pblczero::network_format::{InputFormat, MovesLeftFormat},
 };
-use ort::OrtResult;
-use std::{path::Path, sync::Arc};
+use half::{bf16, f16};
+use ndarray::{Array, CowArray, IxDyn};
+use ort::{
+    execution_providers::{CPUExecutionProviderOptions, CUDAExecutionProviderOptions},
+    tensor::{IntoTensorElementDataType, TensorDataToType, TensorElementDataType},
+    value::DynArrayRef,
+    ExecutionProvider, OrtResult, Value,
+};
+use std::{cell::RefCell, fmt::Debug, path::Path, sync::Arc};
 
 pub struct OnnxNetwork {
     capabilities: NetworkCapabilities,
-    environment: Arc<ort::Environment>,
     session: ort::Session,
+    precision: Precision,
+    outputs: OutputIndices,
+}
+
+/// Position of each head in the session's outputs.
+#[derive(Clone, Copy, Default)]
+struct OutputIndices {
+    policy: usize,
+    wdl: usize,
+    moves_left: Option<usize>,
 }
 
 impl OnnxNetwork {
-    // Create a new ONNX network from the given file
-    pub fn from_file(filepath: &Path) -> OrtResult<Self> {
+    /// Create a new ONNX network from the given file
+    ///
+    /// Models with f32, fp16 or bf16 inputs are supported. Quantized models in QDQ form (as
+    /// written by ONNX Runtime's quantization tools) keep f32 inputs and outputs and load here
+    /// as well, their scales are part of the graph.
+    ///
+    /// # Errors
+    ///
+    /// If ONNX Runtime can't be set up or can't load the model, or if the model has int8 inputs,
+    /// see `from_file_int8`.
+    pub fn from_file(filepath: &Path) -> Result<Self, NetworkError> {
+        Self::load(filepath, None)
+    }
+
+    /// Loads a model with int8 inputs and outputs. Those carry no scales, `calibration` gives
+    /// them, e.g. from `Int8Calibration::calibrate` against the FP32 model.
+    ///
+    /// # Errors
+    ///
+    /// If ONNX Runtime can't be set up or can't load the model.
+    pub fn from_file_int8(
+        filepath: &Path,
+        calibration: Int8Calibration,
+    ) -> Result<Self, NetworkError> {
+        Self::load(filepath, Some(Box::new(calibration)))
+    }
+
+    fn load(
+        filepath: &Path,
+        calibration: Option<Box<Int8Calibration>>,
+    ) -> Result<Self, NetworkError> {
         let environment = Arc::new(
             ort::Environment::builder()
                 .with_name("FatDuck")
-                .with_execution_providers([ort::ExecutionProvider::cuda()])
+                // CPU is the fallback if CUDA isn't available.
+                .with_execution_providers([
+                    ExecutionProvider::CUDA(CUDAExecutionProviderOptions::default()),
+                    ExecutionProvider::CPU(CPUExecutionProviderOptions::default()),
+                ])
                 .build()?,
         );
 
+        // The session keeps the environment alive.
         let session = ort::SessionBuilder::new(&environment)?
             .with_optimization_level(ort::GraphOptimizationLevel::Level3)?
             .with_model_from_file(filepath)?;
 
+        // Half precision and quantized models are run natively, inputs and outputs are converted.
+        let precision = match session.inputs.first().map(|input| input.input_type) {
+            Some(TensorElementDataType::Float16) => Precision::Fp16,
+            Some(TensorElementDataType::Bfloat16) => Precision::Bf16,
+            Some(TensorElementDataType::Int8) => {
+                Precision::Int8(calibration.ok_or(NetworkError::MissingInt8Calibration)?)
+            }
+            _ => Precision::Fp32,
+        };
+
+        let output_index = |name: &str| {
+            session
+                .outputs
+                .iter()
+                .position(|output| output.name.contains(name))
+        };
+        let outputs = OutputIndices {
+            policy: output_index("policy").unwrap_or(0),
+            wdl: output_index("wdl").unwrap_or(1),
+            moves_left: output_index("mlh"),
+        };
+
         // TODO: Extract capabilities from ONNX file
         let input_format = InputFormat::InputClassical112Plane;
-        let mlh_format = MovesLeftFormat::MovesLeftV1;
+        let mlh_format = if outputs.moves_left.is_some() {
+            MovesLeftFormat::MovesLeftV1
+        } else {
+            MovesLeftFormat::MovesLeftNone
+        };
 
         Ok(Self {
             capabilities: NetworkCapabilities::new(input_format, mlh_format),
-            environment,
             session,
+            precision,
+            outputs,
         })
     }
+
+    pub const fn precision(&self) -> &Precision {
+        &self.precision
+    }
+
+    fn run(&self, inputs: &[InputStack<NUM_INPUT_PLANES>]) -> OrtResult<OnnxOutputs> {
+        let outputs = match InputTensor::new(inputs, &self.precision) {
+            InputTensor::Fp32(planes) => self.run_planes(planes)?,
+            InputTensor::Fp16(planes) => self.run_planes(planes)?,
+            InputTensor::Bf16(planes) => self.run_planes(planes)?,
+            InputTensor::Int8(planes) => self.run_planes(planes)?,
+        };
+
+        let read = |idx: usize, int8_scale: fn(&Int8Calibration) -> f32| {
+            let scale = match &self.precision {
+                Precision::Int8(calibration) => int8_scale(calibration),
+                _ => 1.0,
+            };
+            OrtResult::Ok(self.read_output(&outputs[idx], idx)?.into_f32(scale))
+        };
+
+        Ok(OnnxOutputs {
+            policy: read(self.outputs.policy, |c| c.policy_scale)?,
+            wdl: read(self.outputs.wdl, |c| c.value_scale)?,
+            moves_left: self
+                .outputs
+                .moves_left
+                .map(|idx| read(idx, |c| c.moves_left_scale))
+                .transpose()?,
+        })
+    }
+
+    fn run_planes<T>(&self, planes: Array<T, IxDyn>) -> OrtResult<Vec<Value<'static>>>
+    where
+        T: IntoTensorElementDataType + Debug + Clone,
+        for<'a> DynArrayRef<'a>: From<CowArray<'a, T, IxDyn>>,
+    {
+        // The input tensor borrows the array, so it has to live until the run is done.
+        let planes = CowArray::from(planes);
+        let input = Value::from_array(self.session.allocator(), &planes)?;
+        self.session.run(vec![input])
+    }
+
+    fn read_output(&self, value: &Value<'_>, idx: usize) -> OrtResult<OutputValues> {
+        fn extract<T: TensorDataToType>(value: &Value<'_>) -> OrtResult<Vec<T>> {
+            Ok(value.try_extract::<T>()?.view().iter().cloned().collect())
+        }
+
+        Ok(match self.session.outputs[idx].output_type {
+            TensorElementDataType::Float16 => OutputValues::Fp16(extract(value)?),
+            TensorElementDataType::Bfloat16 => OutputValues::Bf16(extract(value)?),
+            TensorElementDataType::Int8 => OutputValues::Int8(extract(value)?),
+            _ => OutputValues::Fp32(extract(value)?),
+        })
+    }
+}
+
+/// Input planes as a batch x planes x 8 x 8 tensor in the element type of the model.
+enum InputTensor {
+    Fp32(Array<f32, IxDyn>),
+    Fp16(Array<f16, IxDyn>),
+    Bf16(Array<bf16, IxDyn>),
+    Int8(Array<i8, IxDyn>),
+}
+
+impl InputTensor {
+    fn new(inputs: &[InputStack<NUM_INPUT_PLANES>], precision: &Precision) -> Self {
+        let mut planes = Array::<f32, _>::zeros((inputs.len(), NUM_INPUT_PLANES, 8, 8));
+        for (stack, mut tensor) in inputs.iter().zip(planes.outer_iter_mut()) {
+            for (plane, mut tensor) in stack.planes().iter().zip(tensor.outer_iter_mut()) {
+                plane.expand_into(tensor.as_slice_mut().expect("tensor is contiguous"));
+            }
+        }
+        let planes = planes.into_dyn();
+
+        match precision {
+            Precision::Fp32 => Self::Fp32(planes),
+            Precision::Fp16 => Self::Fp16(planes.mapv(f16::from_f32)),
+            Precision::Bf16 => Self::Bf16(planes.mapv(bf16::from_f32)),
+            // Each plane has its own scale.
+            Precision::Int8(calibration) => {
+                let mut quantized = planes.mapv(|_| 0_i8);
+                for ((idx, value), out) in planes.indexed_iter().zip(quantized.iter_mut()) {
+                    *out = Int8Calibration::quantize(*value, calibration.input_scales[idx[1]]);
+                }
+                Self::Int8(quantized)
+            }
+        }
+    }
+}
+
+/// Values of one output in the element type of the model.
+enum OutputValues {
+    Fp32(Vec<f32>),
+    Fp16(Vec<f16>),
+    Bf16(Vec<bf16>),
+    Int8(Vec<i8>),
+}
+
+impl OutputValues {
+    /// `int8_scale` is the calibrated scale of the head, it only applies to int8 values.
+    fn into_f32(self, int8_scale: f32) -> Vec<f32> {
+        match self {
+            Self::Fp32(values) => values,
+            Self::Fp16(values) => precision::from_f16(&values),
+            Self::Bf16(values) => precision::from_bf16(&values),
+            Self::Int8(values) => values
+                .into_iter()
+                .map(|v| Int8Calibration::dequantize(v, int8_scale))
+                .collect(),
+        }
+    }
 }
 
 impl Network for OnnxNetwork {
@@ -44,41 +238,149 @@ impl Network for OnnxNetwork {
         &self.capabilities
     }
 
-    fn new_computation(&self) -> Box<dyn NetworkComputation> {
-        unimplemented!();
+    fn new_computation(&self) -> Box<dyn NetworkComputation + '_> {
+        Box::new(OnnxComputation {
+            network: self,
+            inputs: Vec::new(),
+            outputs: RefCell::new(None),
+        })
     }
 }
 
+/// Raw outputs of a batch, converted to `f32`.
+struct OnnxOutputs {
+    policy: Vec<f32>,
+    wdl: Vec<f32>,
+    moves_left: Option<Vec<f32>>,
+}
+
 pub struct OnnxComputation<'a> {
     network: &'a OnnxNetwork,
+    inputs: Vec<InputStack<NUM_INPUT_PLANES>>,
+    outputs: RefCell<Option<OnnxOutputs>>,
+}
+
+impl OnnxComputation<'_> {
+    fn output<T>(&self, read: impl FnOnce(&OnnxOutputs) -> T) -> T {
+        read(
+            self.outputs
+                .borrow()
+                .as_ref()
+                .expect("compute_blocking must be called before reading outputs"),
+        )
+    }
 }
 
 impl NetworkComputation for OnnxComputation<'_> {
     fn add_input(&mut self, planes: InputStack<NUM_INPUT_PLANES>) {
-        todo!()
+        self.inputs.push(planes);
     }
 
-    fn compute_blocking(&self) {
-        todo!()
+    fn compute_blocking(&self) -> Result<(), NetworkError> {
+        let outputs = self.network.run(&self.inputs)?;
+        *self.outputs.borrow_mut() = Some(outputs);
+        Ok(())
     }
 
     fn batch_size(&self) -> usize {
-        todo!()
+        self.inputs.len()
     }
 
     fn q_val(&self, sample: usize) -> f32 {
-        todo!()
+        self.output(|o| o.wdl[sample * 3] - o.wdl[sample * 3 + 2])
     }
 
     fn d_val(&self, sample: usize) -> f32 {
-        todo!()
+        self.output(|o| o.wdl[sample * 3 + 1])
     }
 
     fn p_val(&self, sample: usize, move_id: usize) -> f32 {
-        todo!()
+        self.output(|o| o.policy[sample * POLICY_SIZE + move_id])
     }
 
     fn m_val(&self, sample: usize) -> f32 {
-        todo!()
+        self.output(|o| o.moves_left.as_ref().map_or(0.0, |m| m[sample]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RULE50_PLANE: usize = 109;
+
+    /// A white pawn on a1 and a rule-50 count of 37, as classical inputs encode them.
+    fn sample() -> InputStack<NUM_INPUT_PLANES> {
+        let mut stack = InputStack::new();
+        stack.planes_mut()[0].fill(1.0);
+        *stack.planes_mut()[0].mask_mut() = 1;
+        stack.planes_mut()[RULE50_PLANE].fill(37.0);
+        stack
+    }
+
+    fn calibration(rule50_max: f32) -> Int8Calibration {
+        let mut input_scales = [1.0 / 127.0; NUM_INPUT_PLANES];
+        input_scales[RULE50_PLANE] = rule50_max / 127.0;
+        Int8Calibration {
+            input_scales,
+            policy_scale: 0.5,
+            value_scale: 1.0 / 127.0,
+            moves_left_scale: 1.0,
+        }
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)] // Small integers are exact in every float format.
+    fn half_precision_inputs_are_exact() {
+        let InputTensor::Fp16(planes) = InputTensor::new(&[sample(), sample()], &Precision::Fp16)
+        else {
+            panic!("expected fp16 inputs");
+        };
+        assert_eq!(planes.shape(), &[2, NUM_INPUT_PLANES, 8, 8]);
+        assert_eq!(planes[[1, 0, 0, 0]].to_f32(), 1.0);
+        assert_eq!(planes[[1, 0, 0, 1]].to_f32(), 0.0);
+        assert_eq!(planes[[1, RULE50_PLANE, 7, 7]].to_f32(), 37.0);
+
+        let InputTensor::Bf16(planes) = InputTensor::new(&[sample()], &Precision::Bf16) else {
+            panic!("expected bf16 inputs");
+        };
+        assert_eq!(planes[[0, 0, 0, 0]].to_f32(), 1.0);
+        assert_eq!(planes[[0, RULE50_PLANE, 3, 4]].to_f32(), 37.0);
+    }
+
+    #[test]
+    fn int8_inputs_use_the_plane_scales() {
+        let calibration = calibration(100.0);
+        let precision = Precision::Int8(Box::new(calibration));
+        let InputTensor::Int8(planes) = InputTensor::new(&[sample()], &precision) else {
+            panic!("expected int8 inputs");
+        };
+        assert_eq!(planes[[0, 0, 0, 0]], 127);
+        assert_eq!(planes[[0, 0, 0, 1]], 0);
+
+        let rule50 = planes[[0, RULE50_PLANE, 0, 0]];
+        let scale = calibration.input_scales[RULE50_PLANE];
+        assert!((Int8Calibration::dequantize(rule50, scale) - 37.0).abs() <= scale / 2.0);
+
+        // The piece plane scale would clamp the rule-50 count.
+        assert_eq!(Int8Calibration::quantize(37.0, 1.0 / 127.0), 127);
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)] // Every value here is exact in f32.
+    fn outputs_are_converted_to_f32() {
+        let fp16 = [-1.5, 0.0, 37.0].map(f16::from_f32).to_vec();
+        assert_eq!(OutputValues::Fp16(fp16).into_f32(0.5), [-1.5, 0.0, 37.0]);
+
+        let bf16 = [-1.5, 0.25].map(bf16::from_f32).to_vec();
+        assert_eq!(OutputValues::Bf16(bf16).into_f32(0.5), [-1.5, 0.25]);
+
+        // Only int8 values are scaled.
+        assert_eq!(OutputValues::Fp32(vec![3.0]).into_f32(0.5), [3.0]);
+        let policy_scale = calibration(100.0).policy_scale;
+        assert_eq!(
+            OutputValues::Int8(vec![-127, 0, 64]).into_f32(policy_scale),
+            [-63.5, 0.0, 32.0]
+        );
     }
 }
@@ -0,0 +1,305 @@
+use crate::neural::{
+    network::{InputStack, Network, NetworkError, NUM_INPUT_PLANES},
+    policy::POLICY_SIZE,
+};
+use half::{bf16, f16};
+
+/// Element type a backend feeds to and reads from the model. Everything outside of the backend
+/// works in `f32`, conversions happen when building the input tensor and reading the outputs.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Precision {
+    Fp32,
+    Fp16,
+    Bf16,
+    /// Boxed, the per-plane scales are large.
+    Int8(Box<Int8Calibration>),
+}
+
+impl Precision {
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Fp32 => "fp32",
+            Self::Fp16 => "fp16",
+            Self::Bf16 => "bf16",
+            Self::Int8(_) => "int8",
+        }
+    }
+}
+
+pub fn from_f16(values: &[f16]) -> Vec<f32> {
+    values.iter().map(|v| v.to_f32()).collect()
+}
+
+pub fn from_bf16(values: &[bf16]) -> Vec<f32> {
+    values.iter().map(|v| v.to_f32()).collect()
+}
+
+/// Symmetric linear quantization scales, value = scale * int8. Input planes get one scale each
+/// since e.g. the rule-50 plane has a much larger range than the piece planes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Int8Calibration {
+    pub input_scales: [f32; NUM_INPUT_PLANES],
+    pub policy_scale: f32,
+    pub value_scale: f32,
+    pub moves_left_scale: f32,
+}
+
+impl Int8Calibration {
+    /// Picks the scales from the ranges seen when running `samples` through the FP32 `reference`
+    /// network.
+    ///
+    /// # Errors
+    ///
+    /// If the reference network fails.
+    pub fn calibrate(
+        reference: &dyn Network,
+        samples: &[InputStack<NUM_INPUT_PLANES>],
+    ) -> Result<Self, NetworkError> {
+        let scale = |max_abs: f32| max_abs.max(f32::EPSILON) / 127.0;
+
+        let mut input_max = [0.0_f32; NUM_INPUT_PLANES];
+        for sample in samples {
+            for (max, plane) in input_max.iter_mut().zip(sample.planes()) {
+                if plane.mask() != 0 {
+                    *max = max.max(plane.value().abs());
+                }
+            }
+        }
+
+        let mut computation = reference.new_computation();
+        for sample in samples {
+            computation.add_input(sample.clone());
+        }
+        computation.compute_blocking()?;
+
+        let (mut policy_max, mut value_max, mut moves_left_max) = (0.0_f32, 0.0_f32, 0.0_f32);
+        for i in 0..computation.batch_size() {
+            for move_id in 0..POLICY_SIZE {
+                policy_max = policy_max.max(computation.p_val(i, move_id).abs());
+            }
+            value_max = value_max
+                .max(computation.q_val(i).abs())
+                .max(computation.d_val(i).abs());
+            moves_left_max = moves_left_max.max(computation.m_val(i).abs());
+        }
+
+        Ok(Self {
+            input_scales: input_max.map(scale),
+            policy_scale: scale(policy_max),
+            value_scale: scale(value_max),
+            moves_left_scale: scale(moves_left_max),
+        })
+    }
+
+    // Rounded and clamped to the i8 range first.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn quantize(value: f32, scale: f32) -> i8 {
+        (value / scale).round().clamp(-127.0, 127.0) as i8
+    }
+
+    pub fn dequantize(value: i8, scale: f32) -> f32 {
+        f32::from(value) * scale
+    }
+}
+
+/// Largest differences between a reduced precision network and its FP32 reference.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PrecisionDrift {
+    pub max_q_error: f32,
+    pub max_d_error: f32,
+    pub max_m_error: f32,
+    pub max_policy_error: f32,
+    pub mean_q_error: f32,
+}
+
+impl PrecisionDrift {
+    /// Runs `samples` through both networks and compares their outputs.
+    ///
+    /// # Errors
+    ///
+    /// If either network fails.
+    pub fn measure(
+        reference: &dyn Network,
+        candidate: &dyn Network,
+        samples: &[InputStack<NUM_INPUT_PLANES>],
+    ) -> Result<Self, NetworkError> {
+        let mut expected = reference.new_computation();
+        let mut actual = candidate.new_computation();
+        for sample in samples {
+            expected.add_input(sample.clone());
+            actual.add_input(sample.clone());
+        }
+        expected.compute_blocking()?;
+        actual.compute_blocking()?;
+
+        let mut drift = Self::default();
+        let mut q_error_sum = 0.0;
+
+        for i in 0..expected.batch_size() {
+            let q_error = (expected.q_val(i) - actual.q_val(i)).abs();
+            q_error_sum += q_error;
+            drift.max_q_error = drift.max_q_error.max(q_error);
+            drift.max_d_error = drift
+                .max_d_error
+                .max((expected.d_val(i) - actual.d_val(i)).abs());
+            drift.max_m_error = drift
+                .max_m_error
+                .max((expected.m_val(i) - actual.m_val(i)).abs());
+
+            for move_id in 0..POLICY_SIZE {
+                drift.max_policy_error = drift
+                    .max_policy_error
+                    .max((expected.p_val(i, move_id) - actual.p_val(i, move_id)).abs());
+            }
+        }
+
+        // Batch sizes are far below 2^24, exact as f32.
+        #[allow(clippy::cast_precision_loss)]
+        let batch_size = expected.batch_size().max(1) as f32;
+        drift.mean_q_error = q_error_sum / batch_size;
+        Ok(drift)
+    }
+
+    /// Whether all errors are within `tolerance`.
+    pub fn within(&self, tolerance: f32) -> bool {
+        self.max_q_error <= tolerance
+            && self.max_d_error <= tolerance
+            && self.max_policy_error <= tolerance
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        neural::{
+            benchmark_positions,
+            network::{NetworkCapabilities, NetworkComputation},
+        },
+        pblczero::network_format::{InputFormat, MovesLeftFormat},
+    };
+
+    /// Network whose outputs are a smooth function of the input planes, optionally rounded to a
+    /// lower precision the way a half precision model's outputs would be.
+    struct SyntheticNetwork {
+        capabilities: NetworkCapabilities,
+        round: fn(f32) -> f32,
+    }
+
+    impl SyntheticNetwork {
+        fn new(round: fn(f32) -> f32) -> Self {
+            Self {
+                capabilities: NetworkCapabilities::new(
+                    InputFormat::InputClassical112Plane,
+                    MovesLeftFormat::MovesLeftV1,
+                ),
+                round,
+            }
+        }
+    }
+
+    impl Network for SyntheticNetwork {
+        fn capabilities(&self) -> &NetworkCapabilities {
+            &self.capabilities
+        }
+
+        fn new_computation(&self) -> Box<dyn NetworkComputation + '_> {
+            Box::new(SyntheticComputation {
+                round: self.round,
+                features: Vec::new(),
+            })
+        }
+    }
+
+    struct SyntheticComputation {
+        round: fn(f32) -> f32,
+        features: Vec<f32>,
+    }
+
+    // Square counts, plane and move indices are small.
+    #[allow(clippy::cast_precision_loss)]
+    impl NetworkComputation for SyntheticComputation {
+        fn add_input(&mut self, planes: InputStack<NUM_INPUT_PLANES>) {
+            let feature = planes
+                .planes()
+                .iter()
+                .enumerate()
+                .map(|(idx, plane)| {
+                    plane.value() * plane.mask().count_ones() as f32 * (idx % 7) as f32
+                })
+                .sum::<f32>();
+            self.features.push(feature / 100.0);
+        }
+
+        fn compute_blocking(&self) -> Result<(), NetworkError> {
+            Ok(())
+        }
+
+        fn batch_size(&self) -> usize {
+            self.features.len()
+        }
+
+        fn q_val(&self, sample: usize) -> f32 {
+            (self.round)(self.features[sample].sin() * 0.9)
+        }
+
+        fn d_val(&self, sample: usize) -> f32 {
+            (self.round)(self.features[sample].cos().abs() * 0.5)
+        }
+
+        fn p_val(&self, sample: usize, move_id: usize) -> f32 {
+            (self.round)((move_id as f32).mul_add(0.37, self.features[sample]).sin() * 4.0)
+        }
+
+        fn m_val(&self, sample: usize) -> f32 {
+            (self.round)(self.features[sample].mul_add(3.0, 30.0))
+        }
+    }
+
+    fn samples(network: &dyn Network) -> Vec<InputStack<NUM_INPUT_PLANES>> {
+        benchmark_positions(network, 16)
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)] // Identical networks give exactly zero error.
+    fn fp32_reference_has_no_drift() {
+        let reference = SyntheticNetwork::new(|v| v);
+        let drift = PrecisionDrift::measure(&reference, &reference, &samples(&reference)).unwrap();
+
+        assert!(drift.within(0.0));
+        assert_eq!(drift.max_m_error, 0.0);
+        assert_eq!(drift.mean_q_error, 0.0);
+    }
+
+    #[test]
+    fn half_precision_drift_from_fp32() {
+        let reference = SyntheticNetwork::new(|v| v);
+        let fp16 = SyntheticNetwork::new(|v| f16::from_f32(v).to_f32());
+        let bf16 = SyntheticNetwork::new(|v| bf16::from_f32(v).to_f32());
+        let samples = samples(&reference);
+
+        let fp16_drift = PrecisionDrift::measure(&reference, &fp16, &samples).unwrap();
+        assert!(fp16_drift.max_policy_error > 0.0);
+        assert!(fp16_drift.within(5e-3), "{fp16_drift:?}");
+
+        // bf16 keeps fewer mantissa bits than fp16.
+        let bf16_drift = PrecisionDrift::measure(&reference, &bf16, &samples).unwrap();
+        assert!(bf16_drift.max_policy_error > fp16_drift.max_policy_error);
+        assert!(bf16_drift.within(3e-2), "{bf16_drift:?}");
+    }
+
+    #[test]
+    fn int8_calibration_covers_reference_outputs() {
+        let reference = SyntheticNetwork::new(|v| v);
+        let calibration = Int8Calibration::calibrate(&reference, &samples(&reference)).unwrap();
+
+        assert!(calibration.policy_scale.mul_add(127.0, -4.0).abs() < 0.1);
+        assert!(calibration.moves_left_scale * 127.0 >= 30.0);
+
+        let value = 0.6;
+        let quantized = Int8Calibration::quantize(value, calibration.value_scale);
+        let restored = Int8Calibration::dequantize(quantized, calibration.value_scale);
+        assert!((restored - value).abs() <= calibration.value_scale / 2.0);
+        assert_eq!(Int8Calibration::quantize(1e6, calibration.value_scale), 127);
+    }
+}
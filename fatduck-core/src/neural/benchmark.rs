@@ -0,0 +1,373 @@
+use crate::{
+    chess::GameState,
+    neural::{
+        encoder::FillEmptyHistory,
+        network::{InputStack, Network, NetworkError, MOVE_HISTORY, NUM_INPUT_PLANES},
+    },
+};
+use shakmaty::Position;
+use std::{
+    fmt, fs, io,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Debug)]
+pub struct BenchmarkConfig {
+    pub batch_sizes: Vec<usize>,
+    /// Timed runs per batch size
+    pub runs: usize,
+    /// Untimed runs per batch size, e.g. to let the backend allocate its buffers.
+    pub warmup_runs: usize,
+    /// A batch size is good enough once it reaches this fraction of the best throughput. Smaller
+    /// batches are preferred because they keep the search more selective.
+    pub throughput_tolerance: f64,
+}
+
+impl Default for BenchmarkConfig {
+    fn default() -> Self {
+        Self {
+            batch_sizes: vec![1, 2, 4, 8, 16, 32, 64, 128, 256],
+            runs: 50,
+            warmup_runs: 5,
+            throughput_tolerance: 0.95,
+        }
+    }
+}
+
+/// Results of a single batch size.
+#[derive(Clone, Copy, Debug)]
+pub struct BatchResult {
+    pub batch_size: usize,
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub positions_per_second: f64,
+}
+
+impl BatchResult {
+    /// `latencies` holds at least one run.
+    fn new(batch_size: usize, mut latencies: Vec<Duration>) -> Self {
+        latencies.sort_unstable();
+
+        let total: Duration = latencies.iter().sum();
+        // Nearest rank, rounded down.
+        let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+
+        // Position counts are far below 2^52, exact as f64.
+        #[allow(clippy::cast_precision_loss)]
+        let positions = (batch_size * latencies.len()) as f64;
+
+        Self {
+            batch_size,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            positions_per_second: positions / total.as_secs_f64().max(f64::EPSILON),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct BenchmarkReport {
+    pub results: Vec<BatchResult>,
+    throughput_tolerance: f64,
+}
+
+impl BenchmarkReport {
+    /// Smallest batch size within the throughput tolerance of the fastest one.
+    pub fn recommended_batch_size(&self) -> Option<usize> {
+        let best = self
+            .results
+            .iter()
+            .map(|r| r.positions_per_second)
+            .fold(0.0, f64::max);
+
+        self.results
+            .iter()
+            .filter(|r| r.positions_per_second >= best * self.throughput_tolerance)
+            .map(|r| r.batch_size)
+            .min()
+    }
+
+    /// Stores the recommended batch size so it can be reused on the same machine, see
+    /// `load_batch_size`.
+    ///
+    /// # Errors
+    ///
+    /// If the report has no results or the file can't be written.
+    pub fn store_recommendation(&self, path: &Path) -> io::Result<()> {
+        let batch_size = self.recommended_batch_size().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidData, "benchmark has no results")
+        })?;
+        fs::write(path, format!("{batch_size}\n"))
+    }
+}
+
+impl fmt::Display for BenchmarkReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{:>6} {:>10} {:>10} {:>10} {:>12}",
+            "batch", "p50 ms", "p90 ms", "p99 ms", "pos/s"
+        )?;
+
+        for r in &self.results {
+            writeln!(
+                f,
+                "{:>6} {:>10.3} {:>10.3} {:>10.3} {:>12.0}",
+                r.batch_size,
+                r.p50.as_secs_f64() * 1000.0,
+                r.p90.as_secs_f64() * 1000.0,
+                r.p99.as_secs_f64() * 1000.0,
+                r.positions_per_second
+            )?;
+        }
+
+        if let Some(batch_size) = self.recommended_batch_size() {
+            write!(f, "Recommended minibatch size: {batch_size}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads a batch size stored by `BenchmarkReport::store_recommendation`.
+///
+/// # Errors
+///
+/// If the file can't be read or doesn't hold a batch size.
+pub fn load_batch_size(path: &Path) -> io::Result<usize> {
+    fs::read_to_string(path)?
+        .trim()
+        .parse()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Runs `positions` through `network` at every configured batch size. Batches are filled by
+/// cycling through `positions`.
+///
+/// # Errors
+///
+/// If `positions` is empty or the network fails.
+pub fn benchmark_network(
+    network: &dyn Network,
+    positions: &[InputStack<NUM_INPUT_PLANES>],
+    config: &BenchmarkConfig,
+) -> Result<BenchmarkReport, NetworkError> {
+    if positions.is_empty() {
+        return Err(NetworkError::NoBenchmarkPositions);
+    }
+
+    let run_batch = |batch_size: usize| -> Result<Duration, NetworkError> {
+        let mut computation = network.new_computation();
+        for input in positions.iter().cycle().take(batch_size) {
+            computation.add_input(input.clone());
+        }
+
+        let start = Instant::now();
+        computation.compute_blocking()?;
+        Ok(start.elapsed())
+    };
+
+    let results = config
+        .batch_sizes
+        .iter()
+        .map(|&batch_size| {
+            for _ in 0..config.warmup_runs {
+                run_batch(batch_size)?;
+            }
+
+            let latencies = (0..config.runs.max(1))
+                .map(|_| run_batch(batch_size))
+                .collect::<Result<Vec<Duration>, _>>()?;
+
+            Ok(BatchResult::new(batch_size, latencies))
+        })
+        .collect::<Result<_, NetworkError>>()?;
+
+    Ok(BenchmarkReport {
+        results,
+        throughput_tolerance: config.throughput_tolerance,
+    })
+}
+
+/// Encodes `count` positions of a deterministic game from the start position, for when no
+/// positions of interest are at hand.
+pub fn benchmark_positions(
+    network: &dyn Network,
+    count: usize,
+) -> Vec<InputStack<NUM_INPUT_PLANES>> {
+    let mut history = vec![GameState::new()];
+    let mut positions = Vec::with_capacity(count);
+
+    for ply in 0..count {
+        positions.push(InputStack::<NUM_INPUT_PLANES>::encode_position_for_nn(
+            network.capabilities().input_format(),
+            &history,
+            MOVE_HISTORY,
+            FillEmptyHistory::FenOnly,
+            None,
+        ));
+
+        let state = &history[ply];
+        let moves = state.position().legal_moves();
+        let next = if moves.is_empty() || state.compute_game_result().is_some() {
+            GameState::new()
+        } else {
+            state.make_move(&moves[(ply * 7 + 3) % moves.len()])
+        };
+        history.push(next);
+    }
+
+    positions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        neural::network::{NetworkCapabilities, NetworkComputation},
+        pblczero::network_format::{InputFormat, MovesLeftFormat},
+    };
+    use std::{env, process};
+
+    struct NullNetwork(NetworkCapabilities);
+
+    impl NullNetwork {
+        fn new() -> Self {
+            Self(NetworkCapabilities::new(
+                InputFormat::InputClassical112Plane,
+                MovesLeftFormat::MovesLeftNone,
+            ))
+        }
+    }
+
+    impl Network for NullNetwork {
+        fn capabilities(&self) -> &NetworkCapabilities {
+            &self.0
+        }
+
+        fn new_computation(&self) -> Box<dyn NetworkComputation + '_> {
+            Box::new(NullComputation(0))
+        }
+    }
+
+    struct NullComputation(usize);
+
+    impl NetworkComputation for NullComputation {
+        fn add_input(&mut self, _planes: InputStack<NUM_INPUT_PLANES>) {
+            self.0 += 1;
+        }
+
+        fn compute_blocking(&self) -> Result<(), NetworkError> {
+            Ok(())
+        }
+
+        fn batch_size(&self) -> usize {
+            self.0
+        }
+
+        fn q_val(&self, _sample: usize) -> f32 {
+            0.0
+        }
+
+        fn d_val(&self, _sample: usize) -> f32 {
+            0.0
+        }
+
+        fn p_val(&self, _sample: usize, _move_id: usize) -> f32 {
+            0.0
+        }
+
+        fn m_val(&self, _sample: usize) -> f32 {
+            0.0
+        }
+    }
+
+    fn report(throughputs: &[(usize, f64)], throughput_tolerance: f64) -> BenchmarkReport {
+        BenchmarkReport {
+            results: throughputs
+                .iter()
+                .map(|&(batch_size, positions_per_second)| BatchResult {
+                    positions_per_second,
+                    ..BatchResult::new(batch_size, vec![Duration::from_millis(1)])
+                })
+                .collect(),
+            throughput_tolerance,
+        }
+    }
+
+    #[test]
+    fn smallest_batch_within_tolerance_is_recommended() {
+        let throughputs = [
+            (1, 100.0),
+            (8, 900.0),
+            (16, 960.0),
+            (32, 1000.0),
+            (64, 990.0),
+        ];
+        assert_eq!(
+            report(&throughputs, 0.95).recommended_batch_size(),
+            Some(16)
+        );
+        assert_eq!(report(&throughputs, 0.9).recommended_batch_size(), Some(8));
+        assert_eq!(report(&throughputs, 1.0).recommended_batch_size(), Some(32));
+        assert_eq!(report(&[], 0.95).recommended_batch_size(), None);
+    }
+
+    #[test]
+    fn percentiles_use_the_sorted_latencies() {
+        let latencies = (1..=100).rev().map(Duration::from_millis).collect();
+        let result = BatchResult::new(4, latencies);
+        assert_eq!(result.p50, Duration::from_millis(50));
+        assert_eq!(result.p90, Duration::from_millis(90));
+        assert_eq!(result.p99, Duration::from_millis(99));
+        // 400 positions in 5.05 seconds.
+        assert!((result.positions_per_second - 400.0 / 5.05).abs() < 1e-9);
+
+        let single = BatchResult::new(1, vec![Duration::from_millis(7)]);
+        assert_eq!(single.p50, Duration::from_millis(7));
+        assert_eq!(single.p99, Duration::from_millis(7));
+    }
+
+    #[test]
+    fn recommendation_round_trips_through_a_file() {
+        let path = env::temp_dir().join(format!("fatduck-batch-size-{}", process::id()));
+        report(&[(4, 500.0), (8, 1000.0)], 0.95)
+            .store_recommendation(&path)
+            .unwrap();
+        let loaded = load_batch_size(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.unwrap(), 8);
+
+        assert!(report(&[], 0.95).store_recommendation(&path).is_err());
+        assert!(load_batch_size(&path).is_err());
+    }
+
+    #[test]
+    fn every_batch_size_is_benchmarked() {
+        let network = NullNetwork::new();
+        let config = BenchmarkConfig {
+            batch_sizes: vec![1, 3],
+            runs: 4,
+            warmup_runs: 1,
+            ..BenchmarkConfig::default()
+        };
+        let report =
+            benchmark_network(&network, &benchmark_positions(&network, 2), &config).unwrap();
+
+        let batch_sizes: Vec<usize> = report.results.iter().map(|r| r.batch_size).collect();
+        assert_eq!(batch_sizes, [1, 3]);
+        for result in &report.results {
+            assert!(result.p50 <= result.p90 && result.p90 <= result.p99);
+            assert!(result.positions_per_second > 0.0);
+        }
+    }
+
+    #[test]
+    fn empty_positions_are_an_error() {
+        let result = benchmark_network(&NullNetwork::new(), &[], &BenchmarkConfig::default());
+        assert!(matches!(result, Err(NetworkError::NoBenchmarkPositions)));
+    }
+}
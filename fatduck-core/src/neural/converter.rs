@@ -0,0 +1,1751 @@
+use crate::{
+    neural::{
+        loader::{decode_layer, WeightFile, WeightFileError},
+        network::NUM_INPUT_PLANES,
+        policy::{move_to_policy_index, POLICY_SIZE},
+        validation,
+    },
+    pblczero::{
+        self,
+        attribute_proto::AttributeType,
+        network_format::{
+            ActivationFunction, DefaultActivation, MovesLeftFormat, NetworkStructure, PolicyFormat,
+            ValueFormat,
+        },
+        tensor_proto::DataType,
+        weights::{ConvBlock, EncoderLayer, Layer, SEunit},
+        AttributeProto, GraphProto, ModelProto, NodeProto, OperatorSetIdProto, TensorProto,
+    },
+};
+use prost::Message;
+use shakmaty::{Bitboard, Color, Move, Rank, Role, Square};
+use std::{fs, io, path::Path};
+use thiserror::Error;
+
+pub const INPUT_PLANES_NAME: &str = "/input/planes";
+pub const OUTPUT_POLICY_NAME: &str = "/output/policy";
+pub const OUTPUT_WDL_NAME: &str = "/output/wdl";
+pub const OUTPUT_VALUE_NAME: &str = "/output/value";
+pub const OUTPUT_MLH_NAME: &str = "/output/mlh";
+
+const OPSET_VERSION: i64 = 17;
+const IR_VERSION: i64 = 8;
+
+#[derive(Error, Debug)]
+pub enum OnnxConvertError {
+    #[error("Cannot convert to ONNX: the weight file contains no weights")]
+    NoWeights,
+    #[error("Cannot convert to ONNX: {0}")]
+    InvalidWeights(#[from] WeightFileError),
+    #[error("Cannot convert to ONNX: unsupported {0}")]
+    Unsupported(String),
+}
+
+type ConvertResult<T> = Result<T, OnnxConvertError>;
+
+/// Converts a protobuf weight file to a standalone ONNX model with lc0's standard input and
+/// output tensor names. Supports classical, SE and attention body networks.
+///
+/// # Errors
+///
+/// Fails if the network structure isn't supported or the weights don't pass
+/// `validate_weights`.
+pub fn convert_to_onnx(weight_file: &WeightFile) -> ConvertResult<ModelProto> {
+    let weights = weight_file.weights().ok_or(OnnxConvertError::NoWeights)?;
+    let format = weight_file.network_format();
+    // Weight files are validated on load, nets built in memory may not be.
+    validation::validate_weights(weights, &format)?;
+
+    let default_activation = match format.default_activation() {
+        DefaultActivation::Relu => Activation::Relu,
+        DefaultActivation::Mish => Activation::Mish,
+    };
+    let mut converter = Converter {
+        builder: OnnxBuilder::default(),
+        weights,
+        format: &format,
+        default_activation,
+    };
+
+    let planes = converter
+        .builder
+        .add_input(INPUT_PLANES_NAME, NUM_INPUT_PLANES);
+    let body = match format.network() {
+        NetworkStructure::NetworkClassical
+        | NetworkStructure::NetworkSe
+        | NetworkStructure::NetworkClassicalWithHeadformat
+        | NetworkStructure::NetworkSeWithHeadformat => converter.residual_body(&planes)?,
+        NetworkStructure::NetworkAttentionbodyWithHeadformat => {
+            converter.attention_body(&planes)?
+        }
+        other => {
+            return Err(OnnxConvertError::Unsupported(format!(
+                "network structure {other:?}"
+            )))
+        }
+    };
+
+    let policy = converter.policy_head(&body)?;
+    converter
+        .builder
+        .add_output(&policy, OUTPUT_POLICY_NAME, POLICY_SIZE);
+
+    let value = converter.value_head(&body)?;
+    if format.value() == ValueFormat::ValueWdl {
+        converter.builder.add_output(&value, OUTPUT_WDL_NAME, 3);
+    } else {
+        converter.builder.add_output(&value, OUTPUT_VALUE_NAME, 1);
+    }
+
+    if format.moves_left() == MovesLeftFormat::MovesLeftV1 {
+        let moves_left = converter.moves_left_head(&body)?;
+        converter
+            .builder
+            .add_output(&moves_left, OUTPUT_MLH_NAME, 1);
+    }
+
+    Ok(converter.builder.into_model())
+}
+
+/// Writes the serialized model, e.g. for `OnnxNetwork::from_file`.
+///
+/// # Errors
+///
+/// Fails if the file can't be written.
+pub fn write_onnx(model: &ModelProto, file_path: &Path) -> io::Result<()> {
+    fs::write(file_path, model.encode_to_vec())
+}
+
+/// A copy of `weight_file` where the weights are replaced by `model`, like lc0's ONNX nets.
+pub fn embed_onnx(weight_file: &WeightFile, model: &ModelProto) -> WeightFile {
+    let mut net = weight_file.net().clone();
+    let mut network_format = weight_file.network_format();
+    network_format.network = Some(NetworkStructure::NetworkOnnx as i32);
+
+    let has_wdl = network_format.value() == ValueFormat::ValueWdl;
+    let has_mlh = network_format.moves_left() == MovesLeftFormat::MovesLeftV1;
+
+    net.format
+        .get_or_insert_with(Default::default)
+        .network_format = Some(network_format);
+    net.weights = None;
+    net.onnx_model = Some(pblczero::OnnxModel {
+        model: Some(model.encode_to_vec()),
+        data_type: Some(pblczero::onnx_model::DataType::Float as i32),
+        input_planes: Some(INPUT_PLANES_NAME.to_string()),
+        output_value: (!has_wdl).then(|| OUTPUT_VALUE_NAME.to_string()),
+        output_wdl: has_wdl.then(|| OUTPUT_WDL_NAME.to_string()),
+        output_policy: Some(OUTPUT_POLICY_NAME.to_string()),
+        output_mlh: has_mlh.then(|| OUTPUT_MLH_NAME.to_string()),
+    });
+
+    WeightFile::from_net(net)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Activation {
+    None,
+    Relu,
+    Relu2,
+    Mish,
+    Selu,
+    Swish,
+    Tanh,
+    Sigmoid,
+}
+
+/// Shape of the layers of an encoder stack.
+#[derive(Clone, Copy)]
+struct EncoderParams {
+    embedding: usize,
+    heads: usize,
+    /// Scale of the skip connections.
+    alpha: f32,
+    ffn_activation: Activation,
+}
+
+struct Converter<'a> {
+    builder: OnnxBuilder,
+    weights: &'a pblczero::Weights,
+    format: &'a pblczero::NetworkFormat,
+    default_activation: Activation,
+}
+
+impl Converter<'_> {
+    const fn activation(&self, function: ActivationFunction) -> Activation {
+        match function {
+            ActivationFunction::ActivationDefault => self.default_activation,
+            ActivationFunction::ActivationMish => Activation::Mish,
+            ActivationFunction::ActivationRelu => Activation::Relu,
+            ActivationFunction::ActivationNone | ActivationFunction::ActivationSoftmax => {
+                Activation::None
+            }
+            ActivationFunction::ActivationTanh => Activation::Tanh,
+            ActivationFunction::ActivationSigmoid => Activation::Sigmoid,
+            ActivationFunction::ActivationSelu => Activation::Selu,
+            ActivationFunction::ActivationSwish => Activation::Swish,
+            ActivationFunction::ActivationRelu2 => Activation::Relu2,
+        }
+    }
+
+    /// Up to SE nets with head format, policy embeddings and policy encoders use SELU.
+    fn is_legacy_attention(&self) -> bool {
+        self.format.network() != NetworkStructure::NetworkAttentionbodyWithHeadformat
+    }
+
+    /// Input convolution and residual tower. Output is NCHW.
+    fn residual_body(&mut self, planes: &str) -> ConvertResult<(String, usize)> {
+        let act = self.default_activation;
+        let input = required(self.weights.input.as_ref(), "input")?;
+        let (mut flow, filters) =
+            self.conv_block("/inputconv", planes, input, NUM_INPUT_PLANES, 3, act)?;
+
+        for (i, residual) in self.weights.residual.iter().enumerate() {
+            let name = format!("/block{i}");
+            let conv1 = required(residual.conv1.as_ref(), &format!("{name}/conv1"))?;
+            let conv2 = required(residual.conv2.as_ref(), &format!("{name}/conv2"))?;
+
+            let (out, _) =
+                self.conv_block(&format!("{name}/conv1"), &flow, conv1, filters, 3, act)?;
+            let (mut out, _) = self.conv_block(
+                &format!("{name}/conv2"),
+                &out,
+                conv2,
+                filters,
+                3,
+                Activation::None,
+            )?;
+
+            if let Some(se) = &residual.se {
+                out = self.se_unit(&format!("{name}/se"), &out, se, filters)?;
+            }
+
+            let sum = self
+                .builder
+                .binary("Add", &format!("{name}/add"), &out, &flow);
+            flow = self.builder.activation(&format!("{name}/act"), &sum, act);
+        }
+
+        Ok((flow, filters))
+    }
+
+    /// Embedding and encoder stack. Output is [batch * 64, embedding].
+    fn attention_body(&mut self, planes: &str) -> ConvertResult<(String, usize)> {
+        let act = self.default_activation;
+        // Validation allows extra embedding inputs, which this converter doesn't generate.
+        let embedding_inputs = required(self.weights.ip_emb_w.as_ref(), "ip_emb_w")?
+            .params()
+            .len()
+            / required(self.weights.ip_emb_b.as_ref(), "ip_emb_b")?
+                .params()
+                .len();
+        if embedding_inputs != NUM_INPUT_PLANES {
+            return Err(OnnxConvertError::Unsupported(format!(
+                "input embedding of {embedding_inputs} planes"
+            )));
+        }
+
+        let b = &mut self.builder;
+        let flow = b.transpose("/attn_body/transpose", planes, &[0, 2, 3, 1]);
+        let flow = b.reshape("/attn_body/reshape", &flow, &[-1, dim(NUM_INPUT_PLANES)]);
+        let (flow, embedding) = self.dense(
+            "/attn_body/embedding",
+            &flow,
+            NUM_INPUT_PLANES,
+            self.weights.ip_emb_w.as_ref(),
+            self.weights.ip_emb_b.as_ref(),
+            act,
+        )?;
+
+        let mut flow = flow;
+        if let (Some(mult), Some(add)) = (&self.weights.ip_mult_gate, &self.weights.ip_add_gate) {
+            let b = &mut self.builder;
+            let gated = b.reshape("/attn_body/gate/reshape", &flow, &[-1, 64, dim(embedding)]);
+            let mult = b.initializer(
+                "/attn_body/gate/mult/w",
+                &[64, dim(embedding)],
+                &decode_layer(mult),
+            );
+            let add = b.initializer(
+                "/attn_body/gate/add/w",
+                &[64, dim(embedding)],
+                &decode_layer(add),
+            );
+            let gated = b.binary("Mul", "/attn_body/gate/mul", &gated, &mult);
+            let gated = b.binary("Add", "/attn_body/gate/add", &gated, &add);
+            flow = b.reshape("/attn_body/gate/flatten", &gated, &[-1, dim(embedding)]);
+        }
+
+        let encoders = self.weights.encoder.len();
+        let alpha = (2.0 * size_f32(encoders)).powf(-0.25);
+        let heads = usize::try_from(self.weights.headcount()).expect("head count fits in usize");
+        let ffn_activation = self.activation(self.format.ffn_activation());
+
+        for (i, layer) in self.weights.encoder.iter().enumerate() {
+            flow = self.encoder_layer(
+                &format!("/encoder{i}"),
+                &flow,
+                layer,
+                EncoderParams {
+                    embedding,
+                    heads,
+                    alpha,
+                    ffn_activation,
+                },
+            )?;
+        }
+
+        Ok((flow, embedding))
+    }
+
+    fn encoder_layer(
+        &mut self,
+        name: &str,
+        input: &str,
+        layer: &EncoderLayer,
+        params: EncoderParams,
+    ) -> ConvertResult<String> {
+        let EncoderParams {
+            embedding,
+            heads,
+            alpha,
+            ffn_activation,
+        } = params;
+        let mha = required(layer.mha.as_ref(), &format!("{name}/mha"))?;
+        let attention = self.attention(&format!("{name}/mha"), input, mha, embedding, heads)?;
+
+        let flow = self.skip_connection(&format!("{name}/skip1"), input, &attention, alpha);
+        let flow = self.layer_norm(
+            &format!("{name}/ln1"),
+            &flow,
+            layer.ln1_gammas.as_ref(),
+            layer.ln1_betas.as_ref(),
+            1e-6,
+        )?;
+
+        let ffn = required(layer.ffn.as_ref(), &format!("{name}/ffn"))?;
+        let (hidden, hidden_size) = self.dense(
+            &format!("{name}/ffn/dense1"),
+            &flow,
+            embedding,
+            ffn.dense1_w.as_ref(),
+            ffn.dense1_b.as_ref(),
+            ffn_activation,
+        )?;
+        let (out, _) = self.dense(
+            &format!("{name}/ffn/dense2"),
+            &hidden,
+            hidden_size,
+            ffn.dense2_w.as_ref(),
+            ffn.dense2_b.as_ref(),
+            Activation::None,
+        )?;
+
+        let flow = self.skip_connection(&format!("{name}/skip2"), &flow, &out, alpha);
+        self.layer_norm(
+            &format!("{name}/ln2"),
+            &flow,
+            layer.ln2_gammas.as_ref(),
+            layer.ln2_betas.as_ref(),
+            1e-6,
+        )
+    }
+
+    /// Multi-head self attention over the 64 squares, input and output are [batch * 64, d].
+    fn attention(
+        &mut self,
+        name: &str,
+        input: &str,
+        mha: &pblczero::weights::Mha,
+        embedding: usize,
+        heads: usize,
+    ) -> ConvertResult<String> {
+        let none = Activation::None;
+        let (q, d_model) = self.dense(
+            &format!("{name}/q"),
+            input,
+            embedding,
+            mha.q_w.as_ref(),
+            mha.q_b.as_ref(),
+            none,
+        )?;
+        let (k, _) = self.dense(
+            &format!("{name}/k"),
+            input,
+            embedding,
+            mha.k_w.as_ref(),
+            mha.k_b.as_ref(),
+            none,
+        )?;
+        let (v, _) = self.dense(
+            &format!("{name}/v"),
+            input,
+            embedding,
+            mha.v_w.as_ref(),
+            mha.v_b.as_ref(),
+            none,
+        )?;
+
+        let depth = d_model / heads;
+        let split = [-1, 64, dim(heads), dim(depth)];
+
+        let b = &mut self.builder;
+        let q = b.reshape(&format!("{name}/q/split"), &q, &split);
+        let q = b.transpose(&format!("{name}/q/transpose"), &q, &[0, 2, 1, 3]);
+        let k = b.reshape(&format!("{name}/k/split"), &k, &split);
+        let k = b.transpose(&format!("{name}/k/transpose"), &k, &[0, 2, 3, 1]);
+        let v = b.reshape(&format!("{name}/v/split"), &v, &split);
+        let v = b.transpose(&format!("{name}/v/transpose"), &v, &[0, 2, 1, 3]);
+
+        let logits = b.binary("MatMul", &format!("{name}/qk"), &q, &k);
+        let scale = b.scalar(&format!("{name}/scale"), 1.0 / size_f32(depth).sqrt());
+        let mut logits = b.binary("Mul", &format!("{name}/qk/scaled"), &logits, &scale);
+
+        if let Some(smolgen) = &mha.smolgen {
+            let bias =
+                self.smolgen(&format!("{name}/smolgen"), input, smolgen, embedding, heads)?;
+            logits = self
+                .builder
+                .binary("Add", &format!("{name}/qk/smolgen"), &logits, &bias);
+        }
+
+        let b = &mut self.builder;
+        let attention = b.softmax(&format!("{name}/softmax"), &logits, -1);
+        let out = b.binary("MatMul", &format!("{name}/attention"), &attention, &v);
+        let out = b.transpose(&format!("{name}/out/transpose"), &out, &[0, 2, 1, 3]);
+        let out = b.reshape(&format!("{name}/out/merge"), &out, &[-1, dim(d_model)]);
+
+        let (out, _) = self.dense(
+            &format!("{name}/dense"),
+            &out,
+            d_model,
+            mha.dense_w.as_ref(),
+            mha.dense_b.as_ref(),
+            none,
+        )?;
+        Ok(out)
+    }
+
+    /// Attention logit biases generated from the whole position, [batch, heads, 64, 64].
+    fn smolgen(
+        &mut self,
+        name: &str,
+        input: &str,
+        smolgen: &pblczero::weights::Smolgen,
+        embedding: usize,
+        heads: usize,
+    ) -> ConvertResult<String> {
+        let act = self.activation(self.format.smolgen_activation());
+
+        let (compressed, channels) = self.dense(
+            &format!("{name}/compress"),
+            input,
+            embedding,
+            smolgen.compress.as_ref(),
+            None,
+            Activation::None,
+        )?;
+        let compressed = self.builder.reshape(
+            &format!("{name}/compress/flatten"),
+            &compressed,
+            &[-1, 64 * dim(channels)],
+        );
+
+        let (hidden, hidden_size) = self.dense(
+            &format!("{name}/dense1"),
+            &compressed,
+            64 * channels,
+            smolgen.dense1_w.as_ref(),
+            smolgen.dense1_b.as_ref(),
+            act,
+        )?;
+        let hidden = self.layer_norm(
+            &format!("{name}/ln1"),
+            &hidden,
+            smolgen.ln1_gammas.as_ref(),
+            smolgen.ln1_betas.as_ref(),
+            1e-3,
+        )?;
+        let (generated, generated_size) = self.dense(
+            &format!("{name}/dense2"),
+            &hidden,
+            hidden_size,
+            smolgen.dense2_w.as_ref(),
+            smolgen.dense2_b.as_ref(),
+            act,
+        )?;
+        let generated = self.layer_norm(
+            &format!("{name}/ln2"),
+            &generated,
+            smolgen.ln2_gammas.as_ref(),
+            smolgen.ln2_betas.as_ref(),
+            1e-3,
+        )?;
+
+        let per_head = generated_size / heads.max(1);
+        let generated = self.builder.reshape(
+            &format!("{name}/per_head"),
+            &generated,
+            &[-1, dim(per_head)],
+        );
+        let (biases, _) = self.dense(
+            &format!("{name}/global"),
+            &generated,
+            per_head,
+            self.weights.smolgen_w.as_ref(),
+            self.weights.smolgen_b.as_ref(),
+            Activation::None,
+        )?;
+
+        Ok(self.builder.reshape(
+            &format!("{name}/reshape"),
+            &biases,
+            &[-1, dim(heads), 64, 64],
+        ))
+    }
+
+    fn policy_head(&mut self, body: &(String, usize)) -> ConvertResult<String> {
+        let (flow, channels) = (body.0.as_str(), body.1);
+        let act = self.default_activation;
+        let weights = self.weights;
+
+        match self.format.policy() {
+            PolicyFormat::PolicyAttention => self.attention_policy(flow, channels),
+            PolicyFormat::PolicyConvolution => {
+                let policy1 = required(weights.policy1.as_ref(), "policy1")?;
+                let policy = required(weights.policy.as_ref(), "policy")?;
+                let (flow, _) =
+                    self.conv_block("/policy/conv1", flow, policy1, channels, 3, act)?;
+                let (flow, _) = self.conv_block(
+                    "/policy/conv2",
+                    &flow,
+                    policy,
+                    channels,
+                    3,
+                    Activation::None,
+                )?;
+
+                let b = &mut self.builder;
+                let flow = b.reshape("/policy/flatten", &flow, &[-1, 80 * 64]);
+                let map = b.initializer_i64("/policy/map", &[dim(POLICY_SIZE)], &conv_policy_map());
+                Ok(b.gather("/policy/gather", &flow, &map, 1))
+            }
+            _ => {
+                let policy = required(weights.policy.as_ref(), "policy")?;
+                let (flow, filters) =
+                    self.conv_block("/policy/conv", flow, policy, channels, 1, act)?;
+                let flow = self
+                    .builder
+                    .reshape("/policy/flatten", &flow, &[-1, 64 * dim(filters)]);
+                let (flow, _) = self.dense(
+                    "/policy/dense",
+                    &flow,
+                    64 * filters,
+                    weights.ip_pol_w.as_ref(),
+                    weights.ip_pol_b.as_ref(),
+                    Activation::None,
+                )?;
+                Ok(flow)
+            }
+        }
+    }
+
+    fn attention_policy(&mut self, body: &str, channels: usize) -> ConvertResult<String> {
+        let weights = self.weights;
+        let legacy = self.is_legacy_attention();
+        let embedding_activation = if legacy {
+            Activation::Selu
+        } else {
+            self.default_activation
+        };
+
+        // Residual bodies are NCHW, attention bodies already are [batch * 64, channels].
+        let flow = if legacy {
+            let flow = self
+                .builder
+                .transpose("/policy/transpose", body, &[0, 2, 3, 1]);
+            self.builder
+                .reshape("/policy/reshape", &flow, &[-1, dim(channels)])
+        } else {
+            body.to_string()
+        };
+
+        let (mut flow, embedding) = self.dense(
+            "/policy/embedding",
+            &flow,
+            channels,
+            weights.ip_pol_w.as_ref(),
+            weights.ip_pol_b.as_ref(),
+            embedding_activation,
+        )?;
+
+        let ffn_activation = if legacy {
+            Activation::Selu
+        } else {
+            self.activation(self.format.ffn_activation())
+        };
+        let heads = usize::try_from(weights.pol_headcount()).expect("head count fits in usize");
+        for (i, layer) in weights.pol_encoder.iter().enumerate() {
+            flow = self.encoder_layer(
+                &format!("/policy/encoder{i}"),
+                &flow,
+                layer,
+                EncoderParams {
+                    embedding,
+                    heads,
+                    alpha: 1.0,
+                    ffn_activation,
+                },
+            )?;
+        }
+
+        let none = Activation::None;
+        let (q, dk) = self.dense(
+            "/policy/q",
+            &flow,
+            embedding,
+            weights.ip2_pol_w.as_ref(),
+            weights.ip2_pol_b.as_ref(),
+            none,
+        )?;
+        let (k, _) = self.dense(
+            "/policy/k",
+            &flow,
+            embedding,
+            weights.ip3_pol_w.as_ref(),
+            weights.ip3_pol_b.as_ref(),
+            none,
+        )?;
+        let sqrt_dk = size_f32(dk).sqrt();
+
+        let b = &mut self.builder;
+        let q = b.reshape("/policy/q/reshape", &q, &[-1, 64, dim(dk)]);
+        let keys = b.reshape("/policy/k/reshape", &k, &[-1, 64, dim(dk)]);
+        let keys_t = b.transpose("/policy/k/transpose", &keys, &[0, 2, 1]);
+        let qk = b.binary("MatMul", "/policy/qk", &q, &keys_t);
+
+        let promotions = self.promotion_logits(&qk, &keys, dk, sqrt_dk)?;
+
+        let b = &mut self.builder;
+
+        let regular = b.reshape("/policy/qk/flatten", &qk, &[-1, 64 * 64]);
+        let logits = b.concat("/policy/logits", &[regular, promotions], 1);
+        let inv_sqrt_dk = b.scalar("/policy/inv_sqrt_dk", 1.0 / sqrt_dk);
+        let logits = b.binary("Mul", "/policy/logits/scaled", &logits, &inv_sqrt_dk);
+
+        let map = b.initializer_i64("/policy/map", &[dim(POLICY_SIZE)], &attention_policy_map());
+        Ok(b.gather("/policy/gather", &logits, &map, 1))
+    }
+
+    /// Logits of the queen, rook and bishop promotions, [batch, 8 * 24] by from file, to file
+    /// and piece. `keys` are the attention policy keys, [batch, 64, dk].
+    fn promotion_logits(
+        &mut self,
+        qk: &str,
+        keys: &str,
+        dk: usize,
+        sqrt_dk: f32,
+    ) -> ConvertResult<String> {
+        // Promotion logits are offsets, generated from the promotion rank keys, added to the
+        // logits of the moves from the 7th to the 8th rank. The knight offset applies to all.
+        let b = &mut self.builder;
+        let promotion_keys = b.slice("/policy/promotion/keys", keys, &[56], &[64], &[1]);
+        let promotion_keys = b.reshape(
+            "/policy/promotion/keys/flatten",
+            &promotion_keys,
+            &[-1, dim(dk)],
+        );
+        let (offsets, _) = self.dense(
+            "/policy/promotion/dense",
+            &promotion_keys,
+            dk,
+            self.weights.ip4_pol_w.as_ref(),
+            None,
+            Activation::None,
+        )?;
+
+        let b = &mut self.builder;
+        let offsets = b.reshape("/policy/promotion/reshape", &offsets, &[-1, 8, 4]);
+        let offsets = b.transpose("/policy/promotion/transpose", &offsets, &[0, 2, 1]);
+        let scale = b.scalar("/policy/promotion/sqrt_dk", sqrt_dk);
+        let offsets = b.binary("Mul", "/policy/promotion/scaled", &offsets, &scale);
+        let qrb = b.slice("/policy/promotion/qrb", &offsets, &[0], &[3], &[1]);
+        let knight = b.slice("/policy/promotion/n", &offsets, &[3], &[4], &[1]);
+        let offsets = b.binary("Add", "/policy/promotion/offsets", &qrb, &knight);
+
+        let knight_logits = b.slice(
+            "/policy/promotion/n_logits",
+            qk,
+            &[48, 56],
+            &[56, 64],
+            &[1, 2],
+        );
+        let mut promotions = Vec::new();
+        for (i, piece) in ["q", "r", "b"].iter().enumerate() {
+            let offset = b.slice(
+                &format!("/policy/promotion/{piece}/offset"),
+                &offsets,
+                &[dim(i)],
+                &[dim(i) + 1],
+                &[1],
+            );
+            let logits = b.binary(
+                "Add",
+                &format!("/policy/promotion/{piece}"),
+                &knight_logits,
+                &offset,
+            );
+            promotions.push(b.reshape(
+                &format!("/policy/promotion/{piece}/reshape"),
+                &logits,
+                &[-1, 8, 8, 1],
+            ));
+        }
+        let promotions = b.concat("/policy/promotion/concat", &promotions, 3);
+        Ok(b.reshape("/policy/promotion/flatten", &promotions, &[-1, 8 * 24]))
+    }
+
+    fn value_head(&mut self, body: &(String, usize)) -> ConvertResult<String> {
+        let weights = self.weights;
+        let flow = self.head_embedding(
+            "/value",
+            body,
+            weights.value.as_ref(),
+            weights.ip_val_w.as_ref(),
+            weights.ip_val_b.as_ref(),
+        )?;
+
+        let act = self.default_activation;
+        let (flow, size) = flow;
+        let (flow, hidden) = self.dense(
+            "/value/dense1",
+            &flow,
+            size,
+            weights.ip1_val_w.as_ref(),
+            weights.ip1_val_b.as_ref(),
+            act,
+        )?;
+        let (flow, _) = self.dense(
+            "/value/dense2",
+            &flow,
+            hidden,
+            weights.ip2_val_w.as_ref(),
+            weights.ip2_val_b.as_ref(),
+            Activation::None,
+        )?;
+
+        Ok(if self.format.value() == ValueFormat::ValueWdl {
+            self.builder.softmax("/value/softmax", &flow, 1)
+        } else {
+            self.builder
+                .activation("/value/tanh", &flow, Activation::Tanh)
+        })
+    }
+
+    fn moves_left_head(&mut self, body: &(String, usize)) -> ConvertResult<String> {
+        let weights = self.weights;
+        let (flow, size) = self.head_embedding(
+            "/mlh",
+            body,
+            weights.moves_left.as_ref(),
+            weights.ip_mov_w.as_ref(),
+            weights.ip_mov_b.as_ref(),
+        )?;
+
+        let act = self.default_activation;
+        let (flow, hidden) = self.dense(
+            "/mlh/dense1",
+            &flow,
+            size,
+            weights.ip1_mov_w.as_ref(),
+            weights.ip1_mov_b.as_ref(),
+            act,
+        )?;
+        let (flow, _) = self.dense(
+            "/mlh/dense2",
+            &flow,
+            hidden,
+            weights.ip2_mov_w.as_ref(),
+            weights.ip2_mov_b.as_ref(),
+            Activation::Relu,
+        )?;
+        Ok(flow)
+    }
+
+    /// Per square features of a value or moves left head, flattened to [batch, 64 * size]. A
+    /// 1x1 convolution for residual bodies and a dense embedding for attention bodies.
+    fn head_embedding(
+        &mut self,
+        name: &str,
+        body: &(String, usize),
+        conv: Option<&ConvBlock>,
+        embedding_w: Option<&Layer>,
+        embedding_b: Option<&Layer>,
+    ) -> ConvertResult<(String, usize)> {
+        let act = self.default_activation;
+        let (flow, channels) = (body.0.as_str(), body.1);
+
+        let (flow, size) = if self.is_legacy_attention() {
+            let conv = required(conv, &format!("{name}/conv"))?;
+            self.conv_block(&format!("{name}/conv"), flow, conv, channels, 1, act)?
+        } else {
+            self.dense(
+                &format!("{name}/embedding"),
+                flow,
+                channels,
+                embedding_w,
+                embedding_b,
+                act,
+            )?
+        };
+
+        let flow = self
+            .builder
+            .reshape(&format!("{name}/flatten"), &flow, &[-1, 64 * dim(size)]);
+        Ok((flow, 64 * size))
+    }
+
+    /// Convolution with the batch norm folded into the weights and biases.
+    fn conv_block(
+        &mut self,
+        name: &str,
+        input: &str,
+        block: &ConvBlock,
+        in_channels: usize,
+        kernel: usize,
+        activation: Activation,
+    ) -> ConvertResult<(String, usize)> {
+        let mut weights = layer_values(block.weights.as_ref(), &format!("{name}/weights"))?;
+        let per_filter = in_channels * kernel * kernel;
+        let filters = weights.len() / per_filter;
+
+        let mut biases = block
+            .biases
+            .as_ref()
+            .map_or_else(|| vec![0.0; filters], decode_layer);
+        biases.resize(filters, 0.0);
+
+        if let (Some(means), Some(variances)) = (&block.bn_means, &block.bn_stddivs) {
+            let means = decode_layer(means);
+            let variances = decode_layer(variances);
+            let gammas = block
+                .bn_gammas
+                .as_ref()
+                .map_or_else(|| vec![1.0; filters], decode_layer);
+            let betas = block
+                .bn_betas
+                .as_ref()
+                .map_or_else(|| vec![0.0; filters], decode_layer);
+
+            for f in 0..filters {
+                let scale = gammas[f] / (variances[f] + 1e-5).sqrt();
+                weights[f * per_filter..(f + 1) * per_filter]
+                    .iter_mut()
+                    .for_each(|w| *w *= scale);
+                biases[f] = (biases[f] - means[f]).mul_add(scale, betas[f]);
+            }
+        }
+
+        let b = &mut self.builder;
+        let k = dim(kernel);
+        let w = b.initializer(
+            &format!("{name}/w"),
+            &[dim(filters), dim(in_channels), k, k],
+            &weights,
+        );
+        let bias = b.initializer(&format!("{name}/b"), &[dim(filters)], &biases);
+        let pad = k / 2;
+        let conv = b.node(
+            "Conv",
+            name,
+            &[input, &w, &bias],
+            vec![
+                attr_ints("kernel_shape", &[k, k]),
+                attr_ints("pads", &[pad, pad, pad, pad]),
+            ],
+        );
+
+        Ok((
+            b.activation(&format!("{name}/act"), &conv, activation),
+            filters,
+        ))
+    }
+
+    fn se_unit(
+        &mut self,
+        name: &str,
+        input: &str,
+        se: &SEunit,
+        channels: usize,
+    ) -> ConvertResult<String> {
+        let act = self.default_activation;
+        let b = &mut self.builder;
+        let pooled = b.node(
+            "GlobalAveragePool",
+            &format!("{name}/pool"),
+            &[input],
+            vec![],
+        );
+        let pooled = b.reshape(&format!("{name}/flatten"), &pooled, &[-1, dim(channels)]);
+
+        let (hidden, se_channels) = self.dense(
+            &format!("{name}/dense1"),
+            &pooled,
+            channels,
+            se.w1.as_ref(),
+            se.b1.as_ref(),
+            act,
+        )?;
+        let (out, _) = self.dense(
+            &format!("{name}/dense2"),
+            &hidden,
+            se_channels,
+            se.w2.as_ref(),
+            se.b2.as_ref(),
+            Activation::None,
+        )?;
+
+        let c = dim(channels);
+        let b = &mut self.builder;
+        let gammas = b.slice(&format!("{name}/gammas"), &out, &[0], &[c], &[1]);
+        let gammas = b.activation(&format!("{name}/sigmoid"), &gammas, Activation::Sigmoid);
+        let gammas = b.reshape(&format!("{name}/gammas/reshape"), &gammas, &[-1, c, 1, 1]);
+        let betas = b.slice(&format!("{name}/betas"), &out, &[c], &[2 * c], &[1]);
+        let betas = b.reshape(&format!("{name}/betas/reshape"), &betas, &[-1, c, 1, 1]);
+
+        let scaled = b.binary("Mul", &format!("{name}/mul"), input, &gammas);
+        Ok(b.binary("Add", &format!("{name}/add"), &scaled, &betas))
+    }
+
+    /// Fully connected layer on a 2D input. lc0 stores the weights as [out][in].
+    fn dense(
+        &mut self,
+        name: &str,
+        input: &str,
+        in_size: usize,
+        weights: Option<&Layer>,
+        biases: Option<&Layer>,
+        activation: Activation,
+    ) -> ConvertResult<(String, usize)> {
+        let w = layer_values(weights, &format!("{name}/w"))?;
+        let out_size = w.len() / in_size;
+
+        let b = &mut self.builder;
+        let w = b.initializer(&format!("{name}/w"), &[dim(out_size), dim(in_size)], &w);
+        let mut inputs = vec![input.to_string(), w];
+        if let Some(biases) = biases {
+            inputs.push(b.initializer(
+                &format!("{name}/b"),
+                &[dim(out_size)],
+                &decode_layer(biases),
+            ));
+        }
+
+        let inputs: Vec<&str> = inputs.iter().map(String::as_str).collect();
+        let flow = b.node("Gemm", name, &inputs, vec![attr_int("transB", 1)]);
+        Ok((
+            b.activation(&format!("{name}/act"), &flow, activation),
+            out_size,
+        ))
+    }
+
+    fn layer_norm(
+        &mut self,
+        name: &str,
+        input: &str,
+        gammas: Option<&Layer>,
+        betas: Option<&Layer>,
+        epsilon: f32,
+    ) -> ConvertResult<String> {
+        let gammas = layer_values(gammas, &format!("{name}/gammas"))?;
+        let betas = layer_values(betas, &format!("{name}/betas"))?;
+
+        let b = &mut self.builder;
+        let size = dim(gammas.len());
+        let gammas = b.initializer(&format!("{name}/gammas"), &[size], &gammas);
+        let betas = b.initializer(&format!("{name}/betas"), &[size], &betas);
+        Ok(b.node(
+            "LayerNormalization",
+            name,
+            &[input, &gammas, &betas],
+            vec![attr_int("axis", -1), attr_float("epsilon", epsilon)],
+        ))
+    }
+
+    /// `input * alpha + residual`
+    fn skip_connection(&mut self, name: &str, input: &str, residual: &str, alpha: f32) -> String {
+        let b = &mut self.builder;
+        if (alpha - 1.0).abs() < f32::EPSILON {
+            return b.binary("Add", name, input, residual);
+        }
+
+        let alpha = b.scalar(&format!("{name}/alpha"), alpha);
+        let scaled = b.binary("Mul", &format!("{name}/scaled"), input, &alpha);
+        b.binary("Add", name, &scaled, residual)
+    }
+}
+
+/// Tensor dimension of a size.
+fn dim(size: usize) -> i64 {
+    i64::try_from(size).expect("tensor sizes fit in i64")
+}
+
+/// Sizes are far below 2^24, so they are exact as `f32`.
+#[allow(clippy::cast_precision_loss)]
+const fn size_f32(size: usize) -> f32 {
+    size as f32
+}
+
+/// Layer shapes are checked by `validate_weights` before converting, so this only unwraps.
+fn required<'a, T>(layer: Option<&'a T>, name: &str) -> ConvertResult<&'a T> {
+    layer.ok_or_else(|| WeightFileError::MissingLayer(name.to_string()).into())
+}
+
+fn layer_values(layer: Option<&Layer>, name: &str) -> ConvertResult<Vec<f32>> {
+    Ok(decode_layer(required(layer, name)?))
+}
+
+/// Index of every policy output in the concatenated attention logits: 64 * 64 from/to logits
+/// followed by 8 * 24 promotion logits (from file, to file, queen/rook/bishop).
+fn attention_policy_map() -> Vec<i64> {
+    let mut map = vec![0; POLICY_SIZE];
+
+    for from in 0..64_u32 {
+        for to in 0..64_u32 {
+            let m = Move::Normal {
+                role: Role::Queen,
+                from: Square::new(from),
+                capture: None,
+                to: Square::new(to),
+                promotion: None,
+            };
+            if let Some(idx) = move_to_policy_index(&m, Color::White) {
+                map[idx] = i64::from(from * 64 + to);
+            }
+        }
+    }
+
+    for from_file in 0..8_u32 {
+        for to_file in from_file.saturating_sub(1)..=(from_file + 1).min(7) {
+            for (piece, role) in (0_u32..).zip([Role::Queen, Role::Rook, Role::Bishop]) {
+                let m = Move::Normal {
+                    role: Role::Pawn,
+                    from: Square::new(48 + from_file),
+                    capture: None,
+                    to: Square::new(56 + to_file),
+                    promotion: Some(role),
+                };
+                if let Some(idx) = move_to_policy_index(&m, Color::White) {
+                    map[idx] = i64::from(4096 + from_file * 24 + to_file * 3 + piece);
+                }
+            }
+        }
+    }
+
+    map
+}
+
+/// Index of every policy output in the flattened 80x8x8 convolution policy planes, laid out as in
+/// `AlphaZero`: 56 queen move planes (direction N, NE, E, SE, S, SW, W, NW, then distance 1-7), 8
+/// knight move planes and 9 promotion planes (file delta -1, 0, 1, then rook, bishop, queen).
+/// Knight promotions use the queen move planes.
+fn conv_policy_map() -> Vec<i64> {
+    const QUEEN_DIRECTIONS: [(i32, i32); 8] = [
+        (0, 1),
+        (1, 1),
+        (1, 0),
+        (1, -1),
+        (0, -1),
+        (-1, -1),
+        (-1, 0),
+        (-1, 1),
+    ];
+    const KNIGHT_DIRECTIONS: [(i32, i32); 8] = [
+        (1, 2),
+        (2, 1),
+        (2, -1),
+        (1, -2),
+        (-1, -2),
+        (-2, -1),
+        (-2, 1),
+        (-1, 2),
+    ];
+
+    let mut map = vec![0; POLICY_SIZE];
+    let target = |from: Square, (df, dr): (i32, i32)| {
+        Some(Square::from_coords(
+            from.file().offset(df)?,
+            from.rank().offset(dr)?,
+        ))
+    };
+    let mut set = |plane: u32, from: Square, to: Square, promotion: Option<Role>| {
+        let m = Move::Normal {
+            role: Role::Queen,
+            from,
+            capture: None,
+            to,
+            promotion,
+        };
+        if let Some(idx) = move_to_policy_index(&m, Color::White) {
+            map[idx] = i64::from(plane * 64 + u32::from(from));
+        }
+    };
+
+    for (d, &(df, dr)) in (0_u32..).zip(QUEEN_DIRECTIONS.iter()) {
+        for (distance, plane) in (1..8).zip(d * 7..) {
+            for from in Square::ALL {
+                if let Some(to) = target(from, (df * distance, dr * distance)) {
+                    set(plane, from, to, None);
+                }
+            }
+        }
+    }
+
+    for (plane, &delta) in (56..).zip(KNIGHT_DIRECTIONS.iter()) {
+        for from in Square::ALL {
+            if let Some(to) = target(from, delta) {
+                set(plane, from, to, None);
+            }
+        }
+    }
+
+    let promotions = [-1, 0, 1]
+        .into_iter()
+        .flat_map(|df| [Role::Rook, Role::Bishop, Role::Queen].map(|role| (df, role)));
+    for (plane, (df, role)) in (64..).zip(promotions) {
+        for from in Bitboard::from(Rank::Seventh) {
+            if let Some(to) = target(from, (df, 1)) {
+                set(plane, from, to, Some(role));
+            }
+        }
+    }
+
+    map
+}
+
+fn attr_int(name: &str, value: i64) -> AttributeProto {
+    AttributeProto {
+        name: Some(name.to_string()),
+        i: Some(value),
+        r#type: Some(AttributeType::Int as i32),
+        ..AttributeProto::default()
+    }
+}
+
+fn attr_ints(name: &str, values: &[i64]) -> AttributeProto {
+    AttributeProto {
+        name: Some(name.to_string()),
+        ints: values.to_vec(),
+        r#type: Some(AttributeType::Ints as i32),
+        ..AttributeProto::default()
+    }
+}
+
+fn attr_float(name: &str, value: f32) -> AttributeProto {
+    AttributeProto {
+        name: Some(name.to_string()),
+        f: Some(value),
+        r#type: Some(AttributeType::Float as i32),
+        ..AttributeProto::default()
+    }
+}
+
+/// Appends nodes and initializers to an ONNX graph. Every node has a single output, named after
+/// the node.
+#[derive(Default)]
+struct OnnxBuilder {
+    graph: GraphProto,
+}
+
+impl OnnxBuilder {
+    fn into_model(self) -> ModelProto {
+        ModelProto {
+            ir_version: Some(IR_VERSION),
+            producer_name: Some("FatDuck".to_string()),
+            producer_version: Some(env!("CARGO_PKG_VERSION").to_string()),
+            domain: Some("org.lczero.models.fatduck".to_string()),
+            model_version: Some(1),
+            graph: Some(GraphProto {
+                name: Some("lc0".to_string()),
+                ..self.graph
+            }),
+            opset_import: vec![OperatorSetIdProto {
+                domain: Some(String::new()),
+                version: Some(OPSET_VERSION),
+            }],
+            ..ModelProto::default()
+        }
+    }
+
+    fn value_info(name: &str, dims: &[Option<i64>]) -> pblczero::ValueInfoProto {
+        let dim = dims
+            .iter()
+            .map(|d| pblczero::tensor_shape_proto::Dimension {
+                dim_value: *d,
+                dim_param: d.is_none().then(|| "batch".to_string()),
+            })
+            .collect();
+
+        pblczero::ValueInfoProto {
+            name: Some(name.to_string()),
+            r#type: Some(pblczero::TypeProto {
+                tensor_type: Some(pblczero::type_proto::Tensor {
+                    elem_type: Some(DataType::Float as i32),
+                    shape: Some(pblczero::TensorShapeProto { dim }),
+                }),
+            }),
+            doc_string: None,
+        }
+    }
+
+    fn add_input(&mut self, name: &str, planes: usize) -> String {
+        self.graph.input.push(Self::value_info(
+            name,
+            &[None, Some(dim(planes)), Some(8), Some(8)],
+        ));
+        name.to_string()
+    }
+
+    /// Exposes `flow` as a graph output called `name`.
+    fn add_output(&mut self, flow: &str, name: &str, size: usize) {
+        self.node("Identity", name, &[flow], vec![]);
+        self.graph
+            .output
+            .push(Self::value_info(name, &[None, Some(dim(size))]));
+    }
+
+    fn initializer(&mut self, name: &str, dims: &[i64], values: &[f32]) -> String {
+        let raw_data = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        self.push_initializer(name, dims, DataType::Float, raw_data)
+    }
+
+    fn initializer_i64(&mut self, name: &str, dims: &[i64], values: &[i64]) -> String {
+        let raw_data = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+        self.push_initializer(name, dims, DataType::Int64, raw_data)
+    }
+
+    fn push_initializer(
+        &mut self,
+        name: &str,
+        dims: &[i64],
+        data_type: DataType,
+        raw_data: Vec<u8>,
+    ) -> String {
+        self.graph.initializer.push(TensorProto {
+            dims: dims.to_vec(),
+            data_type: Some(data_type as i32),
+            name: Some(name.to_string()),
+            raw_data: Some(raw_data),
+            doc_string: None,
+        });
+        name.to_string()
+    }
+
+    fn scalar(&mut self, name: &str, value: f32) -> String {
+        self.initializer(name, &[], &[value])
+    }
+
+    fn node(
+        &mut self,
+        op_type: &str,
+        name: &str,
+        inputs: &[&str],
+        attribute: Vec<AttributeProto>,
+    ) -> String {
+        self.graph.node.push(NodeProto {
+            input: inputs.iter().map(|i| (*i).to_string()).collect(),
+            output: vec![name.to_string()],
+            name: Some(name.to_string()),
+            op_type: Some(op_type.to_string()),
+            attribute,
+            doc_string: None,
+            domain: None,
+        });
+        name.to_string()
+    }
+
+    fn binary(&mut self, op_type: &str, name: &str, a: &str, b: &str) -> String {
+        self.node(op_type, name, &[a, b], vec![])
+    }
+
+    fn reshape(&mut self, name: &str, input: &str, shape: &[i64]) -> String {
+        let shape = self.initializer_i64(&format!("{name}/shape"), &[dim(shape.len())], shape);
+        self.node("Reshape", name, &[input, &shape], vec![])
+    }
+
+    fn transpose(&mut self, name: &str, input: &str, perm: &[i64]) -> String {
+        self.node("Transpose", name, &[input], vec![attr_ints("perm", perm)])
+    }
+
+    fn softmax(&mut self, name: &str, input: &str, axis: i64) -> String {
+        self.node("Softmax", name, &[input], vec![attr_int("axis", axis)])
+    }
+
+    fn concat(&mut self, name: &str, inputs: &[String], axis: i64) -> String {
+        let inputs: Vec<&str> = inputs.iter().map(String::as_str).collect();
+        self.node("Concat", name, &inputs, vec![attr_int("axis", axis)])
+    }
+
+    fn gather(&mut self, name: &str, input: &str, indices: &str, axis: i64) -> String {
+        self.node(
+            "Gather",
+            name,
+            &[input, indices],
+            vec![attr_int("axis", axis)],
+        )
+    }
+
+    fn slice(
+        &mut self,
+        name: &str,
+        input: &str,
+        starts: &[i64],
+        ends: &[i64],
+        axes: &[i64],
+    ) -> String {
+        let len = dim(starts.len());
+        let starts = self.initializer_i64(&format!("{name}/starts"), &[len], starts);
+        let ends = self.initializer_i64(&format!("{name}/ends"), &[len], ends);
+        let axes = self.initializer_i64(&format!("{name}/axes"), &[len], axes);
+        self.node("Slice", name, &[input, &starts, &ends, &axes], vec![])
+    }
+
+    fn activation(&mut self, name: &str, input: &str, activation: Activation) -> String {
+        match activation {
+            Activation::None => input.to_string(),
+            Activation::Relu => self.node("Relu", name, &[input], vec![]),
+            Activation::Relu2 => {
+                let relu = self.node("Relu", &format!("{name}/relu"), &[input], vec![]);
+                self.binary("Mul", name, &relu, &relu)
+            }
+            Activation::Mish => {
+                let softplus = self.node("Softplus", &format!("{name}/softplus"), &[input], vec![]);
+                let tanh = self.node("Tanh", &format!("{name}/tanh"), &[&softplus], vec![]);
+                self.binary("Mul", name, input, &tanh)
+            }
+            Activation::Selu => self.node("Selu", name, &[input], vec![]),
+            Activation::Swish => {
+                let sigmoid = self.node("Sigmoid", &format!("{name}/sigmoid"), &[input], vec![]);
+                self.binary("Mul", name, input, &sigmoid)
+            }
+            Activation::Tanh => self.node("Tanh", name, &[input], vec![]),
+            Activation::Sigmoid => self.node("Sigmoid", name, &[input], vec![]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pblczero::{
+        format::Encoding,
+        network_format::InputFormat,
+        weights::{Ffn, Mha, Residual, SEunit, Smolgen},
+        EngineVersion, Format, Net, NetworkFormat, Weights,
+    };
+    use std::{collections::HashSet, env, process};
+
+    const FILTERS: usize = 4;
+
+    fn layer(len: usize) -> Layer {
+        let params = (0..len)
+            .flat_map(|i| u16::try_from(i * 7919 % 65_536).unwrap().to_le_bytes())
+            .collect();
+        Layer {
+            min_val: Some(-0.5),
+            max_val: Some(0.5),
+            params: Some(params),
+        }
+    }
+
+    fn conv(in_channels: usize, filters: usize, kernel: usize) -> ConvBlock {
+        ConvBlock {
+            weights: Some(layer(filters * in_channels * kernel * kernel)),
+            biases: Some(layer(filters)),
+            bn_means: Some(layer(filters)),
+            bn_stddivs: Some(layer(filters)),
+            ..ConvBlock::default()
+        }
+    }
+
+    fn net(weights: Weights, network_format: &NetworkFormat) -> WeightFile {
+        WeightFile::from_net(Net {
+            magic: Some(0x1c0),
+            min_version: Some(EngineVersion {
+                major: Some(0),
+                minor: Some(28),
+                patch: Some(0),
+            }),
+            format: Some(Format {
+                weights_encoding: Some(Encoding::Linear16 as i32),
+                network_format: Some(NetworkFormat {
+                    input: Some(InputFormat::InputClassical112Plane as i32),
+                    ..*network_format
+                }),
+            }),
+            weights: Some(weights),
+            ..Net::default()
+        })
+    }
+
+    /// A tiny SE net with WDL and moves left heads.
+    fn se_net() -> WeightFile {
+        let weights = Weights {
+            input: Some(conv(NUM_INPUT_PLANES, FILTERS, 3)),
+            residual: vec![Residual {
+                conv1: Some(conv(FILTERS, FILTERS, 3)),
+                conv2: Some(conv(FILTERS, FILTERS, 3)),
+                se: Some(SEunit {
+                    w1: Some(layer(2 * FILTERS)),
+                    b1: Some(layer(2)),
+                    w2: Some(layer(2 * FILTERS * 2)),
+                    b2: Some(layer(2 * FILTERS)),
+                }),
+            }],
+            policy: Some(conv(FILTERS, 2, 1)),
+            ip_pol_w: Some(layer(POLICY_SIZE * 64 * 2)),
+            ip_pol_b: Some(layer(POLICY_SIZE)),
+            value: Some(conv(FILTERS, 1, 1)),
+            ip1_val_w: Some(layer(8 * 64)),
+            ip1_val_b: Some(layer(8)),
+            ip2_val_w: Some(layer(3 * 8)),
+            ip2_val_b: Some(layer(3)),
+            moves_left: Some(conv(FILTERS, 1, 1)),
+            ip1_mov_w: Some(layer(4 * 64)),
+            ip1_mov_b: Some(layer(4)),
+            ip2_mov_w: Some(layer(4)),
+            ip2_mov_b: Some(layer(1)),
+            ..Weights::default()
+        };
+
+        net(
+            weights,
+            &NetworkFormat {
+                network: Some(NetworkStructure::NetworkSeWithHeadformat as i32),
+                policy: Some(PolicyFormat::PolicyClassical as i32),
+                value: Some(ValueFormat::ValueWdl as i32),
+                moves_left: Some(MovesLeftFormat::MovesLeftV1 as i32),
+                ..NetworkFormat::default()
+            },
+        )
+    }
+
+    /// A tiny classical net with a convolution policy and a scalar value head.
+    fn classical_net() -> WeightFile {
+        let weights = Weights {
+            input: Some(conv(NUM_INPUT_PLANES, FILTERS, 3)),
+            residual: vec![Residual {
+                conv1: Some(conv(FILTERS, FILTERS, 3)),
+                conv2: Some(conv(FILTERS, FILTERS, 3)),
+                se: None,
+            }],
+            policy1: Some(conv(FILTERS, FILTERS, 3)),
+            policy: Some(conv(FILTERS, 80, 3)),
+            value: Some(conv(FILTERS, 1, 1)),
+            ip1_val_w: Some(layer(8 * 64)),
+            ip1_val_b: Some(layer(8)),
+            ip2_val_w: Some(layer(8)),
+            ip2_val_b: Some(layer(1)),
+            ..Weights::default()
+        };
+
+        net(
+            weights,
+            &NetworkFormat {
+                network: Some(NetworkStructure::NetworkClassicalWithHeadformat as i32),
+                policy: Some(PolicyFormat::PolicyConvolution as i32),
+                value: Some(ValueFormat::ValueClassical as i32),
+                moves_left: Some(MovesLeftFormat::MovesLeftNone as i32),
+                ..NetworkFormat::default()
+            },
+        )
+    }
+
+    const EMBEDDING: usize = 8;
+    const HEADS: u32 = 2;
+
+    fn encoder_layer(smolgen: Option<Smolgen>) -> EncoderLayer {
+        EncoderLayer {
+            mha: Some(Mha {
+                q_w: Some(layer(EMBEDDING * EMBEDDING)),
+                q_b: Some(layer(EMBEDDING)),
+                k_w: Some(layer(EMBEDDING * EMBEDDING)),
+                k_b: Some(layer(EMBEDDING)),
+                v_w: Some(layer(EMBEDDING * EMBEDDING)),
+                v_b: Some(layer(EMBEDDING)),
+                dense_w: Some(layer(EMBEDDING * EMBEDDING)),
+                dense_b: Some(layer(EMBEDDING)),
+                smolgen,
+            }),
+            ln1_gammas: Some(layer(EMBEDDING)),
+            ln1_betas: Some(layer(EMBEDDING)),
+            ffn: Some(Ffn {
+                dense1_w: Some(layer(2 * EMBEDDING * EMBEDDING)),
+                dense1_b: Some(layer(2 * EMBEDDING)),
+                dense2_w: Some(layer(EMBEDDING * 2 * EMBEDDING)),
+                dense2_b: Some(layer(EMBEDDING)),
+            }),
+            ln2_gammas: Some(layer(EMBEDDING)),
+            ln2_betas: Some(layer(EMBEDDING)),
+        }
+    }
+
+    /// A tiny attention body net with smolgen, input gating and an attention policy.
+    fn attention_net() -> WeightFile {
+        // 2 compressed channels per square, 4 hidden and 2 generated values per head.
+        let smolgen = Smolgen {
+            compress: Some(layer(EMBEDDING * 2)),
+            dense1_w: Some(layer(64 * 2 * 4)),
+            dense1_b: Some(layer(4)),
+            ln1_gammas: Some(layer(4)),
+            ln1_betas: Some(layer(4)),
+            dense2_w: Some(layer(4 * 2 * 2)),
+            dense2_b: Some(layer(2 * 2)),
+            ln2_gammas: Some(layer(2 * 2)),
+            ln2_betas: Some(layer(2 * 2)),
+        };
+
+        // The attention policy has 4 values per key.
+        let weights = Weights {
+            ip_emb_w: Some(layer(NUM_INPUT_PLANES * EMBEDDING)),
+            ip_emb_b: Some(layer(EMBEDDING)),
+            ip_mult_gate: Some(layer(64 * EMBEDDING)),
+            ip_add_gate: Some(layer(64 * EMBEDDING)),
+            encoder: vec![encoder_layer(Some(smolgen)), encoder_layer(None)],
+            headcount: Some(HEADS),
+            smolgen_w: Some(layer(2 * 64 * 64)),
+            smolgen_b: Some(layer(64 * 64)),
+            ip_pol_w: Some(layer(EMBEDDING * EMBEDDING)),
+            ip_pol_b: Some(layer(EMBEDDING)),
+            ip2_pol_w: Some(layer(4 * EMBEDDING)),
+            ip2_pol_b: Some(layer(4)),
+            ip3_pol_w: Some(layer(4 * EMBEDDING)),
+            ip3_pol_b: Some(layer(4)),
+            ip4_pol_w: Some(layer(4 * 4)),
+            ip_val_w: Some(layer(2 * EMBEDDING)),
+            ip_val_b: Some(layer(2)),
+            ip1_val_w: Some(layer(8 * 64 * 2)),
+            ip1_val_b: Some(layer(8)),
+            ip2_val_w: Some(layer(3 * 8)),
+            ip2_val_b: Some(layer(3)),
+            ip_mov_w: Some(layer(EMBEDDING)),
+            ip_mov_b: Some(layer(1)),
+            ip1_mov_w: Some(layer(4 * 64)),
+            ip1_mov_b: Some(layer(4)),
+            ip2_mov_w: Some(layer(4)),
+            ip2_mov_b: Some(layer(1)),
+            ..Weights::default()
+        };
+
+        net(
+            weights,
+            &NetworkFormat {
+                network: Some(NetworkStructure::NetworkAttentionbodyWithHeadformat as i32),
+                policy: Some(PolicyFormat::PolicyAttention as i32),
+                value: Some(ValueFormat::ValueWdl as i32),
+                moves_left: Some(MovesLeftFormat::MovesLeftV1 as i32),
+                default_activation: Some(DefaultActivation::Mish as i32),
+                ..NetworkFormat::default()
+            },
+        )
+    }
+
+    fn dims(info: &pblczero::ValueInfoProto) -> Vec<Option<i64>> {
+        let tensor = info.r#type.as_ref().unwrap().tensor_type.as_ref().unwrap();
+        tensor
+            .shape
+            .as_ref()
+            .unwrap()
+            .dim
+            .iter()
+            .map(|d| d.dim_value)
+            .collect()
+    }
+
+    /// Checks the inputs and outputs of `model` and that every node only uses tensors defined
+    /// before it.
+    fn assert_well_formed(model: &ModelProto, outputs: &[(&str, usize)]) {
+        let graph = model.graph.as_ref().unwrap();
+
+        assert_eq!(graph.input.len(), 1);
+        assert_eq!(graph.input[0].name(), INPUT_PLANES_NAME);
+        assert_eq!(
+            dims(&graph.input[0]),
+            [None, Some(dim(NUM_INPUT_PLANES)), Some(8), Some(8)]
+        );
+        let actual: Vec<_> = graph
+            .output
+            .iter()
+            .map(|output| (output.name(), dims(output)))
+            .collect();
+        let expected: Vec<_> = outputs
+            .iter()
+            .map(|&(name, size)| (name, vec![None, Some(dim(size))]))
+            .collect();
+        assert_eq!(actual, expected);
+
+        // Initializers hold as many values as their shape says.
+        let mut defined: HashSet<&str> = HashSet::from([INPUT_PLANES_NAME]);
+        for tensor in &graph.initializer {
+            let values: i64 = tensor.dims.iter().product();
+            let size = if tensor.data_type() == DataType::Int64 {
+                8
+            } else {
+                4
+            };
+            assert_eq!(
+                tensor.raw_data().len(),
+                usize::try_from(values).unwrap() * size,
+                "{}",
+                tensor.name()
+            );
+            assert!(defined.insert(tensor.name()), "{}", tensor.name());
+        }
+
+        // Nodes are in topological order and only use what is defined before them.
+        for node in &graph.node {
+            for input in &node.input {
+                assert!(
+                    defined.contains(input.as_str()),
+                    "{} uses {input}",
+                    node.name()
+                );
+            }
+            for output in &node.output {
+                assert!(defined.insert(output), "{output} defined twice");
+            }
+        }
+        for output in &graph.output {
+            assert!(defined.contains(output.name()));
+        }
+    }
+
+    fn initializer_dims(model: &ModelProto, name: &str) -> Vec<i64> {
+        let graph = model.graph.as_ref().unwrap();
+        let tensor = graph.initializer.iter().find(|t| t.name() == name);
+        tensor.unwrap().dims.clone()
+    }
+
+    fn has_op(model: &ModelProto, op_type: &str) -> bool {
+        let graph = model.graph.as_ref().unwrap();
+        graph.node.iter().any(|node| node.op_type() == op_type)
+    }
+
+    #[test]
+    fn se_net_converts_to_a_well_formed_graph() {
+        let model = convert_to_onnx(&se_net()).unwrap();
+        assert_well_formed(
+            &model,
+            &[
+                (OUTPUT_POLICY_NAME, POLICY_SIZE),
+                (OUTPUT_WDL_NAME, 3),
+                (OUTPUT_MLH_NAME, 1),
+            ],
+        );
+        assert_eq!(
+            initializer_dims(&model, "/inputconv/w"),
+            [dim(FILTERS), dim(NUM_INPUT_PLANES), 3, 3]
+        );
+        assert!(has_op(&model, "GlobalAveragePool"));
+    }
+
+    #[test]
+    fn classical_net_converts_to_a_well_formed_graph() {
+        let model = convert_to_onnx(&classical_net()).unwrap();
+        assert_well_formed(
+            &model,
+            &[(OUTPUT_POLICY_NAME, POLICY_SIZE), (OUTPUT_VALUE_NAME, 1)],
+        );
+        assert_eq!(initializer_dims(&model, "/policy/map"), [dim(POLICY_SIZE)]);
+        assert!(has_op(&model, "Tanh"));
+        assert!(!has_op(&model, "GlobalAveragePool"));
+    }
+
+    #[test]
+    fn attention_body_net_converts_to_a_well_formed_graph() {
+        let model = convert_to_onnx(&attention_net()).unwrap();
+        assert_well_formed(
+            &model,
+            &[
+                (OUTPUT_POLICY_NAME, POLICY_SIZE),
+                (OUTPUT_WDL_NAME, 3),
+                (OUTPUT_MLH_NAME, 1),
+            ],
+        );
+        assert_eq!(
+            initializer_dims(&model, "/attn_body/embedding/w"),
+            [dim(EMBEDDING), dim(NUM_INPUT_PLANES)]
+        );
+        assert_eq!(
+            initializer_dims(&model, "/encoder0/mha/smolgen/global/w"),
+            [64 * 64, 2]
+        );
+        assert_eq!(
+            initializer_dims(&model, "/attn_body/gate/mult/w"),
+            [64, dim(EMBEDDING)]
+        );
+        assert!(has_op(&model, "LayerNormalization"));
+        assert!(!has_op(&model, "Conv"));
+    }
+
+    #[test]
+    fn embedded_model_round_trips_through_a_weight_file() {
+        let weight_file = se_net();
+        let model = convert_to_onnx(&weight_file).unwrap();
+        let path = env::temp_dir().join(format!("fatduck-embed-{}.pb.gz", process::id()));
+        embed_onnx(&weight_file, &model)
+            .write_to_file(&path)
+            .unwrap();
+        let read = WeightFile::from_filepath(path.clone());
+        fs::remove_file(&path).unwrap();
+        let read = read.unwrap();
+
+        assert!(read.weights().is_none());
+        assert_eq!(
+            read.network_format().network(),
+            NetworkStructure::NetworkOnnx
+        );
+        let onnx = read.net().onnx_model.as_ref().unwrap();
+        assert_eq!(onnx.input_planes(), INPUT_PLANES_NAME);
+        assert_eq!(onnx.output_policy(), OUTPUT_POLICY_NAME);
+        assert_eq!(onnx.output_wdl(), OUTPUT_WDL_NAME);
+        assert_eq!(onnx.output_mlh(), OUTPUT_MLH_NAME);
+        assert!(onnx.output_value.is_none());
+        assert_eq!(ModelProto::decode(onnx.model()).unwrap(), model);
+    }
+
+    #[test]
+    fn malformed_layers_are_rejected() {
+        let mut net = se_net().net().clone();
+        net.weights.as_mut().unwrap().residual[0].conv1 = Some(conv(FILTERS + 1, FILTERS, 2));
+        assert!(matches!(
+            convert_to_onnx(&WeightFile::from_net(net.clone())),
+            Err(OnnxConvertError::InvalidWeights(WeightFileError::InvalidLayer { layer, .. }))
+                if layer == "residual[0].conv1.weights"
+        ));
+
+        net = se_net().net().clone();
+        net.weights.as_mut().unwrap().ip2_val_w = None;
+        assert!(matches!(
+            convert_to_onnx(&WeightFile::from_net(net.clone())),
+            Err(OnnxConvertError::InvalidWeights(WeightFileError::MissingLayer(layer)))
+                if layer == "ip2_val_w"
+        ));
+
+        net.weights = None;
+        assert!(matches!(
+            convert_to_onnx(&WeightFile::from_net(net)),
+            Err(OnnxConvertError::NoWeights)
+        ));
+    }
+
+    #[test]
+    fn extra_embedding_inputs_are_unsupported() {
+        let mut net = attention_net().net().clone();
+        net.weights.as_mut().unwrap().ip_emb_w = Some(layer(176 * EMBEDDING));
+        assert!(matches!(
+            convert_to_onnx(&WeightFile::from_net(net)),
+            Err(OnnxConvertError::Unsupported(_))
+        ));
+    }
+}
@@ -1,14 +1,14 @@
 use crate::{
-    chess::GameState,
+    chess::{GameRules, GameState},
     neural::network::{
         InputStack, AUX_PLANE_BASE, MOVE_HISTORY, NUM_INPUT_PLANES, PLANES_PER_BOARD,
     },
-    pblczero,
+    pblczero::network_format::InputFormat,
 };
 use shakmaty::{
-    Bitboard, Board, Castles, CastlingMode, CastlingSide, Chess, Color, EnPassantMode, Position,
+    Bitboard, Board, CastlingSide, Chess, Color, EnPassantMode, File, Position, Rank, Square,
 };
-use std::{cmp, hint::unreachable_unchecked};
+use std::cmp::{self, Ordering};
 
 #[derive(PartialEq, Eq, Clone, Copy)]
 pub enum FillEmptyHistory {
@@ -23,10 +23,115 @@ pub enum BoardTransform {
     Flip = 1,
     // Vertical mirror
     Mirror = 2,
-    // Diagonal transpose a1 to h8
+    // Transpose along the a8-h1 anti-diagonal
     Transpose = 4,
 }
 
+/// Combination of `BoardTransform`s mapping a position to its canonical form. They are applied in
+/// the order flip, mirror, transpose.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct BoardTransforms(u8);
+
+impl BoardTransforms {
+    pub const NONE: Self = Self(0);
+
+    pub const fn contains(self, transform: BoardTransform) -> bool {
+        self.0 & transform as u8 != 0
+    }
+
+    pub fn is_none(self) -> bool {
+        self == Self::NONE
+    }
+
+    const fn with(self, transform: BoardTransform) -> Self {
+        Self(self.0 | transform as u8)
+    }
+
+    pub const fn bitboard(self, mut bitboard: Bitboard) -> Bitboard {
+        if self.contains(BoardTransform::Flip) {
+            bitboard = bitboard.flip_horizontal();
+        }
+        if self.contains(BoardTransform::Mirror) {
+            bitboard = bitboard.flip_vertical();
+        }
+        if self.contains(BoardTransform::Transpose) {
+            bitboard = bitboard.flip_anti_diagonal();
+        }
+        bitboard
+    }
+
+    pub fn square(self, mut square: Square) -> Square {
+        if self.contains(BoardTransform::Flip) {
+            square = square.flip_horizontal();
+        }
+        if self.contains(BoardTransform::Mirror) {
+            square = square.flip_vertical();
+        }
+        if self.contains(BoardTransform::Transpose) {
+            square = square.flip_anti_diagonal();
+        }
+        square
+    }
+
+    /// Transforms of lc0's canonical input formats for `board`, seen from `us`. Our king ends up
+    /// on the e-h files and, without pawns, on ranks 1-4 below the a8-h1 anti-diagonal. Castling
+    /// rights aren't symmetric, so positions with any are never transformed.
+    pub fn canonical(board: &Board, us: Color, can_castle: bool) -> Self {
+        let mut transforms = Self::NONE;
+        if can_castle {
+            return transforms;
+        }
+        let Some(mut king) = board.king_of(us) else {
+            return transforms;
+        };
+
+        if king.file() <= File::D {
+            transforms = transforms.with(BoardTransform::Flip);
+            king = king.flip_horizontal();
+        }
+
+        // Pawns only allow the horizontal flip.
+        if board.pawns().any() {
+            return transforms;
+        }
+
+        if king.rank() >= Rank::Fifth {
+            transforms = transforms.with(BoardTransform::Mirror);
+            king = king.flip_vertical();
+        }
+
+        // Our king is on e1-h4 now, squares on the anti-diagonal are decided by the other pieces.
+        match (usize::from(king.file()) + usize::from(king.rank())).cmp(&7) {
+            Ordering::Greater => transforms.with(BoardTransform::Transpose),
+            Ordering::Equal if transforms.prefers_transpose(board, us) => {
+                transforms.with(BoardTransform::Transpose)
+            }
+            _ => transforms,
+        }
+    }
+
+    /// Whether transposing gives the smaller bitboards, comparing all pieces, ours, then each
+    /// piece type in lc0's order.
+    fn prefers_transpose(self, board: &Board, us: Color) -> bool {
+        [
+            board.occupied(),
+            board.by_color(us),
+            board.kings(),
+            board.queens(),
+            board.rooks(),
+            board.knights(),
+            board.bishops(),
+        ]
+        .into_iter()
+        .map(|bitboard| {
+            let value = self.bitboard(bitboard);
+            u64::from(value).cmp(&u64::from(value.flip_anti_diagonal()))
+        })
+        .find(|ordering| ordering.is_ne())
+            == Some(Ordering::Greater)
+    }
+}
+
 struct InputStackAugmenter;
 impl InputStackAugmenter {
     pub fn classical_112_castling(stack: &mut InputStack<NUM_INPUT_PLANES>, position: &Chess) {
@@ -52,18 +157,77 @@ impl InputStackAugmenter {
         }
     }
 
+    /// Castling rights as the squares of the rooks that can still castle, which unlike the
+    /// classical planes also encodes Chess960 castling. The board is seen from the side to move,
+    /// so our rooks are on the first rank and theirs on the eighth.
+    pub fn castling_rook_planes(stack: &mut InputStack<NUM_INPUT_PLANES>, position: &Chess) {
+        let us = position.turn();
+        let castles = position.castles();
+
+        for (plane, side) in [CastlingSide::QueenSide, CastlingSide::KingSide]
+            .into_iter()
+            .enumerate()
+        {
+            let mut rooks = Bitboard::EMPTY;
+            for color in [us, !us] {
+                if let Some(rook) = castles.rook(color, side) {
+                    rooks.add(match us {
+                        Color::White => rook,
+                        Color::Black => rook.flip_vertical(),
+                    });
+                }
+            }
+            *stack.planes_mut()[AUX_PLANE_BASE + plane].mask_mut() = rooks.into();
+        }
+    }
+
     pub fn help_find_edges(stack: &mut InputStack<NUM_INPUT_PLANES>) {
         stack.planes_mut()[AUX_PLANE_BASE + 7].set_mask_max();
     }
 
-    pub fn two_fold_checked(stack: &mut InputStack<NUM_INPUT_PLANES>, state: &GameState) {
+    /// The last plane of every history board marks positions that occurred before.
+    pub fn repetitions(
+        stack: &mut InputStack<NUM_INPUT_PLANES>,
+        state: &GameState,
+        base_offset: usize,
+    ) {
         if state.repetition_count() >= 1 {
-            stack.planes_mut()[AUX_PLANE_BASE + 12].set_mask_max();
+            stack.planes_mut()[base_offset + 12].set_mask_max();
+        }
+    }
+
+    /// Hectoplies formats scale the counter to the 0-1 range.
+    pub fn fifty_move_rule(
+        stack: &mut InputStack<NUM_INPUT_PLANES>,
+        state: &GameState,
+        hectoplies: bool,
+    ) {
+        // The counter is far below 2^24, exact as f32.
+        #[allow(clippy::cast_precision_loss)]
+        let halfmoves = state.position().halfmoves() as f32;
+        stack.planes_mut()[AUX_PLANE_BASE + 5].fill(if hectoplies {
+            halfmoves / 100.0
+        } else {
+            halfmoves
+        });
+    }
+
+    /// Canonical formats replace the side to move plane with the en passant file, marked on the
+    /// eighth rank as seen from the side to move.
+    pub fn en_passant(stack: &mut InputStack<NUM_INPUT_PLANES>, position: &Chess) {
+        if let Some(square) = position.ep_square(EnPassantMode::Legal) {
+            *stack.planes_mut()[AUX_PLANE_BASE + 4].mask_mut() = u64::from(Bitboard::from_square(
+                Square::from_coords(square.file(), Rank::Eighth),
+            ));
         }
     }
 
-    pub fn fifty_move_rule(stack: &mut InputStack<NUM_INPUT_PLANES>, state: &GameState) {
-        stack.planes_mut()[AUX_PLANE_BASE + 5].fill(state.position().halfmoves() as f32);
+    /// Armageddon formats mark positions with black to move, who wins draws. Standard games leave
+    /// the plane empty.
+    pub fn armageddon(stack: &mut InputStack<NUM_INPUT_PLANES>, state: &GameState) {
+        if state.rules() == GameRules::Armageddon && state.position().turn().is_black() {
+            stack.planes_mut()[AUX_PLANE_BASE + 6].set_mask_max();
+        }
     }
 
     pub fn piece_planes(
@@ -91,10 +255,7 @@ impl InputStackAugmenter {
         *stack.planes_mut()[offset + 11].mask_mut() = their_pieces.intersect(board.kings()).into();
     }
 
-    pub fn transform_masks(
-        stack: &mut InputStack<NUM_INPUT_PLANES>,
-        transform: Option<BoardTransform>,
-    ) {
+    pub fn transform_masks(stack: &mut InputStack<NUM_INPUT_PLANES>, transform: BoardTransforms) {
         if transform.is_none() {
             return;
         }
@@ -104,135 +265,382 @@ impl InputStackAugmenter {
                 continue;
             }
 
-            let mut mask_bboard = Bitboard::from(plane.mask());
-
-            match transform {
-                Some(BoardTransform::Flip) => mask_bboard = mask_bboard.flip_horizontal(),
-                Some(BoardTransform::Mirror) => mask_bboard = mask_bboard.flip_vertical(),
-                Some(BoardTransform::Transpose) => mask_bboard = mask_bboard.flip_diagonal(),
-                // SAFETY: None is early returned above.
-                None => unsafe { unreachable_unchecked() },
-            }
-
-            *plane.mask_mut() = mask_bboard.into();
+            *plane.mask_mut() = transform.bitboard(Bitboard::from(plane.mask())).into();
         }
     }
 }
 
 impl<const N: usize> InputStack<N> {
+    /// Encodes the last state of `history` and up to `history_planes` of its predecessors. With
+    /// a canonical input format the planes are transformed, and the transforms are written to
+    /// `transform_out` so the policy can be mapped back.
+    // Kept in one piece to follow lc0's EncodePositionForNN.
+    #[allow(clippy::too_many_lines)]
     pub fn encode_position_for_nn(
-        input_format: pblczero::network_format::InputFormat,
+        input_format: InputFormat,
         history: &[GameState],
         history_planes: usize,
         fill_setting: FillEmptyHistory,
-        transform_out: Option<&mut BoardTransform>,
+        transform_out: Option<&mut BoardTransforms>,
     ) -> InputStack<NUM_INPUT_PLANES> {
         let mut result = InputStack::new();
         let current_state = history.last().unwrap();
+        let current_position = current_state.position();
+
+        let canonical = is_canonical(input_format);
+        let hectoplies = canonical && input_format != InputFormat::Input112WithCanonicalization;
+        let armageddon = matches!(
+            input_format,
+            InputFormat::Input112WithCanonicalizationHectopliesArmageddon
+                | InputFormat::Input112WithCanonicalizationV2Armageddon
+        );
+        // V2 formats only encode the positions that are repeated, apart from the current one.
+        let only_repetitions = matches!(
+            input_format,
+            InputFormat::Input112WithCanonicalizationV2
+                | InputFormat::Input112WithCanonicalizationV2Armageddon
+        );
 
         match input_format {
-            pblczero::network_format::InputFormat::InputClassical112Plane => {
-                InputStackAugmenter::classical_112_castling(&mut result, current_state.position());
+            InputFormat::InputClassical112Plane => {
+                InputStackAugmenter::classical_112_castling(&mut result, current_position);
+            }
+            _ if canonical || input_format == InputFormat::Input112WithCastlingPlane => {
+                InputStackAugmenter::castling_rook_planes(&mut result, current_position);
             }
             _ => panic!("Unsupported input format: {input_format:?}"),
-        };
+        }
 
-        if current_state.position().turn().is_black() {
+        if canonical {
+            InputStackAugmenter::en_passant(&mut result, current_position);
+        } else if current_position.turn().is_black() {
             result.planes_mut()[AUX_PLANE_BASE + 4].set_mask_max();
         }
 
-        InputStackAugmenter::fifty_move_rule(&mut result, current_state);
+        InputStackAugmenter::fifty_move_rule(&mut result, current_state, hectoplies);
+        if armageddon {
+            InputStackAugmenter::armageddon(&mut result, current_state);
+        }
         InputStackAugmenter::help_find_edges(&mut result);
 
-        let mut flip = false;
-        let mut history_idx = (history.len() as i32) - 1;
-
-        for i in 0..cmp::min(history_planes, MOVE_HISTORY) {
-            // SAFETY: current_state will already panic on unwrap if history is empty
-            let state = unsafe { history.get_unchecked(history.len().saturating_sub(1)) };
-            if flip {
-                // mutably rotates
-                state.position().board().rotate_180();
+        // Boards are encoded from the perspective of the side to move in the current position.
+        let (us, them) = match current_position.turn() {
+            Color::White => (Color::White, Color::Black),
+            Color::Black => (Color::Black, Color::White),
+        };
+        let perspective = |square: Square| match us {
+            Color::White => square,
+            Color::Black => square.flip_vertical(),
+        };
+        let perspective_board = |position: &Chess| {
+            let mut board = position.board().clone();
+            if us == Color::Black {
+                board.flip_vertical();
             }
+            board
+        };
 
-            let mut board = state.position().board();
+        let mut history_idx = isize::try_from(history.len()).unwrap_or(isize::MAX) - 1;
+        let mut i = 0;
 
-            // Castling changes can't be repeated, so we can stop early.
-            if state.position().castles().castling_rights()
-                == Castles::empty(CastlingMode::Standard).castling_rights()
-            {
-                break;
-            }
+        while i < cmp::min(history_planes, MOVE_HISTORY) {
+            // Missing history is filled with the oldest position.
+            let state = &history[usize::try_from(history_idx).unwrap_or(0)];
 
-            // En passant's cant be repeated, but we do need to always send the current position.
-            if history_idx != (history.len() as i32 - 1)
-                && state.position().legal_ep_square().is_some()
-            {
+            if fill_setting == FillEmptyHistory::No && history_idx < 0 {
                 break;
             }
 
-            // If en passant is possible we know the previous move.
-            if fill_setting == FillEmptyHistory::No
-                && (history_idx < -1
-                    || history_idx == -1 && current_state.position().legal_ep_square().is_none())
+            if history_idx < 0
+                && fill_setting == FillEmptyHistory::FenOnly
+                && *state.position() == Chess::new()
             {
                 break;
             }
 
-            // Board may be flipped so compare with the original board
-            if history_idx < 0
-                && fill_setting == FillEmptyHistory::FenOnly
-                && *state.position() == Chess::new()
+            // Canonical formats stop where the transforms could become invalid: castling rights
+            // can't come back.
+            if canonical
+                && state.position().castles().castling_rights()
+                    != current_position.castles().castling_rights()
             {
                 break;
             }
 
-            let (us, them) = match state.position().turn() {
-                Color::White => (Color::White, Color::Black),
-                Color::Black => (Color::Black, Color::White),
-            };
+            if only_repetitions && i > 0 && state.repetition_count() == 0 {
+                // Nothing before a zeroing move can repeat, and filled history never does.
+                if state.position().halfmoves() == 0 || history_idx <= 0 {
+                    break;
+                }
+                history_idx -= 1;
+                continue;
+            }
 
+            let board = perspective_board(state.position());
             let base_offset = i * PLANES_PER_BOARD;
 
             InputStackAugmenter::piece_planes(&mut result, &board, (us, them), base_offset);
-            InputStackAugmenter::two_fold_checked(&mut result, state);
+            InputStackAugmenter::repetitions(&mut result, state, base_offset);
 
+            // If en passant is possible we know the previous move, so the filled history can undo
+            // the double pawn push.
             if let (Some(ep_square), true) = (
                 state.position().ep_square(EnPassantMode::Legal),
                 history_idx < 0,
             ) {
-                let idx = u64::from(ep_square).trailing_zeros();
-
-                if idx < 8 {
-                    *result.planes_mut()[base_offset].mask_mut() +=
-                        0x100_u64.wrapping_sub(0x1_0000_0000_u64) << idx;
-                } else {
-                    *result.planes_mut()[base_offset + 6].mask_mut() +=
-                        (0x1_0000_0000_0000_u64 - 0x1_0000_0000_u64) << (idx.wrapping_sub(56));
+                let pusher = !state.position().turn();
+                let (now, before) = match pusher {
+                    Color::White => (ep_square.offset(8), ep_square.offset(-8)),
+                    Color::Black => (ep_square.offset(-8), ep_square.offset(8)),
+                };
+
+                if let (Some(now), Some(before)) = (now, before) {
+                    let plane = if pusher == us {
+                        base_offset
+                    } else {
+                        base_offset + 6
+                    };
+                    *result.planes_mut()[plane].mask_mut() ^=
+                        u64::from(Bitboard::from_square(perspective(now)))
+                            | u64::from(Bitboard::from_square(perspective(before)));
                 }
             }
 
-            if history_idx > 0 {
-                flip = !flip;
-            }
+            history_idx -= 1;
+            i += 1;
 
-            if state.position().halfmoves() == 0 {
+            // Positions before a zeroing move don't matter for canonical formats.
+            if canonical && state.position().halfmoves() == 0 {
                 break;
             }
-
-            history_idx -= 1;
         }
 
-        let mut transform = None;
+        // Only canonical input formats transform the board.
+        let transform = if canonical {
+            BoardTransforms::canonical(
+                &perspective_board(current_position),
+                us,
+                !current_position.castles().is_empty(),
+            )
+        } else {
+            BoardTransforms::NONE
+        };
 
-        if transform.is_some() {
-            InputStackAugmenter::transform_masks(&mut result, transform);
-        }
+        InputStackAugmenter::transform_masks(&mut result, transform);
 
         if let Some(t_out) = transform_out {
-            *t_out = transform.unwrap();
+            *t_out = transform;
         }
 
         result
     }
 }
+
+/// Formats whose positions are canonicalized with `BoardTransforms::canonical`.
+const fn is_canonical(input_format: InputFormat) -> bool {
+    matches!(
+        input_format,
+        InputFormat::Input112WithCanonicalization
+            | InputFormat::Input112WithCanonicalizationHectoplies
+            | InputFormat::Input112WithCanonicalizationHectopliesArmageddon
+            | InputFormat::Input112WithCanonicalizationV2
+            | InputFormat::Input112WithCanonicalizationV2Armageddon
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{chess::PositionHistory, neural::network::InputPlane};
+    use shakmaty::uci::Uci;
+
+    fn canonical(fen: &str) -> BoardTransforms {
+        let state = GameState::from_fen(fen).unwrap();
+        let position = state.position();
+        // Seen from the side to move, as the encoder does.
+        let mut board = position.board().clone();
+        if position.turn().is_black() {
+            board.flip_vertical();
+        }
+        BoardTransforms::canonical(&board, position.turn(), !position.castles().is_empty())
+    }
+
+    fn transforms(transforms: &[BoardTransform]) -> BoardTransforms {
+        transforms
+            .iter()
+            .fold(BoardTransforms::NONE, |all, t| all.with(*t))
+    }
+
+    fn encode(format: InputFormat, history: &[GameState]) -> InputStack<NUM_INPUT_PLANES> {
+        InputStack::<NUM_INPUT_PLANES>::encode_position_for_nn(
+            format,
+            history,
+            MOVE_HISTORY,
+            FillEmptyHistory::FenOnly,
+            None,
+        )
+    }
+
+    fn history(moves: &[&str]) -> PositionHistory {
+        let mut history = PositionHistory::default();
+        for m in moves {
+            let m = Uci::from_ascii(m.as_bytes())
+                .unwrap()
+                .to_move(history.last().position())
+                .unwrap();
+            history.append(&m);
+        }
+        history
+    }
+
+    #[test]
+    fn square_transforms() {
+        let flip = transforms(&[BoardTransform::Flip]);
+        let mirror = transforms(&[BoardTransform::Mirror]);
+        let transpose = transforms(&[BoardTransform::Transpose]);
+        assert_eq!(flip.square(Square::B1), Square::G1);
+        assert_eq!(mirror.square(Square::B1), Square::B8);
+        // The a8-h1 anti-diagonal stays put.
+        assert_eq!(transpose.square(Square::H1), Square::H1);
+        assert_eq!(transpose.square(Square::E4), Square::E4);
+        assert_eq!(transpose.square(Square::A1), Square::H8);
+        assert_eq!(transpose.square(Square::G1), Square::H2);
+        // Flip before transpose.
+        let both = transforms(&[BoardTransform::Transpose, BoardTransform::Flip]);
+        assert_eq!(both.square(Square::B1), Square::H2);
+        assert_eq!(
+            both.bitboard(Bitboard::from_square(Square::B1)),
+            Bitboard::from_square(Square::H2)
+        );
+    }
+
+    /// Expectations follow lc0's `ChooseTransform`.
+    #[test]
+    fn canonical_transforms_match_lc0() {
+        use BoardTransform::{Flip, Mirror, Transpose};
+
+        // Castling rights rule out any transform.
+        assert!(canonical("r3k2r/8/8/8/8/8/8/R2K3R w KQkq - 0 1").is_none());
+        // King on the e-h files already.
+        assert!(canonical("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1").is_none());
+        // Pawns only allow the flip.
+        assert_eq!(
+            canonical("4k3/8/8/8/8/8/4P3/K7 w - - 0 1"),
+            transforms(&[Flip])
+        );
+        assert_eq!(
+            canonical("4k3/4p3/8/8/8/8/8/6K1 w - - 0 1"),
+            BoardTransforms::NONE
+        );
+        // Without pawns the king moves to ranks 1-4 below the anti-diagonal.
+        assert_eq!(
+            canonical("8/8/8/8/8/8/8/K6k w - - 0 1"),
+            transforms(&[Flip])
+        );
+        assert_eq!(
+            canonical("8/8/8/8/K7/8/8/7k w - - 0 1"),
+            transforms(&[Flip, Transpose])
+        );
+        assert_eq!(
+            canonical("8/8/4K3/8/8/8/8/k7 w - - 0 1"),
+            transforms(&[Mirror])
+        );
+        assert_eq!(
+            canonical("8/2K5/8/8/8/8/8/k7 w - - 0 1"),
+            transforms(&[Flip, Mirror])
+        );
+        // On the anti-diagonal the rest of the board decides, transposing if that gives the
+        // smaller bitboard.
+        assert_eq!(
+            canonical("7k/8/8/8/4K3/8/8/8 w - - 0 1"),
+            transforms(&[Transpose])
+        );
+        assert_eq!(
+            canonical("8/8/8/8/4K3/8/8/k7 w - - 0 1"),
+            BoardTransforms::NONE
+        );
+        // Black is seen from its own side, so its king on e8 is on e1.
+        assert!(canonical("4k3/8/8/8/8/8/8/K7 b - - 0 1").is_none());
+    }
+
+    #[test]
+    // The plane values are exact.
+    #[allow(clippy::float_cmp)]
+    fn hectoplies_scale_the_rule50_plane() {
+        let state = GameState::from_fen("4k3/8/8/8/8/8/8/R3K3 w - - 50 80").unwrap();
+        let rule50 = |format| {
+            encode(format, std::slice::from_ref(&state)).planes()[AUX_PLANE_BASE + 5].value()
+        };
+
+        assert_eq!(rule50(InputFormat::InputClassical112Plane), 50.0);
+        assert_eq!(rule50(InputFormat::Input112WithCanonicalization), 50.0);
+        assert_eq!(
+            rule50(InputFormat::Input112WithCanonicalizationHectoplies),
+            0.5
+        );
+        assert_eq!(rule50(InputFormat::Input112WithCanonicalizationV2), 0.5);
+    }
+
+    #[test]
+    fn canonical_formats_encode_en_passant_instead_of_the_side_to_move() {
+        let history = history(&["e2e4", "a7a6", "e4e5", "d7d5"]);
+        let classical = encode(InputFormat::InputClassical112Plane, history.states());
+        assert_eq!(classical.planes()[AUX_PLANE_BASE + 4].mask(), 0);
+
+        let canonical = encode(
+            InputFormat::Input112WithCanonicalizationHectoplies,
+            history.states(),
+        );
+        assert_eq!(
+            canonical.planes()[AUX_PLANE_BASE + 4].mask(),
+            u64::from(Bitboard::from_square(Square::D8))
+        );
+
+        let black = encode(InputFormat::InputClassical112Plane, &history.states()[..4]);
+        assert_eq!(black.planes()[AUX_PLANE_BASE + 4].mask(), u64::MAX);
+    }
+
+    #[test]
+    fn v2_formats_only_keep_repeated_positions() {
+        // The start position after the knights return, then once more after Nf3.
+        let history = history(&["g1f3", "g8f6", "f3g1", "f6g8", "g1f3"]);
+        let board = |stack: &InputStack<NUM_INPUT_PLANES>, i: usize| {
+            stack.planes()[i * PLANES_PER_BOARD..(i + 1) * PLANES_PER_BOARD]
+                .iter()
+                .map(InputPlane::mask)
+                .collect::<Vec<_>>()
+        };
+
+        let full = encode(InputFormat::Input112WithCanonicalization, history.states());
+        let v2 = encode(
+            InputFormat::Input112WithCanonicalizationV2,
+            history.states(),
+        );
+
+        assert_eq!(board(&v2, 0), board(&full, 0));
+        // The repeated start position, a repetition itself.
+        assert_eq!(board(&v2, 1), board(&full, 1));
+        assert_eq!(v2.planes()[PLANES_PER_BOARD + 12].mask(), u64::MAX);
+        // Positions that never repeated are left out, and the first start position is behind a
+        // zeroing move.
+        assert!(board(&v2, 2).iter().all(|&mask| mask == 0));
+        assert!(board(&full, 2).iter().any(|&mask| mask != 0));
+    }
+
+    #[test]
+    fn armageddon_plane_marks_black_to_move() {
+        let format = InputFormat::Input112WithCanonicalizationHectopliesArmageddon;
+        let white = GameState::new().with_rules(GameRules::Armageddon);
+        let black =
+            GameState::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1")
+                .unwrap()
+                .with_rules(GameRules::Armageddon);
+        let plane = |state: &GameState| {
+            encode(format, std::slice::from_ref(state)).planes()[AUX_PLANE_BASE + 6].mask()
+        };
+
+        assert_eq!(plane(&white), 0);
+        assert_eq!(plane(&black), u64::MAX);
+        assert_eq!(plane(&black.with_rules(GameRules::Standard)), 0);
+    }
+}
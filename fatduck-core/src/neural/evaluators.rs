@@ -0,0 +1,559 @@
+use crate::{
+    chess::GameState,
+    neural::{
+        backends::OnnxNetwork,
+        encoder::{BoardTransforms, FillEmptyHistory},
+        network::{
+            InputStack, Network, NetworkComputation, NetworkError, MOVE_HISTORY, NUM_INPUT_PLANES,
+        },
+        policy, uniform_policy, Evaluation, NNEvaluator,
+    },
+    utils::Rng,
+};
+use shakmaty::{
+    zobrist::{Zobrist64, ZobristHash},
+    Board, Color, EnPassantMode, Move, Position, Role, Square,
+};
+use std::{path::Path, sync::Arc};
+
+/// Maps a centipawn score to Q, the inverse of the conversion used for UCI output.
+fn centipawns_to_q(cp: f32) -> f32 {
+    (cp / 90.0).atan() / 1.563_754_2
+}
+
+/// Handcrafted evaluator: material plus piece-square tables.
+#[derive(Default, Clone, Copy)]
+pub struct MaterialEvaluator;
+
+// Piece-square tables from white's point of view, rank 8 first.
+#[rustfmt::skip]
+const PAWN_PST: [i16; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    10, 10, 20, 30, 30, 20, 10, 10,
+     5,  5, 10, 25, 25, 10,  5,  5,
+     0,  0,  0, 20, 20,  0,  0,  0,
+     5, -5,-10,  0,  0,-10, -5,  5,
+     5, 10, 10,-20,-20, 10, 10,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+#[rustfmt::skip]
+const KNIGHT_PST: [i16; 64] = [
+    -50,-40,-30,-30,-30,-30,-40,-50,
+    -40,-20,  0,  0,  0,  0,-20,-40,
+    -30,  0, 10, 15, 15, 10,  0,-30,
+    -30,  5, 15, 20, 20, 15,  5,-30,
+    -30,  0, 15, 20, 20, 15,  0,-30,
+    -30,  5, 10, 15, 15, 10,  5,-30,
+    -40,-20,  0,  5,  5,  0,-20,-40,
+    -50,-40,-30,-30,-30,-30,-40,-50,
+];
+#[rustfmt::skip]
+const BISHOP_PST: [i16; 64] = [
+    -20,-10,-10,-10,-10,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  5,  5, 10, 10,  5,  5,-10,
+    -10,  0, 10, 10, 10, 10,  0,-10,
+    -10, 10, 10, 10, 10, 10, 10,-10,
+    -10,  5,  0,  0,  0,  0,  5,-10,
+    -20,-10,-10,-10,-10,-10,-10,-20,
+];
+#[rustfmt::skip]
+const ROOK_PST: [i16; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+     5, 10, 10, 10, 10, 10, 10,  5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+     0,  0,  0,  5,  5,  0,  0,  0,
+];
+#[rustfmt::skip]
+const QUEEN_PST: [i16; 64] = [
+    -20,-10,-10, -5, -5,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+     -5,  0,  5,  5,  5,  5,  0, -5,
+      0,  0,  5,  5,  5,  5,  0, -5,
+    -10,  5,  5,  5,  5,  5,  0,-10,
+    -10,  0,  5,  0,  0,  0,  0,-10,
+    -20,-10,-10, -5, -5,-10,-10,-20,
+];
+#[rustfmt::skip]
+const KING_PST: [i16; 64] = [
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -20,-30,-30,-40,-40,-30,-30,-20,
+    -10,-20,-20,-20,-20,-20,-20,-10,
+     20, 20,  0,  0,  0,  0, 20, 20,
+     20, 30, 10,  0,  0, 10, 30, 20,
+];
+
+impl MaterialEvaluator {
+    fn piece_value(role: Role, color: Color, square: Square) -> i32 {
+        let (material, pst) = match role {
+            Role::Pawn => (100, &PAWN_PST),
+            Role::Knight => (320, &KNIGHT_PST),
+            Role::Bishop => (330, &BISHOP_PST),
+            Role::Rook => (500, &ROOK_PST),
+            Role::Queen => (900, &QUEEN_PST),
+            Role::King => (0, &KING_PST),
+        };
+
+        // The tables start at rank 8, so white's squares have to be mirrored.
+        let square = match color {
+            Color::White => square.flip_vertical(),
+            Color::Black => square,
+        };
+
+        material + i32::from(pst[usize::from(square)])
+    }
+}
+
+impl NNEvaluator for MaterialEvaluator {
+    // Board and side to move
+    type Input = (Board, Color);
+
+    fn eval_by_info(&self, info: Self::Input) -> Result<f32, NetworkError> {
+        let (board, turn) = info;
+
+        let cp: i32 = board
+            .into_iter()
+            .map(|(square, piece)| {
+                let value = Self::piece_value(piece.role, piece.color, square);
+                if piece.color == turn {
+                    value
+                } else {
+                    -value
+                }
+            })
+            .sum();
+
+        // Material and tables add up to a few thousand centipawns, exact as f32.
+        #[allow(clippy::cast_precision_loss)]
+        Ok(centipawns_to_q(cp as f32))
+    }
+
+    fn eval_state(&self, state: &GameState, _moves: &[Move]) -> Result<f32, NetworkError> {
+        let position = state.position();
+        self.eval_by_info((position.board().clone(), position.turn()))
+    }
+}
+
+/// Evaluator returning pseudo-random values and priors. The values only depend on the position
+/// and the seed, so searches with the same seed are reproducible.
+#[derive(Default, Clone, Copy)]
+pub struct RandomEvaluator {
+    seed: u64,
+}
+
+impl RandomEvaluator {
+    pub const fn new(seed: u64) -> Self {
+        Self { seed }
+    }
+
+    // Generator for a position, its first draw is the value and the following ones the priors
+    const fn rng(self, hash: u64) -> Rng {
+        Rng::new(hash ^ self.seed)
+    }
+
+    fn hash(state: &GameState) -> u64 {
+        state
+            .position()
+            .zobrist_hash::<Zobrist64>(EnPassantMode::Legal)
+            .0
+    }
+}
+
+impl NNEvaluator for RandomEvaluator {
+    // Zobrist hash of the position
+    type Input = u64;
+
+    fn eval_by_info(&self, info: Self::Input) -> Result<f32, NetworkError> {
+        Ok(self.rng(info).next_f32().mul_add(2.0, -1.0))
+    }
+
+    fn eval_state(&self, state: &GameState, _moves: &[Move]) -> Result<f32, NetworkError> {
+        self.eval_by_info(Self::hash(state))
+    }
+
+    fn evaluate(&self, history: &[GameState], moves: &[Move]) -> Result<Evaluation, NetworkError> {
+        let state = history
+            .last()
+            .expect("history must contain the evaluated state");
+        let mut rng = self.rng(Self::hash(state));
+        let q = rng.next_f32().mul_add(2.0, -1.0);
+
+        let mut policy: Vec<f32> = moves.iter().map(|_| rng.next_f32()).collect();
+        let total: f32 = policy.iter().sum();
+        for p in &mut policy {
+            *p /= total.max(f32::EPSILON);
+        }
+
+        Ok(Evaluation {
+            q,
+            d: 0.0,
+            m: 0.0,
+            policy,
+        })
+    }
+}
+
+/// Adapter running any `Network` as an evaluator.
+pub struct NetworkEvaluator<N: Network> {
+    network: Arc<N>,
+}
+
+impl<N: Network> NetworkEvaluator<N> {
+    pub const fn new(network: Arc<N>) -> Self {
+        Self { network }
+    }
+
+    /// Input planes of the last state and the transforms the policy has to be read with.
+    fn encode(&self, history: &[GameState]) -> (InputStack<NUM_INPUT_PLANES>, BoardTransforms) {
+        let mut transform = BoardTransforms::NONE;
+        let planes = InputStack::<NUM_INPUT_PLANES>::encode_position_for_nn(
+            self.network.capabilities().input_format(),
+            history,
+            MOVE_HISTORY,
+            FillEmptyHistory::FenOnly,
+            Some(&mut transform),
+        );
+        (planes, transform)
+    }
+
+    fn read_evaluation(
+        &self,
+        computation: &dyn NetworkComputation,
+        sample: usize,
+        turn: Color,
+        transform: BoardTransforms,
+        moves: &[Move],
+    ) -> Evaluation {
+        // Softmax over the logits of the legal moves only.
+        let logits: Vec<f32> = moves
+            .iter()
+            .map(|m| {
+                policy::move_to_transformed_policy_index(m, turn, transform)
+                    .map_or(f32::NEG_INFINITY, |idx| computation.p_val(sample, idx))
+            })
+            .collect();
+        let max_logit = logits.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        // Without a single finite logit the softmax is NaN, nothing tells the moves apart.
+        let policy = if max_logit.is_finite() {
+            let mut policy: Vec<f32> = logits.iter().map(|l| (l - max_logit).exp()).collect();
+            let total: f32 = policy.iter().sum();
+            for p in &mut policy {
+                *p /= total.max(f32::EPSILON);
+            }
+            policy
+        } else {
+            uniform_policy(moves.len())
+        };
+
+        let m = if self.network.capabilities().has_mlh() {
+            computation.m_val(sample)
+        } else {
+            0.0
+        };
+
+        Evaluation {
+            q: computation.q_val(sample),
+            d: computation.d_val(sample),
+            m,
+            policy,
+        }
+    }
+}
+
+impl NetworkEvaluator<OnnxNetwork> {
+    /// Evaluator running the ONNX model at `path`, e.g. one written by `write_onnx`.
+    ///
+    /// # Errors
+    ///
+    /// See `OnnxNetwork::from_file`.
+    pub fn from_onnx_file(path: &Path) -> Result<Self, NetworkError> {
+        Ok(Self::new(Arc::new(OnnxNetwork::from_file(path)?)))
+    }
+}
+
+// Derived `Clone` would require `N: Clone`.
+impl<N: Network> Clone for NetworkEvaluator<N> {
+    fn clone(&self) -> Self {
+        Self {
+            network: Arc::clone(&self.network),
+        }
+    }
+}
+
+impl<N: Network + Send + Sync> NNEvaluator for NetworkEvaluator<N> {
+    type Input = InputStack<NUM_INPUT_PLANES>;
+
+    fn eval_by_info(&self, info: Self::Input) -> Result<f32, NetworkError> {
+        let mut computation = self.network.new_computation();
+        computation.add_input(info);
+        computation.compute_blocking()?;
+        Ok(computation.q_val(0))
+    }
+
+    fn eval_state(&self, state: &GameState, _moves: &[Move]) -> Result<f32, NetworkError> {
+        self.eval_by_info(self.encode(std::slice::from_ref(state)).0)
+    }
+
+    fn has_moves_left(&self) -> bool {
+        self.network.capabilities().has_mlh()
+    }
+
+    fn evaluate(&self, history: &[GameState], moves: &[Move]) -> Result<Evaluation, NetworkError> {
+        Ok(self
+            .evaluate_batch(&[history.to_vec()], &[moves.to_vec()])?
+            .pop()
+            .expect("one evaluation per input"))
+    }
+
+    fn evaluate_batch(
+        &self,
+        histories: &[Vec<GameState>],
+        moves: &[Vec<Move>],
+    ) -> Result<Vec<Evaluation>, NetworkError> {
+        let mut computation = self.network.new_computation();
+        let mut transforms = Vec::with_capacity(histories.len());
+        for history in histories {
+            let (planes, transform) = self.encode(history);
+            computation.add_input(planes);
+            transforms.push(transform);
+        }
+        computation.compute_blocking()?;
+
+        Ok(histories
+            .iter()
+            .zip(moves)
+            .zip(transforms)
+            .enumerate()
+            .map(|(sample, ((history, moves), transform))| {
+                let turn = history
+                    .last()
+                    .expect("history must contain the evaluated state")
+                    .position()
+                    .turn();
+                self.read_evaluation(computation.as_ref(), sample, turn, transform, moves)
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        neural::network::NetworkCapabilities,
+        pblczero::network_format::{InputFormat, MovesLeftFormat},
+    };
+
+    fn state(fen: &str) -> GameState {
+        GameState::from_fen(fen).unwrap()
+    }
+
+    fn legal_moves(state: &GameState) -> Vec<Move> {
+        state.position().legal_moves().to_vec()
+    }
+
+    #[test]
+    fn material_is_seen_from_the_side_to_move() {
+        let start = GameState::new();
+        assert!(MaterialEvaluator.eval_state(&start, &[]).unwrap().abs() < 1e-6);
+
+        // White is a queen up.
+        let white = state("4k3/8/8/8/8/8/8/3QK3 w - - 0 1");
+        let black = state("4k3/8/8/8/8/8/8/3QK3 b - - 0 1");
+        let q = MaterialEvaluator.eval_state(&white, &[]).unwrap();
+        assert!(q > 0.9 && q < 1.0, "{q}");
+        assert!((MaterialEvaluator.eval_state(&black, &[]).unwrap() + q).abs() < 1e-6);
+    }
+
+    #[test]
+    fn material_tables_reward_development() {
+        let start = state("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR b KQkq - 1 1");
+        let developed = state("rnbqkbnr/pppppppp/8/8/8/5N2/PPPPPPPP/RNBQKB1R b KQkq - 1 1");
+        assert!(
+            MaterialEvaluator.eval_state(&developed, &[]).unwrap()
+                < MaterialEvaluator.eval_state(&start, &[]).unwrap()
+        );
+    }
+
+    #[test]
+    fn random_evaluations_depend_on_position_and_seed() {
+        let start = GameState::new();
+        let moves = legal_moves(&start);
+        let history = std::slice::from_ref(&start);
+
+        let eval = RandomEvaluator::new(1).evaluate(history, &moves).unwrap();
+        assert!((-1.0..=1.0).contains(&eval.q));
+        assert_eq!(eval.policy.len(), moves.len());
+        assert!((eval.policy.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+
+        let again = RandomEvaluator::new(1).evaluate(history, &moves).unwrap();
+        assert_eq!(again.q.to_bits(), eval.q.to_bits());
+        assert_eq!(again.policy, eval.policy);
+        assert_eq!(
+            RandomEvaluator::new(1)
+                .eval_state(&start, &moves)
+                .unwrap()
+                .to_bits(),
+            eval.q.to_bits()
+        );
+
+        let other_seed = RandomEvaluator::new(2).evaluate(history, &moves).unwrap();
+        assert_ne!(other_seed.policy, eval.policy);
+        let other_position = start.make_move(&moves[0]);
+        assert_ne!(
+            RandomEvaluator::new(1)
+                .eval_state(&other_position, &[])
+                .unwrap()
+                .to_bits(),
+            eval.q.to_bits()
+        );
+    }
+
+    /// Network with fixed outputs: Q by sample, logits by policy index.
+    struct FixedNetwork {
+        capabilities: NetworkCapabilities,
+        logit: fn(usize) -> f32,
+    }
+
+    impl FixedNetwork {
+        fn new(moves_left: MovesLeftFormat, logit: fn(usize) -> f32) -> Self {
+            Self {
+                capabilities: NetworkCapabilities::new(
+                    InputFormat::InputClassical112Plane,
+                    moves_left,
+                ),
+                logit,
+            }
+        }
+    }
+
+    struct FixedComputation<'a> {
+        network: &'a FixedNetwork,
+        batch_size: usize,
+    }
+
+    const QS: [f32; 2] = [0.25, -0.5];
+
+    impl Network for FixedNetwork {
+        fn capabilities(&self) -> &NetworkCapabilities {
+            &self.capabilities
+        }
+
+        fn new_computation(&self) -> Box<dyn NetworkComputation + '_> {
+            Box::new(FixedComputation {
+                network: self,
+                batch_size: 0,
+            })
+        }
+    }
+
+    impl NetworkComputation for FixedComputation<'_> {
+        fn add_input(&mut self, _: InputStack<NUM_INPUT_PLANES>) {
+            self.batch_size += 1;
+        }
+
+        fn compute_blocking(&self) -> Result<(), NetworkError> {
+            Ok(())
+        }
+
+        fn batch_size(&self) -> usize {
+            self.batch_size
+        }
+
+        fn q_val(&self, sample: usize) -> f32 {
+            QS[sample]
+        }
+
+        fn d_val(&self, _: usize) -> f32 {
+            0.3
+        }
+
+        fn p_val(&self, _: usize, move_id: usize) -> f32 {
+            (self.network.logit)(move_id)
+        }
+
+        fn m_val(&self, _: usize) -> f32 {
+            42.0
+        }
+    }
+
+    fn evaluator(moves_left: MovesLeftFormat, logit: fn(usize) -> f32) -> impl NNEvaluator {
+        NetworkEvaluator::new(Arc::new(FixedNetwork::new(moves_left, logit)))
+    }
+
+    fn index_logit(idx: usize) -> f32 {
+        f32::from(u16::try_from(idx).unwrap()) / 100.0
+    }
+
+    #[test]
+    fn network_priors_are_a_softmax_over_the_legal_moves() {
+        let start = GameState::new();
+        let moves = legal_moves(&start);
+        let eval = evaluator(MovesLeftFormat::MovesLeftNone, index_logit)
+            .evaluate(std::slice::from_ref(&start), &moves)
+            .unwrap();
+
+        let exp: Vec<f32> = moves
+            .iter()
+            .map(|m| index_logit(policy::move_to_policy_index(m, Color::White).unwrap()).exp())
+            .collect();
+        let total: f32 = exp.iter().sum();
+        for (p, e) in eval.policy.iter().zip(exp) {
+            assert!((p - e / total).abs() < 1e-5, "{p} != {}", e / total);
+        }
+        assert!((eval.q - QS[0]).abs() < f32::EPSILON);
+        assert!((eval.d - 0.3).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn network_priors_fall_back_to_uniform() {
+        let start = GameState::new();
+        let moves = legal_moves(&start);
+        let eval = evaluator(MovesLeftFormat::MovesLeftNone, |_| f32::NEG_INFINITY)
+            .evaluate(std::slice::from_ref(&start), &moves)
+            .unwrap();
+
+        assert_eq!(eval.policy, uniform_policy(moves.len()));
+    }
+
+    #[test]
+    fn moves_left_only_with_a_moves_left_head() {
+        let start = GameState::new();
+        let moves = legal_moves(&start);
+        let history = std::slice::from_ref(&start);
+
+        let without = evaluator(MovesLeftFormat::MovesLeftNone, index_logit);
+        assert!(!without.has_moves_left());
+        assert!(without.evaluate(history, &moves).unwrap().m.abs() < f32::EPSILON);
+
+        let with = evaluator(MovesLeftFormat::MovesLeftV1, index_logit);
+        assert!(with.has_moves_left());
+        assert!((with.evaluate(history, &moves).unwrap().m - 42.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn batches_keep_their_order() {
+        let start = GameState::new();
+        let after = start.make_move(&legal_moves(&start)[0]);
+        let histories = vec![vec![start.clone()], vec![start.clone(), after.clone()]];
+        let moves = vec![legal_moves(&start), legal_moves(&after)];
+
+        let evals = evaluator(MovesLeftFormat::MovesLeftNone, index_logit)
+            .evaluate_batch(&histories, &moves)
+            .unwrap();
+        assert_eq!(evals.len(), 2);
+        assert!((evals[0].q - QS[0]).abs() < f32::EPSILON);
+        assert!((evals[1].q - QS[1]).abs() < f32::EPSILON);
+        assert_eq!(evals[1].policy.len(), moves[1].len());
+    }
+}
@@ -0,0 +1,479 @@
+use crate::{
+    neural::loader::WeightFile,
+    pblczero::{
+        self,
+        network_format::NetworkStructure,
+        tensor_proto::DataType,
+        weights::{ConvBlock, EncoderLayer, Layer},
+        ModelProto,
+    },
+    utils,
+};
+use prost::Message;
+use serde::Serialize;
+use std::fmt;
+
+/// Summary of a weight file, for triaging nets without loading them into a backend.
+#[derive(Clone, Debug, Serialize)]
+pub struct NetInfo {
+    pub license: Option<String>,
+    pub min_version: Option<String>,
+    pub input_format: String,
+    pub output_format: String,
+    pub network_structure: String,
+    pub policy_format: String,
+    pub value_format: String,
+    pub moves_left_format: String,
+    pub default_activation: String,
+    /// Residual blocks or encoder layers.
+    pub blocks: usize,
+    /// Filters of the residual tower or embedding size of the attention body.
+    pub filters: usize,
+    pub parameters: usize,
+    pub training_params: Option<TrainingInfo>,
+    pub embeds_onnx: bool,
+    /// Why the net would be rejected on load, see `WeightFile::validate`.
+    pub validation_error: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct TrainingInfo {
+    pub training_steps: Option<u32>,
+    pub learning_rate: Option<f32>,
+    pub mse_loss: Option<f32>,
+    pub policy_loss: Option<f32>,
+    pub accuracy: Option<f32>,
+    pub lc0_params: Option<String>,
+}
+
+impl NetInfo {
+    // Every field serializes to JSON.
+    #[allow(clippy::missing_panics_doc)]
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("net info is always serializable")
+    }
+}
+
+impl fmt::Display for NetInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let or_unknown = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+
+        writeln!(f, "License:            {}", or_unknown(&self.license))?;
+        writeln!(f, "Min version:        {}", or_unknown(&self.min_version))?;
+        writeln!(f, "Input format:       {}", self.input_format)?;
+        writeln!(f, "Output format:      {}", self.output_format)?;
+        writeln!(f, "Network structure:  {}", self.network_structure)?;
+        writeln!(f, "Policy format:      {}", self.policy_format)?;
+        writeln!(f, "Value format:       {}", self.value_format)?;
+        writeln!(f, "Moves left format:  {}", self.moves_left_format)?;
+        writeln!(f, "Default activation: {}", self.default_activation)?;
+        writeln!(f, "Blocks:             {}", self.blocks)?;
+        writeln!(f, "Filters:            {}", self.filters)?;
+        writeln!(f, "Parameters:         {}", self.parameters)?;
+        writeln!(f, "Embeds ONNX:        {}", self.embeds_onnx)?;
+        writeln!(
+            f,
+            "Validation:         {}",
+            self.validation_error.as_deref().unwrap_or("ok")
+        )?;
+
+        if let Some(training) = &self.training_params {
+            let show =
+                |value: Option<f32>| value.map_or_else(|| "-".to_string(), |v| v.to_string());
+            writeln!(f, "Training:")?;
+            writeln!(
+                f,
+                "  Steps:            {}",
+                training
+                    .training_steps
+                    .map_or_else(|| "-".to_string(), |s| s.to_string())
+            )?;
+            writeln!(f, "  Learning rate:    {}", show(training.learning_rate))?;
+            writeln!(f, "  MSE loss:         {}", show(training.mse_loss))?;
+            writeln!(f, "  Policy loss:      {}", show(training.policy_loss))?;
+            writeln!(f, "  Accuracy:         {}", show(training.accuracy))?;
+            writeln!(
+                f,
+                "  Lc0 params:       {}",
+                or_unknown(&training.lc0_params)
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl WeightFile {
+    /// Also works for nets that fail validation, e.g. read by `from_filepath_unchecked`.
+    pub fn info(&self) -> NetInfo {
+        let net = self.net();
+        let format = self.network_format();
+        let weights = net.weights.as_ref();
+
+        let attention_body =
+            format.network() == NetworkStructure::NetworkAttentionbodyWithHeadformat;
+        let (blocks, filters) = weights.map_or((0, 0), |w| {
+            if attention_body {
+                (w.encoder.len(), layer_size(w.ip_emb_b.as_ref()))
+            } else {
+                (
+                    w.residual.len(),
+                    w.input.as_ref().map_or(0, |input| {
+                        layer_size(input.biases.as_ref().or(input.bn_means.as_ref()))
+                    }),
+                )
+            }
+        });
+
+        let parameters = match (weights, &net.onnx_model) {
+            (Some(weights), _) => weights_parameters(weights),
+            (None, Some(onnx)) => {
+                ModelProto::decode(onnx.model()).map_or(0, |model| onnx_parameters(&model))
+            }
+            (None, None) => 0,
+        };
+
+        NetInfo {
+            license: net.license.clone(),
+            min_version: net
+                .min_version
+                .as_ref()
+                .map(|v| utils::get_version_string(v.major(), v.minor(), v.patch(), "", "")),
+            input_format: format.input().as_str_name().to_string(),
+            output_format: format.output().as_str_name().to_string(),
+            network_structure: format.network().as_str_name().to_string(),
+            policy_format: format.policy().as_str_name().to_string(),
+            value_format: format.value().as_str_name().to_string(),
+            moves_left_format: format.moves_left().as_str_name().to_string(),
+            default_activation: format.default_activation().as_str_name().to_string(),
+            blocks,
+            filters,
+            parameters,
+            training_params: net.training_params.as_ref().map(|t| TrainingInfo {
+                training_steps: t.training_steps,
+                learning_rate: t.learning_rate,
+                mse_loss: t.mse_loss,
+                policy_loss: t.policy_loss,
+                accuracy: t.accuracy,
+                lc0_params: t.lc0_params.clone(),
+            }),
+            embeds_onnx: net.onnx_model.is_some(),
+            validation_error: self.validate().err().map(|e| e.to_string()),
+        }
+    }
+}
+
+/// Number of values in a `LINEAR16` layer.
+fn layer_size(layer: Option<&Layer>) -> usize {
+    layer.map_or(0, |l| l.params().len() / 2)
+}
+
+const fn conv_block_layers(block: &ConvBlock) -> [&Option<Layer>; 6] {
+    [
+        &block.weights,
+        &block.biases,
+        &block.bn_means,
+        &block.bn_stddivs,
+        &block.bn_gammas,
+        &block.bn_betas,
+    ]
+}
+
+fn encoder_layers(encoder: &EncoderLayer) -> Vec<&Option<Layer>> {
+    let mut layers = vec![
+        &encoder.ln1_gammas,
+        &encoder.ln1_betas,
+        &encoder.ln2_gammas,
+        &encoder.ln2_betas,
+    ];
+
+    if let Some(mha) = &encoder.mha {
+        layers.extend([
+            &mha.q_w,
+            &mha.q_b,
+            &mha.k_w,
+            &mha.k_b,
+            &mha.v_w,
+            &mha.v_b,
+            &mha.dense_w,
+            &mha.dense_b,
+        ]);
+        if let Some(smolgen) = &mha.smolgen {
+            layers.extend([
+                &smolgen.compress,
+                &smolgen.dense1_w,
+                &smolgen.dense1_b,
+                &smolgen.ln1_gammas,
+                &smolgen.ln1_betas,
+                &smolgen.dense2_w,
+                &smolgen.dense2_b,
+                &smolgen.ln2_gammas,
+                &smolgen.ln2_betas,
+            ]);
+        }
+    }
+
+    if let Some(ffn) = &encoder.ffn {
+        layers.extend([&ffn.dense1_w, &ffn.dense1_b, &ffn.dense2_w, &ffn.dense2_b]);
+    }
+
+    layers
+}
+
+fn weights_parameters(weights: &pblczero::Weights) -> usize {
+    let mut layers = vec![
+        &weights.ip_emb_w,
+        &weights.ip_emb_b,
+        &weights.ip_mult_gate,
+        &weights.ip_add_gate,
+        &weights.ip_pol_w,
+        &weights.ip_pol_b,
+        &weights.ip2_pol_w,
+        &weights.ip2_pol_b,
+        &weights.ip3_pol_w,
+        &weights.ip3_pol_b,
+        &weights.ip4_pol_w,
+        &weights.ip_val_w,
+        &weights.ip_val_b,
+        &weights.ip1_val_w,
+        &weights.ip1_val_b,
+        &weights.ip2_val_w,
+        &weights.ip2_val_b,
+        &weights.ip_mov_w,
+        &weights.ip_mov_b,
+        &weights.ip1_mov_w,
+        &weights.ip1_mov_b,
+        &weights.ip2_mov_w,
+        &weights.ip2_mov_b,
+        &weights.smolgen_w,
+        &weights.smolgen_b,
+    ];
+
+    let conv_blocks = [
+        &weights.input,
+        &weights.policy1,
+        &weights.policy,
+        &weights.value,
+        &weights.moves_left,
+    ];
+    for block in conv_blocks.into_iter().flatten() {
+        layers.extend(conv_block_layers(block));
+    }
+
+    for residual in &weights.residual {
+        for block in [&residual.conv1, &residual.conv2].into_iter().flatten() {
+            layers.extend(conv_block_layers(block));
+        }
+        if let Some(se) = &residual.se {
+            layers.extend([&se.w1, &se.b1, &se.w2, &se.b2]);
+        }
+    }
+
+    for encoder in weights.encoder.iter().chain(&weights.pol_encoder) {
+        layers.extend(encoder_layers(encoder));
+    }
+
+    layers.into_iter().map(|l| layer_size(l.as_ref())).sum()
+}
+
+/// Number of values in the initializers of an ONNX model.
+fn onnx_parameters(model: &ModelProto) -> usize {
+    model.graph.as_ref().map_or(0, |graph| {
+        graph
+            .initializer
+            .iter()
+            .filter(|tensor| {
+                !matches!(
+                    tensor.data_type(),
+                    DataType::Int32 | DataType::Int64 | DataType::Bool
+                )
+            })
+            .map(|tensor| {
+                tensor
+                    .dims
+                    .iter()
+                    .map(|&d| usize::try_from(d).unwrap_or(0))
+                    .product::<usize>()
+            })
+            .sum()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        neural::{network::NUM_INPUT_PLANES, policy::POLICY_SIZE},
+        pblczero::{
+            format::Encoding,
+            network_format::{MovesLeftFormat, PolicyFormat, ValueFormat},
+            weights::Residual,
+            EngineVersion, Format, GraphProto, Net, NetworkFormat, OnnxModel, TensorProto,
+            TrainingParams, Weights,
+        },
+    };
+    use std::{env, fs, path::PathBuf, process};
+
+    fn layer(len: usize) -> Layer {
+        Layer {
+            min_val: Some(-1.0),
+            max_val: Some(1.0),
+            params: Some(vec![0; 2 * len]),
+        }
+    }
+
+    fn conv(in_channels: usize, filters: usize, kernel: usize) -> ConvBlock {
+        ConvBlock {
+            weights: Some(layer(filters * in_channels * kernel * kernel)),
+            biases: Some(layer(filters)),
+            ..ConvBlock::default()
+        }
+    }
+
+    fn net(format: NetworkFormat) -> Net {
+        Net {
+            magic: Some(0x1c0),
+            license: Some("GPL-3.0".to_string()),
+            min_version: Some(EngineVersion {
+                major: Some(0),
+                minor: Some(28),
+                patch: Some(2),
+            }),
+            format: Some(Format {
+                weights_encoding: Some(Encoding::Linear16 as i32),
+                network_format: Some(format),
+            }),
+            ..Net::default()
+        }
+    }
+
+    /// A classical net with one residual block of 4 filters.
+    fn classical() -> WeightFile {
+        let weights = Weights {
+            input: Some(conv(NUM_INPUT_PLANES, 4, 3)),
+            residual: vec![Residual {
+                conv1: Some(conv(4, 4, 3)),
+                conv2: Some(conv(4, 4, 3)),
+                se: None,
+            }],
+            policy: Some(conv(4, 2, 1)),
+            ip_pol_w: Some(layer(POLICY_SIZE * 64 * 2)),
+            ip_pol_b: Some(layer(POLICY_SIZE)),
+            value: Some(conv(4, 1, 1)),
+            ip1_val_w: Some(layer(8 * 64)),
+            ip1_val_b: Some(layer(8)),
+            ip2_val_w: Some(layer(8)),
+            ip2_val_b: Some(layer(1)),
+            ..Weights::default()
+        };
+
+        WeightFile::from_net(Net {
+            weights: Some(weights),
+            training_params: Some(TrainingParams {
+                training_steps: Some(1000),
+                learning_rate: Some(0.5),
+                ..TrainingParams::default()
+            }),
+            ..net(NetworkFormat {
+                network: Some(NetworkStructure::NetworkClassicalWithHeadformat as i32),
+                policy: Some(PolicyFormat::PolicyClassical as i32),
+                value: Some(ValueFormat::ValueClassical as i32),
+                moves_left: Some(MovesLeftFormat::MovesLeftNone as i32),
+                ..NetworkFormat::default()
+            })
+        })
+    }
+
+    #[test]
+    fn protobuf_weights_are_summarized() {
+        let info = classical().info();
+
+        assert_eq!(info.license.as_deref(), Some("GPL-3.0"));
+        assert_eq!(info.min_version.as_deref(), Some("0.28.2"));
+        assert_eq!(info.network_structure, "NETWORK_CLASSICAL_WITH_HEADFORMAT");
+        assert_eq!(info.policy_format, "POLICY_CLASSICAL");
+        assert_eq!(info.value_format, "VALUE_CLASSICAL");
+        assert_eq!((info.blocks, info.filters), (1, 4));
+        // Input and residual convolutions, then the policy and value heads.
+        let parameters = (4 * NUM_INPUT_PLANES * 9 + 4)
+            + 2 * (4 * 4 * 9 + 4)
+            + (2 * 4 + 2 + POLICY_SIZE * 64 * 2 + POLICY_SIZE)
+            + (4 + 1 + 8 * 64 + 8 + 8 + 1);
+        assert_eq!(info.parameters, parameters);
+        assert_eq!(info.training_params.unwrap().training_steps, Some(1000));
+        assert!(!info.embeds_onnx);
+        assert_eq!(info.validation_error, None);
+    }
+
+    #[test]
+    fn onnx_parameters_count_float_initializers() {
+        let tensor = |dims: Vec<i64>, data_type: DataType| TensorProto {
+            dims,
+            data_type: Some(data_type as i32),
+            ..TensorProto::default()
+        };
+        let model = ModelProto {
+            graph: Some(GraphProto {
+                initializer: vec![
+                    tensor(vec![2, 3], DataType::Float),
+                    tensor(vec![4], DataType::Float16),
+                    tensor(vec![3], DataType::Int64),
+                ],
+                ..GraphProto::default()
+            }),
+            ..ModelProto::default()
+        };
+        let weight_file = WeightFile::from_net(Net {
+            onnx_model: Some(OnnxModel {
+                model: Some(model.encode_to_vec()),
+                ..OnnxModel::default()
+            }),
+            ..net(NetworkFormat {
+                network: Some(NetworkStructure::NetworkOnnx as i32),
+                ..NetworkFormat::default()
+            })
+        });
+
+        let info = weight_file.info();
+        assert!(info.embeds_onnx);
+        assert_eq!(info.parameters, 10);
+        assert_eq!((info.blocks, info.filters), (0, 0));
+        assert_eq!(info.validation_error, None);
+    }
+
+    #[test]
+    fn json_has_every_field() {
+        let json: serde_json::Value = serde_json::from_str(&classical().info().to_json()).unwrap();
+
+        assert_eq!(json["license"], "GPL-3.0");
+        assert_eq!(json["blocks"], 1);
+        assert_eq!(json["filters"], 4);
+        assert_eq!(json["embeds_onnx"], false);
+        assert_eq!(json["training_params"]["training_steps"], 1000);
+        assert_eq!(json["training_params"]["learning_rate"], 0.5);
+        assert!(json["training_params"]["mse_loss"].is_null());
+        assert!(json["validation_error"].is_null());
+    }
+
+    #[test]
+    fn invalid_nets_report_the_validation_error() {
+        let mut net = classical().net().clone();
+        net.weights.as_mut().unwrap().ip_pol_b = None;
+        let weight_file = WeightFile::from_net(net);
+
+        let path = env::temp_dir().join(format!("fatduck-netinfo-{}.pb.gz", process::id()));
+        weight_file.write_to_file(&path).unwrap();
+        let checked = WeightFile::from_filepath(PathBuf::from(&path));
+        let unchecked = WeightFile::from_filepath_unchecked(PathBuf::from(&path));
+        fs::remove_file(&path).unwrap();
+
+        assert!(checked.is_err());
+        let info = unchecked.unwrap().info();
+        let error = info.validation_error.clone().unwrap();
+        assert!(error.contains("ip_pol_b"), "{error}");
+        assert!(info
+            .to_string()
+            .contains(&format!("Validation:         {error}")));
+        assert_eq!(info.blocks, 1);
+    }
+}
@@ -1,26 +1,54 @@
-use crate::{pblczero, utils};
-use flate2::read::GzDecoder;
+use crate::{neural::validation, pblczero, utils};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
 use prost::Message;
-use std::{env, ffi, fs, fs::File, io, io::Read, path::PathBuf};
+use std::{
+    env, ffi, fs,
+    fs::File,
+    io,
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
 use thiserror::Error;
 
-pub(crate) struct WeightFile(pblczero::Net);
+pub struct WeightFile(pblczero::Net);
 
 impl WeightFile {
+    /// Reads a gzipped lc0 protobuf weight file.
+    ///
+    /// # Errors
+    ///
+    /// If the file can't be read or decompressed, or isn't a supported protobuf weight file.
     pub fn from_filepath(file_path: PathBuf) -> Result<Self, WeightFileError> {
+        let weight_file = Self::from_filepath_unchecked(file_path)?;
+        weight_file.validate()?;
+        Ok(weight_file)
+    }
+
+    /// Reads a gzipped protobuf without the checks of `from_filepath`, e.g. to inspect a file
+    /// that fails them.
+    ///
+    /// # Errors
+    ///
+    /// If the file can't be read or decompressed, or isn't a protobuf weight file at all.
+    pub fn from_filepath_unchecked(file_path: PathBuf) -> Result<Self, WeightFileError> {
         let buffer = Self::decompress_gzip(file_path)?;
 
         if buffer.len() < 2 {
             return Err(WeightFileError::TooSmall);
         }
 
-        match (buffer.as_bytes()[0] as char, buffer.as_bytes()[1] as char) {
+        match (buffer[0] as char, buffer[1] as char) {
             ('1', '\n') => Err(WeightFileError::UnsupportedVersion("2".to_string())),
             ('2', '\n') => Err(WeightFileError::TextFormat),
-            _ => Self::from_pb_buffer_string(buffer),
+            _ => Ok(Self(Message::decode(buffer.as_slice())?)),
         }
     }
 
+    /// The first `.pb.gz` file in the current directory.
+    ///
+    /// # Errors
+    ///
+    /// If the directory can't be read or has no weight file.
     pub fn discover_weights_file() -> Result<PathBuf, WeightFileError> {
         let current_dir = env::current_dir().map_err(WeightFileError::IoError)?;
 
@@ -35,9 +63,13 @@ impl WeightFile {
         Err(WeightFileError::NoWeightFileFound)
     }
 
-    // ParseWeightProto
-    fn from_pb_buffer_string(pb_buffer: String) -> Result<Self, WeightFileError> {
-        let net: pblczero::Net = Message::decode(pb_buffer.as_bytes())?;
+    /// Checks the header, version and encoding of the net and the shapes of its layers.
+    ///
+    /// # Errors
+    ///
+    /// The first check that fails.
+    pub fn validate(&self) -> Result<(), WeightFileError> {
+        let net = &self.0;
         let weight_magic = 0x1c0_u32;
 
         if net.magic() != weight_magic {
@@ -47,9 +79,9 @@ impl WeightFile {
         // TODO: Check if min_version is a required field
         let mv = net
             .min_version
-            .clone()
+            .as_ref()
             .ok_or(WeightFileError::MissingMinVersion)?;
-        let min_version = utils::get_version_string(mv.major(), mv.major(), mv.patch(), "", "");
+        let min_version = utils::get_version_string(mv.major(), mv.minor(), mv.patch(), "", "");
         let lc0_version = utils::get_version_int(0, 30, 0);
         let net_version = utils::get_version_int(mv.major(), mv.minor(), mv.patch());
 
@@ -60,7 +92,7 @@ impl WeightFile {
         if net.weights.is_some()
             && net
                 .format
-                .clone()
+                .as_ref()
                 .ok_or(WeightFileError::MissingFormat)?
                 .weights_encoding()
                 != pblczero::format::Encoding::Linear16
@@ -68,21 +100,74 @@ impl WeightFile {
             return Err(WeightFileError::UnsupportedWeightEncoding);
         }
 
-        Ok(Self(net))
+        if let Some(weights) = self.weights() {
+            validation::validate_weights(weights, &self.network_format())?;
+        }
+
+        Ok(())
+    }
+
+    pub const fn from_net(net: pblczero::Net) -> Self {
+        Self(net)
+    }
+
+    pub const fn net(&self) -> &pblczero::Net {
+        &self.0
+    }
+
+    pub const fn weights(&self) -> Option<&pblczero::Weights> {
+        self.0.weights.as_ref()
     }
 
-    pub fn weights(&self) -> &Option<pblczero::Weights> {
-        &self.0.weights
+    /// Network format, with the defaults lc0 assumes for files that don't specify one.
+    pub fn network_format(&self) -> pblczero::NetworkFormat {
+        self.0
+            .format
+            .as_ref()
+            .and_then(|format| format.network_format.clone())
+            .unwrap_or_else(|| pblczero::NetworkFormat {
+                input: Some(pblczero::network_format::InputFormat::InputClassical112Plane as i32),
+                output: Some(pblczero::network_format::OutputFormat::OutputClassical as i32),
+                network: Some(pblczero::network_format::NetworkStructure::NetworkClassical as i32),
+                ..pblczero::NetworkFormat::default()
+            })
     }
 
-    fn decompress_gzip(file_path: PathBuf) -> Result<String, WeightFileError> {
+    /// Writes the net as a gzipped protobuf, the same format `from_filepath` reads.
+    ///
+    /// # Errors
+    ///
+    /// If the file can't be created or written.
+    pub fn write_to_file(&self, file_path: &Path) -> Result<(), WeightFileError> {
+        let mut encoder = GzEncoder::new(File::create(file_path)?, Compression::default());
+        encoder.write_all(&self.0.encode_to_vec())?;
+        encoder.finish()?;
+        Ok(())
+    }
+
+    fn decompress_gzip(file_path: PathBuf) -> Result<Vec<u8>, WeightFileError> {
         let mut d = GzDecoder::new(File::open(file_path)?);
-        let mut content = String::new();
-        d.read_to_string(&mut content)?;
+        let mut content = Vec::new();
+        d.read_to_end(&mut content)?;
         Ok(content)
     }
 }
 
+/// Decodes a `LINEAR16` layer: every value is a little endian u16 linearly mapped onto
+/// `[min_val, max_val]`.
+pub fn decode_layer(layer: &pblczero::weights::Layer) -> Vec<f32> {
+    let (min, max) = (layer.min_val(), layer.max_val());
+
+    layer
+        .params()
+        .chunks_exact(2)
+        .map(|bytes| {
+            let raw = f32::from(u16::from_le_bytes([bytes[0], bytes[1]]));
+            (raw / f32::from(u16::MAX)).mul_add(max - min, min)
+        })
+        .collect()
+}
+
 #[derive(Error, Debug)]
 pub enum WeightFileError {
     #[error("No files with extension '.pb.gz' found in the binary directory")]
@@ -101,6 +186,16 @@ pub enum WeightFileError {
     MissingMinVersion,
     #[error("Invalid weight file: Missing format field")]
     MissingFormat,
+    #[error("Invalid weight file: Missing layer '{0}'")]
+    MissingLayer(String),
+    #[error("Invalid weight file: Layer '{layer}' has {actual} values, expected {expected}")]
+    LayerSize {
+        layer: String,
+        expected: usize,
+        actual: usize,
+    },
+    #[error("Invalid weight file: Layer '{layer}' {reason}")]
+    InvalidLayer { layer: String, reason: String },
     #[error(transparent)]
     IoError(#[from] io::Error),
     #[error(transparent)]
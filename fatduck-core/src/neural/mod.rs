@@ -2,20 +2,122 @@ use crate::chess::GameState;
 use shakmaty::Move;
 
 mod backends;
+mod benchmark;
+mod converter;
 mod encoder;
+mod evaluators;
+mod info;
 mod loader;
 mod network;
+mod policy;
+mod validation;
+
+pub use backends::{Int8Calibration, OnnxNetwork, Precision, PrecisionDrift};
+pub use benchmark::{
+    benchmark_network, benchmark_positions, load_batch_size, BenchmarkConfig, BenchmarkReport,
+};
+pub use converter::{convert_to_onnx, embed_onnx, write_onnx, OnnxConvertError};
+pub use evaluators::{MaterialEvaluator, NetworkEvaluator, RandomEvaluator};
+pub use info::{NetInfo, TrainingInfo};
+pub use loader::{WeightFile, WeightFileError};
+pub use network::NetworkError;
 
 // Only used to retrieve a quantitative evaluation of a GameState. Maybe it should return an order
 // of moves to search? Or something that is more generic and not raw numbers.
 // Idea is that someone can implement their own Evaluator that returns a score based off whatever
 // algo they want. They can return a naive piece total as the score from the state or some more
 // elaborate thing.
-pub trait NNEvaluator: Default + Copy + Clone + Sync + Send {
+pub trait NNEvaluator: Clone + Sync + Send {
     // the inputs needed to compute a score. (i.e wdl, nodes, piece type, player color etc.)
     type Input;
 
     // Impl's will probably call eval_by_info in eval_state (extract info from state).
-    fn eval_by_info(&self, info: Self::Input) -> f32;
-    fn eval_state(&self, state: &GameState, moves: &[Move]) -> f32;
+
+    /// Q of the position described by `info`.
+    ///
+    /// # Errors
+    ///
+    /// Evaluators running a network return its errors, the others never fail.
+    fn eval_by_info(&self, info: Self::Input) -> Result<f32, NetworkError>;
+
+    /// Q of `state` with legal moves `moves`.
+    ///
+    /// # Errors
+    ///
+    /// Same as `eval_by_info`.
+    fn eval_state(&self, state: &GameState, moves: &[Move]) -> Result<f32, NetworkError>;
+
+    /// Everything search needs to know about the last state in `history`. Evaluators without a
+    /// policy or a draw/moves-left estimate can rely on this default.
+    ///
+    /// # Errors
+    ///
+    /// Same as `eval_by_info`.
+    ///
+    /// # Panics
+    ///
+    /// If `history` is empty.
+    fn evaluate(&self, history: &[GameState], moves: &[Move]) -> Result<Evaluation, NetworkError> {
+        let state = history
+            .last()
+            .expect("history must contain the evaluated state");
+        Ok(Evaluation::with_uniform_policy(
+            self.eval_state(state, moves)?,
+            moves.len(),
+        ))
+    }
+
+    // Whether evaluations carry a moves-left estimate (`Evaluation::m`). Search only applies its
+    // moves-left utility when they do.
+    fn has_moves_left(&self) -> bool {
+        false
+    }
+
+    /// Evaluates several states at once, `histories[i]` with legal moves `moves[i]`. Evaluators
+    /// that benefit from batching (i.e. networks) should override this.
+    ///
+    /// # Errors
+    ///
+    /// Same as `eval_by_info`, a failed batch fails as a whole.
+    fn evaluate_batch(
+        &self,
+        histories: &[Vec<GameState>],
+        moves: &[Vec<Move>],
+    ) -> Result<Vec<Evaluation>, NetworkError> {
+        histories
+            .iter()
+            .zip(moves)
+            .map(|(history, moves)| self.evaluate(history, moves))
+            .collect()
+    }
+}
+
+/// Evaluation of a state from the perspective of its side to move.
+#[derive(Clone, Debug, Default)]
+pub struct Evaluation {
+    /// Q = W - L
+    pub q: f32,
+    /// Draw probability
+    pub d: f32,
+    /// Expected number of plies left
+    pub m: f32,
+    /// Prior of every legal move, in the order the moves were given.
+    pub policy: Vec<f32>,
+}
+
+impl Evaluation {
+    pub fn with_uniform_policy(q: f32, num_moves: usize) -> Self {
+        Self {
+            q,
+            policy: uniform_policy(num_moves),
+            ..Self::default()
+        }
+    }
+}
+
+/// Equal priors for `num_moves` legal moves.
+// A position has at most a few hundred legal moves, exact as f32.
+#[allow(clippy::cast_precision_loss)]
+pub(crate) fn uniform_policy(num_moves: usize) -> Vec<f32> {
+    vec![1.0 / num_moves.max(1) as f32; num_moves]
 }
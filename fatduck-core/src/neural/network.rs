@@ -1,6 +1,7 @@
 use crate::pblczero;
 use shakmaty::Bitboard;
 use std::fmt;
+use thiserror::Error;
 
 pub struct NetworkCapabilities {
     input_format: pblczero::network_format::InputFormat,
@@ -8,7 +9,7 @@ pub struct NetworkCapabilities {
 }
 
 impl NetworkCapabilities {
-    pub fn new(
+    pub const fn new(
         input_format: pblczero::network_format::InputFormat,
         moves_left_format: pblczero::network_format::MovesLeftFormat,
     ) -> Self {
@@ -17,16 +18,39 @@ impl NetworkCapabilities {
             moves_left_format,
         }
     }
+
+    pub const fn input_format(&self) -> pblczero::network_format::InputFormat {
+        self.input_format
+    }
+
+    pub const fn moves_left_format(&self) -> pblczero::network_format::MovesLeftFormat {
+        self.moves_left_format
+    }
+
+    /// Whether `NetworkComputation::m_val` returns anything meaningful.
+    pub fn has_mlh(&self) -> bool {
+        self.moves_left_format != pblczero::network_format::MovesLeftFormat::MovesLeftNone
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum NetworkError {
+    #[error("ONNX inference failed: {0}")]
+    Onnx(#[from] ort::OrtError),
+    #[error("the model has int8 inputs, load it with a calibration")]
+    MissingInt8Calibration,
+    #[error("benchmark needs at least one position")]
+    NoBenchmarkPositions,
 }
 
 pub trait Network {
     fn capabilities(&self) -> &NetworkCapabilities;
-    fn new_computation(&self) -> Box<dyn NetworkComputation>;
+    fn new_computation(&self) -> Box<dyn NetworkComputation + '_>;
 }
 
 pub trait NetworkComputation {
     fn add_input(&mut self, planes: InputStack<NUM_INPUT_PLANES>);
-    fn compute_blocking(&self);
+    fn compute_blocking(&self) -> Result<(), NetworkError>;
     fn batch_size(&self) -> usize;
     fn q_val(&self, sample: usize) -> f32;
     fn d_val(&self, sample: usize) -> f32;
@@ -40,12 +64,22 @@ pub const AUX_PLANE_BASE: usize = PLANES_PER_BOARD * MOVE_HISTORY;
 /// Number of input planes per stack of input
 pub const NUM_INPUT_PLANES: usize = 112;
 
-#[derive(Default, Clone, Copy)]
+#[derive(Clone, Copy)]
 pub struct InputPlane {
     mask: u64,
     value: f32,
 }
 
+impl Default for InputPlane {
+    // Squares set in the mask are 1.0 unless filled with another value.
+    fn default() -> Self {
+        Self {
+            mask: 0,
+            value: 1.0,
+        }
+    }
+}
+
 impl fmt::Debug for InputPlane {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -58,26 +92,41 @@ impl fmt::Debug for InputPlane {
 }
 
 impl InputPlane {
-    pub fn set_mask_max(&mut self) {
+    pub const fn set_mask_max(&mut self) {
         self.mask = u64::MAX;
     }
 
-    pub fn fill(&mut self, value: f32) {
+    pub const fn fill(&mut self, value: f32) {
         self.mask = u64::MAX;
         self.value = value;
     }
 
-    pub fn mask(&self) -> u64 {
+    pub const fn mask(&self) -> u64 {
         self.mask
     }
 
-    pub fn mask_mut(&mut self) -> &mut u64 {
+    pub const fn mask_mut(&mut self) -> &mut u64 {
         &mut self.mask
     }
+
+    pub const fn value(&self) -> f32 {
+        self.value
+    }
+
+    /// Writes the plane as 64 floats, square a1 first.
+    pub fn expand_into(&self, out: &mut [f32]) {
+        for (square, out) in out.iter_mut().enumerate().take(64) {
+            *out = if self.mask & (1 << square) == 0 {
+                0.0
+            } else {
+                self.value
+            };
+        }
+    }
 }
 
 // A stack of input planes
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct InputStack<const N: usize>([InputPlane; N]);
 
 impl<const N: usize> InputStack<N> {
@@ -85,11 +134,11 @@ impl<const N: usize> InputStack<N> {
         Self([InputPlane::default(); N])
     }
 
-    pub fn planes(&self) -> &[InputPlane] {
+    pub const fn planes(&self) -> &[InputPlane] {
         &self.0
     }
 
-    pub fn planes_mut(&mut self) -> &mut [InputPlane] {
+    pub const fn planes_mut(&mut self) -> &mut [InputPlane] {
         &mut self.0
     }
 }
@@ -0,0 +1,227 @@
+use crate::neural::encoder::BoardTransforms;
+use shakmaty::{Color, Move, Role, Square};
+use std::sync::OnceLock;
+
+/// Number of outputs of the classical/attention policy head.
+pub const POLICY_SIZE: usize = 1858;
+
+const NO_INDEX: u16 = u16::MAX;
+
+/// Maps moves to lc0 policy head indices. Indices are laid out by origin square, then target
+/// square, over all queen and knight moves, followed by the queen, rook and bishop promotions.
+/// Knight promotions share the index of the plain pawn move and castling is encoded as the king
+/// capturing its own rook, which is how shakmaty represents castling in standard chess and
+/// Chess960 alike.
+struct PolicyMap {
+    regular: [[u16; 64]; 64],
+    // Indexed by origin file, target file and promotion (queen, rook, bishop).
+    promotions: [[[u16; 3]; 8]; 8],
+}
+
+impl PolicyMap {
+    fn get() -> &'static Self {
+        static MAP: OnceLock<PolicyMap> = OnceLock::new();
+        MAP.get_or_init(Self::build)
+    }
+
+    fn build() -> Self {
+        let mut map = Self {
+            regular: [[NO_INDEX; 64]; 64],
+            promotions: [[[NO_INDEX; 3]; 8]; 8],
+        };
+        let mut index = 0;
+
+        for from in 0..64 {
+            for to in 0..64 {
+                if is_queen_or_knight_move(from, to) {
+                    map.regular[from][to] = index;
+                    index += 1;
+                }
+            }
+        }
+
+        for from_file in 0..8_usize {
+            for to_file in from_file.saturating_sub(1)..=(from_file + 1).min(7) {
+                for promotion in 0..3 {
+                    map.promotions[from_file][to_file][promotion] = index;
+                    index += 1;
+                }
+            }
+        }
+
+        debug_assert_eq!(index as usize, POLICY_SIZE);
+        map
+    }
+}
+
+const fn is_queen_or_knight_move(from: usize, to: usize) -> bool {
+    if from == to {
+        return false;
+    }
+
+    let file_diff = (from % 8).abs_diff(to % 8);
+    let rank_diff = (from / 8).abs_diff(to / 8);
+
+    file_diff == 0
+        || rank_diff == 0
+        || file_diff == rank_diff
+        || (file_diff == 1 && rank_diff == 2)
+        || (file_diff == 2 && rank_diff == 1)
+}
+
+/// Policy index of `m` for a network evaluating the position with `turn` to move. The board is
+/// seen from the side to move, so black's moves are mirrored vertically.
+pub fn move_to_policy_index(m: &Move, turn: Color) -> Option<usize> {
+    move_to_transformed_policy_index(m, turn, BoardTransforms::NONE)
+}
+
+/// Policy index of `m` for a network fed planes transformed by `transform`, see
+/// `encode_position_for_nn`.
+pub fn move_to_transformed_policy_index(
+    m: &Move,
+    turn: Color,
+    transform: BoardTransforms,
+) -> Option<usize> {
+    let orient = |sq: Square| {
+        transform.square(match turn {
+            Color::White => sq,
+            Color::Black => sq.flip_vertical(),
+        })
+    };
+
+    let from = orient(m.from()?);
+    let to = orient(m.to());
+    let map = PolicyMap::get();
+
+    let index = match m.promotion() {
+        Some(role @ (Role::Queen | Role::Rook | Role::Bishop)) => {
+            let promotion = match role {
+                Role::Queen => 0,
+                Role::Rook => 1,
+                _ => 2,
+            };
+            map.promotions[usize::from(from.file())][usize::from(to.file())][promotion]
+        }
+        _ => map.regular[usize::from(from)][usize::from(to)],
+    };
+
+    (index != NO_INDEX).then_some(usize::from(index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::neural::encoder::BoardTransform;
+    use shakmaty::{fen::Fen, uci::Uci, CastlingMode, Chess, Position};
+
+    fn index(uci: &str, turn: Color, transform: BoardTransforms) -> Option<usize> {
+        let uci: Uci = uci.parse().unwrap();
+        let m = match uci {
+            Uci::Normal {
+                from,
+                to,
+                promotion,
+            } => Move::Normal {
+                role: if promotion.is_some() {
+                    Role::Pawn
+                } else {
+                    Role::Queen
+                },
+                from,
+                capture: None,
+                to,
+                promotion,
+            },
+            _ => unreachable!(),
+        };
+        move_to_transformed_policy_index(&m, turn, transform)
+    }
+
+    fn white(uci: &str) -> usize {
+        index(uci, Color::White, BoardTransforms::NONE).unwrap()
+    }
+
+    /// First and last entries of lc0's move table.
+    #[test]
+    fn indices_match_lc0() {
+        let first = [
+            "a1b1", "a1c1", "a1d1", "a1e1", "a1f1", "a1g1", "a1h1", "a1a2", "a1b2", "a1c2", "a1a3",
+            "a1b3", "a1c3", "a1a4", "a1d4", "a1a5", "a1e5", "a1a6", "a1f6", "a1a7", "a1g7", "a1a8",
+            "a1h8", "b1a1",
+        ];
+        for (i, m) in first.iter().enumerate() {
+            assert_eq!(white(m), i, "{m}");
+        }
+        assert_eq!(white("h8g8"), 1791);
+
+        assert_eq!(white("a7a8q"), 1792);
+        assert_eq!(white("a7a8r"), 1793);
+        assert_eq!(white("a7a8b"), 1794);
+        assert_eq!(white("a7b8q"), 1795);
+        assert_eq!(white("h7h8b"), POLICY_SIZE - 1);
+        // Knight promotions are plain pawn moves.
+        assert_eq!(white("a7a8n"), white("a7a8"));
+    }
+
+    #[test]
+    fn black_moves_are_mirrored() {
+        assert_eq!(
+            index("e7e5", Color::Black, BoardTransforms::NONE),
+            Some(white("e2e4"))
+        );
+        assert_eq!(
+            index("b8c6", Color::Black, BoardTransforms::NONE),
+            Some(white("b1c3"))
+        );
+        assert_eq!(
+            index("d2d1q", Color::Black, BoardTransforms::NONE),
+            Some(white("d7d8q"))
+        );
+    }
+
+    #[test]
+    fn transformed_moves_follow_the_board() {
+        let flip =
+            BoardTransforms::canonical(&"8/8/8/8/8/8/8/K6k".parse().unwrap(), Color::White, false);
+        assert!(flip.contains(BoardTransform::Flip));
+        assert_eq!(index("a1a2", Color::White, flip), Some(white("h1h2")));
+
+        let flip_transpose =
+            BoardTransforms::canonical(&"8/8/8/8/K7/8/8/7k".parse().unwrap(), Color::White, false);
+        // a4 flips to h4 and h4 transposes to e1.
+        assert_eq!(
+            index("a4b5", Color::White, flip_transpose),
+            Some(white("e1d2"))
+        );
+        // Black's move is mirrored before the transforms apply.
+        assert_eq!(
+            index("a5b4", Color::Black, flip_transpose),
+            Some(white("e1d2"))
+        );
+    }
+
+    #[test]
+    fn every_legal_move_has_an_index() {
+        for fen in [
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N w - - 0 1",
+            "n1n5/PPPk4/8/8/8/8/4Kppp/5N1N b - - 0 1",
+        ] {
+            let position: Chess = fen
+                .parse::<Fen>()
+                .unwrap()
+                .into_position(CastlingMode::Standard)
+                .unwrap();
+            let mut indices: Vec<usize> = position
+                .legal_moves()
+                .iter()
+                .map(|m| move_to_policy_index(m, position.turn()).unwrap())
+                .collect();
+            let moves = indices.len();
+            indices.sort_unstable();
+            indices.dedup();
+            assert_eq!(indices.len(), moves, "{fen}");
+            assert!(indices.iter().all(|&i| i < POLICY_SIZE));
+        }
+    }
+}
@@ -0,0 +1,737 @@
+use crate::{
+    neural::{loader::WeightFileError, network::NUM_INPUT_PLANES, policy::POLICY_SIZE},
+    pblczero::{
+        network_format::{MovesLeftFormat, NetworkStructure, PolicyFormat, ValueFormat},
+        weights::{ConvBlock, EncoderLayer, Layer, Smolgen},
+        NetworkFormat, Weights,
+    },
+};
+
+type ValidationResult<T> = Result<T, WeightFileError>;
+
+/// Checks that the shape of every layer in `weights` is consistent with `format` and with the
+/// layers it is connected to, so a malformed file is rejected on load instead of crashing a
+/// backend.
+pub fn validate_weights(weights: &Weights, format: &NetworkFormat) -> ValidationResult<()> {
+    let network = format.network();
+    let attention_body = match network {
+        NetworkStructure::NetworkClassical
+        | NetworkStructure::NetworkSe
+        | NetworkStructure::NetworkClassicalWithHeadformat
+        | NetworkStructure::NetworkSeWithHeadformat => false,
+        NetworkStructure::NetworkAttentionbodyWithHeadformat => true,
+        NetworkStructure::NetworkOnnx | NetworkStructure::NetworkUnknown => {
+            return Err(invalid(
+                "format",
+                format!("{} can't have protobuf weights", network.as_str_name()),
+            ))
+        }
+    };
+
+    let channels = if attention_body {
+        attention_body_channels(weights)?
+    } else {
+        residual_body_channels(weights, network)?
+    };
+
+    match format.policy() {
+        PolicyFormat::PolicyAttention => attention_policy(weights, channels)?,
+        PolicyFormat::PolicyConvolution => {
+            conv_block(weights.policy1.as_ref(), "policy1", channels, 3)?;
+            expect_outputs(weights.policy.as_ref(), "policy", channels, 3, 80)?;
+        }
+        PolicyFormat::PolicyClassical | PolicyFormat::PolicyUnknown => {
+            let filters = conv_block(weights.policy.as_ref(), "policy", channels, 1)?;
+            dense(
+                weights.ip_pol_w.as_ref(),
+                weights.ip_pol_b.as_ref(),
+                "ip_pol",
+                64 * filters,
+                Some(POLICY_SIZE),
+            )?;
+        }
+    }
+
+    let value_outputs = if format.value() == ValueFormat::ValueWdl {
+        3
+    } else {
+        1
+    };
+    let value = Head {
+        name: "val",
+        conv: weights.value.as_ref(),
+        conv_name: "value",
+        embedding: (weights.ip_val_w.as_ref(), weights.ip_val_b.as_ref()),
+        hidden: (weights.ip1_val_w.as_ref(), weights.ip1_val_b.as_ref()),
+        output: (weights.ip2_val_w.as_ref(), weights.ip2_val_b.as_ref()),
+    };
+    value.validate(channels, attention_body, value_outputs)?;
+
+    if format.moves_left() == MovesLeftFormat::MovesLeftV1 {
+        let moves_left = Head {
+            name: "mov",
+            conv: weights.moves_left.as_ref(),
+            conv_name: "moves_left",
+            embedding: (weights.ip_mov_w.as_ref(), weights.ip_mov_b.as_ref()),
+            hidden: (weights.ip1_mov_w.as_ref(), weights.ip1_mov_b.as_ref()),
+            output: (weights.ip2_mov_w.as_ref(), weights.ip2_mov_b.as_ref()),
+        };
+        moves_left.validate(channels, attention_body, 1)?;
+    }
+
+    Ok(())
+}
+
+/// Input convolution and residual tower, returns the number of filters.
+fn residual_body_channels(weights: &Weights, network: NetworkStructure) -> ValidationResult<usize> {
+    let filters = conv_block(weights.input.as_ref(), "input", NUM_INPUT_PLANES, 3)?;
+    if weights.residual.is_empty() {
+        return Err(invalid("residual", "has no blocks".to_string()));
+    }
+    let has_se = matches!(
+        network,
+        NetworkStructure::NetworkSe | NetworkStructure::NetworkSeWithHeadformat
+    );
+
+    for (i, residual) in weights.residual.iter().enumerate() {
+        expect_outputs(
+            residual.conv1.as_ref(),
+            &format!("residual[{i}].conv1"),
+            filters,
+            3,
+            filters,
+        )?;
+        expect_outputs(
+            residual.conv2.as_ref(),
+            &format!("residual[{i}].conv2"),
+            filters,
+            3,
+            filters,
+        )?;
+
+        match (&residual.se, has_se) {
+            (Some(se), true) => {
+                let name = format!("residual[{i}].se");
+                let se_channels = outputs(se.w1.as_ref(), &format!("{name}.w1"), filters)?;
+                expect(se.b1.as_ref(), &format!("{name}.b1"), se_channels)?;
+                expect(
+                    se.w2.as_ref(),
+                    &format!("{name}.w2"),
+                    2 * filters * se_channels,
+                )?;
+                expect(se.b2.as_ref(), &format!("{name}.b2"), 2 * filters)?;
+            }
+            (None, true) => return Err(WeightFileError::MissingLayer(format!("residual[{i}].se"))),
+            (Some(_), false) => {
+                return Err(invalid(
+                    &format!("residual[{i}].se"),
+                    format!("not allowed in {}", network.as_str_name()),
+                ))
+            }
+            (None, false) => {}
+        }
+    }
+
+    Ok(filters)
+}
+
+/// Input embedding and encoder stack, returns the embedding size.
+fn attention_body_channels(weights: &Weights) -> ValidationResult<usize> {
+    let embedding = count(weights.ip_emb_b.as_ref(), "ip_emb_b")?;
+    let inputs = outputs(weights.ip_emb_w.as_ref(), "ip_emb_w", embedding)?;
+    if inputs < NUM_INPUT_PLANES {
+        return Err(invalid(
+            "ip_emb_w",
+            format!("has {inputs} inputs, expected at least {NUM_INPUT_PLANES}"),
+        ));
+    }
+
+    if weights.ip_mult_gate.is_some() || weights.ip_add_gate.is_some() {
+        expect(
+            weights.ip_mult_gate.as_ref(),
+            "ip_mult_gate",
+            64 * embedding,
+        )?;
+        expect(weights.ip_add_gate.as_ref(), "ip_add_gate", 64 * embedding)?;
+    }
+
+    if !weights.residual.is_empty() {
+        return Err(invalid(
+            "residual",
+            "not allowed in an attention body".to_string(),
+        ));
+    }
+    if weights.encoder.is_empty() {
+        return Err(invalid("encoder", "has no layers".to_string()));
+    }
+
+    let heads = weights.headcount() as usize;
+    for (i, layer) in weights.encoder.iter().enumerate() {
+        encoder_layer(weights, layer, &format!("encoder[{i}]"), embedding, heads)?;
+    }
+
+    Ok(embedding)
+}
+
+fn attention_policy(weights: &Weights, channels: usize) -> ValidationResult<()> {
+    let embedding = dense(
+        weights.ip_pol_w.as_ref(),
+        weights.ip_pol_b.as_ref(),
+        "ip_pol",
+        channels,
+        None,
+    )?;
+
+    let heads = weights.pol_headcount() as usize;
+    for (i, layer) in weights.pol_encoder.iter().enumerate() {
+        encoder_layer(
+            weights,
+            layer,
+            &format!("pol_encoder[{i}]"),
+            embedding,
+            heads,
+        )?;
+    }
+
+    let dk = dense(
+        weights.ip2_pol_w.as_ref(),
+        weights.ip2_pol_b.as_ref(),
+        "ip2_pol",
+        embedding,
+        None,
+    )?;
+    dense(
+        weights.ip3_pol_w.as_ref(),
+        weights.ip3_pol_b.as_ref(),
+        "ip3_pol",
+        embedding,
+        Some(dk),
+    )?;
+    expect(weights.ip4_pol_w.as_ref(), "ip4_pol_w", 4 * dk)?;
+
+    Ok(())
+}
+
+fn encoder_layer(
+    weights: &Weights,
+    layer: &EncoderLayer,
+    name: &str,
+    embedding: usize,
+    heads: usize,
+) -> ValidationResult<()> {
+    let mha = layer
+        .mha
+        .as_ref()
+        .ok_or_else(|| WeightFileError::MissingLayer(format!("{name}.mha")))?;
+
+    let depth = dense(
+        mha.q_w.as_ref(),
+        mha.q_b.as_ref(),
+        &format!("{name}.mha.q"),
+        embedding,
+        None,
+    )?;
+    dense(
+        mha.k_w.as_ref(),
+        mha.k_b.as_ref(),
+        &format!("{name}.mha.k"),
+        embedding,
+        Some(depth),
+    )?;
+    dense(
+        mha.v_w.as_ref(),
+        mha.v_b.as_ref(),
+        &format!("{name}.mha.v"),
+        embedding,
+        Some(depth),
+    )?;
+    dense(
+        mha.dense_w.as_ref(),
+        mha.dense_b.as_ref(),
+        &format!("{name}.mha.dense"),
+        depth,
+        Some(embedding),
+    )?;
+    if heads == 0 || depth % heads != 0 {
+        return Err(invalid(
+            &format!("{name}.mha"),
+            format!("depth {depth} can't be split into {heads} heads"),
+        ));
+    }
+
+    if let Some(smolgen) = &mha.smolgen {
+        self::smolgen(
+            weights,
+            smolgen,
+            &format!("{name}.mha.smolgen"),
+            embedding,
+            heads,
+        )?;
+    }
+
+    layer_norm(
+        layer.ln1_gammas.as_ref(),
+        layer.ln1_betas.as_ref(),
+        &format!("{name}.ln1"),
+        embedding,
+    )?;
+
+    let ffn = layer
+        .ffn
+        .as_ref()
+        .ok_or_else(|| WeightFileError::MissingLayer(format!("{name}.ffn")))?;
+    let hidden = dense(
+        ffn.dense1_w.as_ref(),
+        ffn.dense1_b.as_ref(),
+        &format!("{name}.ffn.dense1"),
+        embedding,
+        None,
+    )?;
+    dense(
+        ffn.dense2_w.as_ref(),
+        ffn.dense2_b.as_ref(),
+        &format!("{name}.ffn.dense2"),
+        hidden,
+        Some(embedding),
+    )?;
+
+    layer_norm(
+        layer.ln2_gammas.as_ref(),
+        layer.ln2_betas.as_ref(),
+        &format!("{name}.ln2"),
+        embedding,
+    )
+}
+
+/// Smolgen generates attention logits for each head from a compressed copy of the embeddings,
+/// with the final projection shared by all layers.
+fn smolgen(
+    weights: &Weights,
+    smolgen: &Smolgen,
+    name: &str,
+    embedding: usize,
+    heads: usize,
+) -> ValidationResult<()> {
+    let compressed = outputs(
+        smolgen.compress.as_ref(),
+        &format!("{name}.compress"),
+        embedding,
+    )?;
+    let hidden = dense(
+        smolgen.dense1_w.as_ref(),
+        smolgen.dense1_b.as_ref(),
+        &format!("{name}.dense1"),
+        64 * compressed,
+        None,
+    )?;
+    layer_norm(
+        smolgen.ln1_gammas.as_ref(),
+        smolgen.ln1_betas.as_ref(),
+        &format!("{name}.ln1"),
+        hidden,
+    )?;
+    let generated = dense(
+        smolgen.dense2_w.as_ref(),
+        smolgen.dense2_b.as_ref(),
+        &format!("{name}.dense2"),
+        hidden,
+        None,
+    )?;
+    layer_norm(
+        smolgen.ln2_gammas.as_ref(),
+        smolgen.ln2_betas.as_ref(),
+        &format!("{name}.ln2"),
+        generated,
+    )?;
+
+    if generated % heads != 0 {
+        return Err(invalid(
+            &format!("{name}.dense2"),
+            format!("{generated} outputs can't be split into {heads} heads"),
+        ));
+    }
+    dense(
+        weights.smolgen_w.as_ref(),
+        weights.smolgen_b.as_ref(),
+        "smolgen",
+        generated / heads,
+        Some(64 * 64),
+    )?;
+    Ok(())
+}
+
+/// Value and moves left heads share their structure: per square features, from a 1x1
+/// convolution or a dense embedding, followed by two dense layers.
+struct Head<'a> {
+    name: &'static str,
+    conv: Option<&'a ConvBlock>,
+    conv_name: &'static str,
+    embedding: (Option<&'a Layer>, Option<&'a Layer>),
+    hidden: (Option<&'a Layer>, Option<&'a Layer>),
+    output: (Option<&'a Layer>, Option<&'a Layer>),
+}
+
+impl Head<'_> {
+    fn validate(
+        &self,
+        channels: usize,
+        attention_body: bool,
+        outputs: usize,
+    ) -> ValidationResult<()> {
+        let features = if attention_body {
+            let (w, b) = self.embedding;
+            dense(w, b, &format!("ip_{}", self.name), channels, None)?
+        } else {
+            conv_block(self.conv, self.conv_name, channels, 1)?
+        };
+
+        let (w, b) = self.hidden;
+        let hidden = dense(w, b, &format!("ip1_{}", self.name), 64 * features, None)?;
+        let (w, b) = self.output;
+        dense(w, b, &format!("ip2_{}", self.name), hidden, Some(outputs))?;
+        Ok(())
+    }
+}
+
+/// Number of values in a `LINEAR16` layer.
+fn count(layer: Option<&Layer>, name: &str) -> ValidationResult<usize> {
+    let params = layer
+        .map(Layer::params)
+        .filter(|params| !params.is_empty())
+        .ok_or_else(|| WeightFileError::MissingLayer(name.to_string()))?;
+
+    if params.len() % 2 != 0 {
+        return Err(invalid(
+            name,
+            format!("has an odd number of bytes ({})", params.len()),
+        ));
+    }
+    Ok(params.len() / 2)
+}
+
+fn expect(layer: Option<&Layer>, name: &str, expected: usize) -> ValidationResult<()> {
+    let actual = count(layer, name)?;
+    if actual != expected {
+        return Err(WeightFileError::LayerSize {
+            layer: name.to_string(),
+            expected,
+            actual,
+        });
+    }
+    Ok(())
+}
+
+/// Number of outputs of a layer with `per_output` values per output.
+fn outputs(layer: Option<&Layer>, name: &str, per_output: usize) -> ValidationResult<usize> {
+    let values = count(layer, name)?;
+    if per_output == 0 || values % per_output != 0 {
+        return Err(invalid(
+            name,
+            format!("has {values} values, not a multiple of {per_output}"),
+        ));
+    }
+    Ok(values / per_output)
+}
+
+/// Fully connected layer stored as `{name}_w`/`{name}_b`, returns the output size.
+fn dense(
+    weights: Option<&Layer>,
+    biases: Option<&Layer>,
+    name: &str,
+    inputs: usize,
+    expected_outputs: Option<usize>,
+) -> ValidationResult<usize> {
+    let weights_name = format!("{name}_w");
+    let size = match expected_outputs {
+        Some(size) => {
+            expect(weights, &weights_name, size * inputs)?;
+            size
+        }
+        None => outputs(weights, &weights_name, inputs)?,
+    };
+
+    expect(biases, &format!("{name}_b"), size)?;
+    Ok(size)
+}
+
+fn layer_norm(
+    gammas: Option<&Layer>,
+    betas: Option<&Layer>,
+    name: &str,
+    size: usize,
+) -> ValidationResult<()> {
+    expect(gammas, &format!("{name}.gammas"), size)?;
+    expect(betas, &format!("{name}.betas"), size)
+}
+
+/// Convolution with optional batch norm, returns the number of filters.
+fn conv_block(
+    block: Option<&ConvBlock>,
+    name: &str,
+    in_channels: usize,
+    kernel: usize,
+) -> ValidationResult<usize> {
+    let block = block.ok_or_else(|| WeightFileError::MissingLayer(name.to_string()))?;
+    let filters = outputs(
+        block.weights.as_ref(),
+        &format!("{name}.weights"),
+        in_channels * kernel * kernel,
+    )?;
+
+    let per_filter = [
+        (block.biases.as_ref(), "biases"),
+        (block.bn_means.as_ref(), "bn_means"),
+        (block.bn_stddivs.as_ref(), "bn_stddivs"),
+        (block.bn_gammas.as_ref(), "bn_gammas"),
+        (block.bn_betas.as_ref(), "bn_betas"),
+    ];
+    for (layer, suffix) in per_filter {
+        if layer.is_some() {
+            expect(layer, &format!("{name}.{suffix}"), filters)?;
+        }
+    }
+
+    if block.bn_means.is_some() != block.bn_stddivs.is_some() {
+        return Err(invalid(
+            name,
+            "batch norm needs both means and variances".to_string(),
+        ));
+    }
+    Ok(filters)
+}
+
+fn expect_outputs(
+    block: Option<&ConvBlock>,
+    name: &str,
+    in_channels: usize,
+    kernel: usize,
+    filters: usize,
+) -> ValidationResult<()> {
+    let actual = conv_block(block, name, in_channels, kernel)?;
+    if actual != filters {
+        return Err(invalid(
+            name,
+            format!("has {actual} filters, expected {filters}"),
+        ));
+    }
+    Ok(())
+}
+
+fn invalid(layer: &str, reason: String) -> WeightFileError {
+    WeightFileError::InvalidLayer {
+        layer: layer.to_string(),
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pblczero::weights::{Residual, SEunit};
+
+    const FILTERS: usize = 4;
+
+    fn layer(len: usize) -> Layer {
+        Layer {
+            min_val: Some(-1.0),
+            max_val: Some(1.0),
+            params: Some(vec![0; 2 * len]),
+        }
+    }
+
+    fn conv(in_channels: usize, filters: usize, kernel: usize) -> ConvBlock {
+        ConvBlock {
+            weights: Some(layer(filters * in_channels * kernel * kernel)),
+            biases: Some(layer(filters)),
+            ..ConvBlock::default()
+        }
+    }
+
+    /// A classical net with one residual block and a scalar value head.
+    fn classical() -> (Weights, NetworkFormat) {
+        let weights = Weights {
+            input: Some(conv(NUM_INPUT_PLANES, FILTERS, 3)),
+            residual: vec![Residual {
+                conv1: Some(conv(FILTERS, FILTERS, 3)),
+                conv2: Some(conv(FILTERS, FILTERS, 3)),
+                se: None,
+            }],
+            policy: Some(conv(FILTERS, 2, 1)),
+            ip_pol_w: Some(layer(POLICY_SIZE * 64 * 2)),
+            ip_pol_b: Some(layer(POLICY_SIZE)),
+            value: Some(conv(FILTERS, 1, 1)),
+            ip1_val_w: Some(layer(8 * 64)),
+            ip1_val_b: Some(layer(8)),
+            ip2_val_w: Some(layer(8)),
+            ip2_val_b: Some(layer(1)),
+            ..Weights::default()
+        };
+        let format = NetworkFormat {
+            network: Some(NetworkStructure::NetworkClassicalWithHeadformat as i32),
+            policy: Some(PolicyFormat::PolicyClassical as i32),
+            value: Some(ValueFormat::ValueClassical as i32),
+            moves_left: Some(MovesLeftFormat::MovesLeftNone as i32),
+            ..NetworkFormat::default()
+        };
+        (weights, format)
+    }
+
+    fn missing_layer(result: ValidationResult<()>) -> String {
+        match result {
+            Err(WeightFileError::MissingLayer(layer)) => layer,
+            other => panic!("expected a missing layer, got {other:?}"),
+        }
+    }
+
+    fn invalid_layer(result: ValidationResult<()>) -> String {
+        match result {
+            Err(WeightFileError::InvalidLayer { layer, .. }) => layer,
+            other => panic!("expected an invalid layer, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn consistent_weights_are_accepted() {
+        let (weights, format) = classical();
+        validate_weights(&weights, &format).unwrap();
+    }
+
+    #[test]
+    fn missing_layers_are_rejected() {
+        let (mut weights, format) = classical();
+        weights.ip_pol_b = None;
+        assert_eq!(
+            missing_layer(validate_weights(&weights, &format)),
+            "ip_pol_b"
+        );
+
+        let (mut weights, format) = classical();
+        weights.residual[0].conv2 = None;
+        assert_eq!(
+            missing_layer(validate_weights(&weights, &format)),
+            "residual[0].conv2"
+        );
+
+        // Empty layers count as missing.
+        let (mut weights, format) = classical();
+        weights.ip1_val_w = Some(layer(0));
+        assert_eq!(
+            missing_layer(validate_weights(&weights, &format)),
+            "ip1_val_w"
+        );
+    }
+
+    #[test]
+    fn wrong_shapes_are_rejected() {
+        let (mut weights, format) = classical();
+        weights.ip_pol_b = Some(layer(POLICY_SIZE - 1));
+        assert!(matches!(
+            validate_weights(&weights, &format),
+            Err(WeightFileError::LayerSize { layer, expected: POLICY_SIZE, actual })
+                if layer == "ip_pol_b" && actual == POLICY_SIZE - 1
+        ));
+
+        // The residual block doesn't match the input convolution.
+        let (mut weights, format) = classical();
+        weights.residual[0].conv1 = Some(conv(FILTERS, FILTERS + 1, 3));
+        assert_eq!(
+            invalid_layer(validate_weights(&weights, &format)),
+            "residual[0].conv1"
+        );
+
+        let (mut weights, format) = classical();
+        weights.ip1_val_w = Some(layer(8 * 64 + 1));
+        assert_eq!(
+            invalid_layer(validate_weights(&weights, &format)),
+            "ip1_val_w"
+        );
+
+        let (mut weights, format) = classical();
+        weights.input.as_mut().unwrap().bn_means = Some(layer(FILTERS));
+        assert_eq!(invalid_layer(validate_weights(&weights, &format)), "input");
+    }
+
+    #[test]
+    fn residual_blocks_are_counted() {
+        let (mut weights, format) = classical();
+        weights.residual.clear();
+        assert_eq!(
+            invalid_layer(validate_weights(&weights, &format)),
+            "residual"
+        );
+
+        // Attention bodies have encoder layers instead.
+        let (weights, mut format) = classical();
+        format.network = Some(NetworkStructure::NetworkAttentionbodyWithHeadformat as i32);
+        let weights = Weights {
+            ip_emb_w: Some(layer(NUM_INPUT_PLANES * FILTERS)),
+            ip_emb_b: Some(layer(FILTERS)),
+            ..weights
+        };
+        assert_eq!(
+            invalid_layer(validate_weights(&weights, &format)),
+            "residual"
+        );
+        let weights = Weights {
+            residual: Vec::new(),
+            ..weights
+        };
+        assert_eq!(
+            invalid_layer(validate_weights(&weights, &format)),
+            "encoder"
+        );
+    }
+
+    #[test]
+    fn formats_without_protobuf_weights_are_rejected() {
+        let (weights, mut format) = classical();
+        format.network = Some(NetworkStructure::NetworkOnnx as i32);
+        assert_eq!(invalid_layer(validate_weights(&weights, &format)), "format");
+    }
+
+    #[test]
+    fn heads_must_match_the_capabilities() {
+        // A WDL value head has three outputs.
+        let (weights, mut format) = classical();
+        format.value = Some(ValueFormat::ValueWdl as i32);
+        assert!(matches!(
+            validate_weights(&weights, &format),
+            Err(WeightFileError::LayerSize { layer, expected: 24, actual: 8 })
+                if layer == "ip2_val_w"
+        ));
+
+        let (weights, mut format) = classical();
+        format.moves_left = Some(MovesLeftFormat::MovesLeftV1 as i32);
+        assert_eq!(
+            missing_layer(validate_weights(&weights, &format)),
+            "moves_left"
+        );
+
+        let (weights, mut format) = classical();
+        format.policy = Some(PolicyFormat::PolicyConvolution as i32);
+        assert_eq!(
+            missing_layer(validate_weights(&weights, &format)),
+            "policy1"
+        );
+
+        // SE units are required by SE nets and not allowed otherwise.
+        let (weights, mut format) = classical();
+        format.network = Some(NetworkStructure::NetworkSeWithHeadformat as i32);
+        assert_eq!(
+            missing_layer(validate_weights(&weights, &format)),
+            "residual[0].se"
+        );
+
+        let (mut weights, format) = classical();
+        weights.residual[0].se = Some(SEunit {
+            w1: Some(layer(2 * FILTERS)),
+            b1: Some(layer(2)),
+            w2: Some(layer(2 * FILTERS * 2)),
+            b2: Some(layer(2 * FILTERS)),
+        });
+        assert_eq!(
+            invalid_layer(validate_weights(&weights, &format)),
+            "residual[0].se"
+        );
+    }
+}
@@ -1,37 +1,178 @@
-use crate::chess::GameState;
-use petgraph::prelude::DiGraphMap;
+use crate::chess::{GameState, PositionHistory};
+use daggy::{Dag, EdgeIndex, NodeIndex, Walker};
 use shakmaty::Move;
+use std::mem::size_of;
 
-// TODO: Make more compact, don't store complete state (also for hash etc.)
-pub struct GameGraph(pub DiGraphMap<u8, u8>);
+/// Nodes don't store their position, it is replayed from the root along the edges' moves.
+pub struct GameNode<N> {
+    /// Zobrist hash of the node's position, 0 until the node is first visited.
+    pub hash: u64,
+    pub data: N,
+    /// Whether the children of this node have been added.
+    pub expanded: bool,
+}
+
+pub struct GameEdge<E> {
+    pub move_: Move,
+    pub data: E,
+}
+
+pub struct GameGraph<N, E> {
+    dag: Dag<GameNode<N>, GameEdge<E>>,
+    root: NodeIndex,
+    /// The only complete state in the graph.
+    root_state: GameState,
+}
 
-impl GameGraph {
+impl<N: Default, E: Default> GameGraph<N, E> {
     pub fn new(root: GameState) -> Self {
-        GameGraph(DiGraphMap::new())
+        let mut dag = Dag::new();
+        let root_node = dag.add_node(GameNode {
+            hash: PositionHistory::compute_hash(root.position()),
+            data: N::default(),
+            expanded: false,
+        });
+
+        Self {
+            dag,
+            root: root_node,
+            root_state: root,
+        }
+    }
+
+    /// Empties the graph down to a new root. The memory of the old tree is kept for the new one,
+    /// so discarding even a large tree is cheap.
+    pub fn reset(&mut self, root: GameState) {
+        self.dag.clear();
+        self.root = self.dag.add_node(GameNode {
+            hash: PositionHistory::compute_hash(root.position()),
+            data: N::default(),
+            expanded: false,
+        });
+        self.root_state = root;
+    }
+
+    pub const fn root_state(&self) -> &GameState {
+        &self.root_state
+    }
+
+    pub const fn root(&self) -> NodeIndex {
+        self.root
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.dag.node_count()
+    }
+
+    pub fn node(&self, node: NodeIndex) -> &GameNode<N> {
+        &self.dag[node]
+    }
+
+    pub fn node_mut(&mut self, node: NodeIndex) -> &mut GameNode<N> {
+        &mut self.dag[node]
+    }
+
+    pub fn edge(&self, edge: EdgeIndex) -> &GameEdge<E> {
+        &self.dag[edge]
     }
 
-    // Compute new board state from given move and n - 1 state
-    pub fn add_visited_node(&mut self, move_: Move, current_state: &GameState) {
-        todo!()
+    /// Adds a child reached by `move_`. Its hash is only known once a descent reaches it.
+    pub fn add_unvisited_node(&mut self, parent: NodeIndex, move_: Move, data: N) -> NodeIndex {
+        let edge = GameEdge {
+            move_,
+            data: E::default(),
+        };
+        let node = GameNode {
+            hash: 0,
+            data,
+            expanded: false,
+        };
+
+        self.dag.add_child(parent, edge, node).1
     }
 
-    pub fn add_unvisited_node(&mut self, move_: Move, current_state: &GameState) {
-        todo!()
+    /// Children of `parent`, the most recently added first.
+    pub fn children(&self, parent: NodeIndex) -> impl Iterator<Item = (EdgeIndex, NodeIndex)> + '_ {
+        self.dag.children(parent).iter(&self.dag)
     }
 
-    pub fn add_unvisited_edge(&mut self, move_: Move, current_state: &GameState) {
-        todo!()
+    pub fn parent(&self, node: NodeIndex) -> Option<(EdgeIndex, NodeIndex)> {
+        self.dag.parents(node).walk_next(&self.dag)
     }
 
-    pub fn add_visited_edge(&mut self, move_: Move, current_state: &GameState) {
-        todo!()
+    /// Bytes the graph takes up, including the graph's own bookkeeping of nodes and edges and the
+    /// capacity kept for more of them, e.g. after `reset`.
+    pub fn memory_usage(&self) -> usize {
+        // The storage of the underlying graph adds two indices per node and four per edge.
+        let index = size_of::<u32>();
+        let (node_capacity, edge_capacity) = self.dag.graph().capacity();
+        node_capacity * (size_of::<GameNode<N>>() + 2 * index)
+            + edge_capacity * (size_of::<GameEdge<E>>() + 4 * index)
+            + size_of::<Self>()
     }
 
-    pub fn backprop_hot_path(&mut self, move_: Move, current_state: &GameState) {
-        todo!()
+    /// Average of `memory_usage` per node.
+    pub fn bytes_per_node(&self) -> usize {
+        self.memory_usage() / self.dag.node_count()
     }
 
-    pub fn backprop_brute(&mut self, move_: Move, current_state: &GameState) {
-        todo!()
+    /// Calls `update` on `node` and all of its ancestors, starting at `node`. The second argument
+    /// is the distance from `node`.
+    pub fn backprop(&mut self, node: NodeIndex, mut update: impl FnMut(&mut N, usize)) {
+        let mut current = Some(node);
+        let mut distance = 0;
+
+        while let Some(n) = current {
+            update(&mut self.dag[n].data, distance);
+            current = self.parent(n).map(|(_, parent)| parent);
+            distance += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shakmaty::Position;
+
+    #[test]
+    fn memory_usage_counts_kept_capacity() {
+        let state = GameState::new();
+        let mut graph = GameGraph::<u32, ()>::new(state.clone());
+        let root = graph.root();
+        for _ in 0..50 {
+            for m in state.position().legal_moves() {
+                graph.add_unvisited_node(root, m, 0);
+            }
+        }
+        let used = graph.memory_usage();
+        assert!(used >= 1_001 * size_of::<GameNode<u32>>());
+
+        graph.reset(state);
+        assert_eq!(graph.node_count(), 1);
+        assert_eq!(graph.memory_usage(), used);
+    }
+
+    #[test]
+    fn children_are_listed_last_added_first() {
+        let state = GameState::new();
+        let mut graph = GameGraph::<usize, ()>::new(state.clone());
+        let root = graph.root();
+        for (idx, m) in state.position().legal_moves().into_iter().enumerate() {
+            graph.add_unvisited_node(root, m, idx);
+        }
+
+        let order: Vec<usize> = graph
+            .children(root)
+            .map(|(_, child)| graph.node(child).data)
+            .collect();
+        assert_eq!(order, (0..20).rev().collect::<Vec<_>>());
+        assert_eq!(
+            graph.node(root).hash,
+            PositionHistory::compute_hash(state.position())
+        );
+        assert!(graph
+            .children(root)
+            .all(|(_, child)| graph.node(child).hash == 0));
     }
 }
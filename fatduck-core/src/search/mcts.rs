@@ -1,148 +1,2306 @@
 use crate::{
-    chess::GameState,
-    search::{SearchLimits, SearchStrategy},
+    chess::{GameRules, PositionHistory, SyzygyTablebase, Terminal},
+    neural::{NNEvaluator, NetworkError},
+    search::{
+        graph::GameGraph, IterationStats, Perspective, RootMoveFilter, SearchError, SearchLimits,
+        SearchSignals, SearchStrategy, Wdl, WdlRescale,
+    },
     time::TimeManager,
+    uci,
+    utils::Rng,
 };
-use shakmaty::Move;
+use daggy::{EdgeIndex, NodeIndex};
+use shakmaty::{uci::Uci, Color, Move, Outcome, Position};
+use std::{
+    mem,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SelectorError {
+    #[error("Unknown play selector '{0}'")]
+    UnknownPlaySelector(String),
+    #[error("Unknown explore formula '{0}'")]
+    UnknownExploreFormula(String),
+}
+
+/// How the move to play is chosen among the root's children once the search is done, along with
+/// the parameters of the chosen method.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum PlaySelector {
+    #[default]
+    MostVisits,
+    HighestQ,
+    LowerConfidenceBound(LcbParams),
+    /// Samples the move with its own temperature schedule, in place of the one of `MctsParams`.
+    /// Where a single best move is needed, e.g. for the PV, it is the most visited.
+    Temperature(TemperatureParams),
+}
+
+impl PlaySelector {
+    /// Indices of `children` from best to worst.
+    fn rank(&self, children: &[MctsNodeData]) -> Vec<usize> {
+        let mut remaining: Vec<usize> = (0..children.len()).collect();
+        let mut ranked = Vec::with_capacity(children.len());
+        while !remaining.is_empty() {
+            let data: Vec<_> = remaining.iter().map(|&idx| children[idx]).collect();
+            ranked.push(remaining.remove(self.select(&data)));
+        }
+        ranked
+    }
 
-// If has custom params for specific formula, then will need to create own struct and fill it from
-// user input.
-type PlayMoveSelector = fn(&[MctsNodeData]) -> usize;
-type ExploreMoveSelector = fn(&[MctsNodeData]) -> usize;
+    pub const NAMES: [&'static str; 4] = ["visits", "q", "lcb", "temperature"];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::MostVisits => "visits",
+            Self::HighestQ => "q",
+            Self::LowerConfidenceBound(_) => "lcb",
+            Self::Temperature(_) => "temperature",
+        }
+    }
+
+    /// The selector called `name`, with default parameters. The temperature selector samples at
+    /// a constant temperature of 1.
+    ///
+    /// # Errors
+    ///
+    /// If `name` isn't one of `NAMES`.
+    pub fn from_name(name: &str) -> Result<Self, SelectorError> {
+        match name.to_ascii_lowercase().as_str() {
+            "visits" => Ok(Self::MostVisits),
+            "q" => Ok(Self::HighestQ),
+            "lcb" => Ok(Self::LowerConfidenceBound(LcbParams::default())),
+            "temperature" => Ok(Self::Temperature(TemperatureParams {
+                initial: 1.0,
+                ..TemperatureParams::default()
+            })),
+            _ => Err(SelectorError::UnknownPlaySelector(name.to_string())),
+        }
+    }
+
+    /// Temperature schedule to sample the move to play with, `temperature` unless the selector
+    /// brings its own.
+    const fn temperature(&self, temperature: TemperatureParams) -> TemperatureParams {
+        match self {
+            Self::Temperature(own) => *own,
+            _ => temperature,
+        }
+    }
+
+    /// Index of the child to play.
+    fn select(&self, children: &[MctsNodeData]) -> usize {
+        match self {
+            Self::MostVisits | Self::Temperature(_) => MostVisits.best_move_index(&(), children),
+            Self::HighestQ => HighestQ.best_move_index(&(), children),
+            Self::LowerConfidenceBound(params) => {
+                LowerConfidenceBound.best_move_index(params, children)
+            }
+        }
+    }
+}
+
+/// Plays the most visited child, the one the search is most confident in. Ties go to the higher
+/// prior.
+struct MostVisits;
+
+impl PlayMoveSelection for MostVisits {
+    type AlgoParams = ();
+
+    fn best_move_index(&self, (): &(), children: &[MctsNodeData]) -> usize {
+        children
+            .iter()
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.n.cmp(&b.n).then(a.p.total_cmp(&b.p)))
+            .map_or(0, |(idx, _)| idx)
+    }
+}
+
+/// Plays the visited child with the highest Q. Ties go to the more visited child.
+struct HighestQ;
+
+impl PlayMoveSelection for HighestQ {
+    type AlgoParams = ();
+
+    fn best_move_index(&self, (): &(), children: &[MctsNodeData]) -> usize {
+        children
+            .iter()
+            .enumerate()
+            .filter(|(_, child)| child.n > 0)
+            .max_by(|(_, a), (_, b)| a.q.total_cmp(&b.q).then(a.n.cmp(&b.n)))
+            .map_or_else(|| MostVisits.best_move_index(&(), children), |(idx, _)| idx)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct LcbParams {
+    /// Number of standard errors below Q the bound lies.
+    pub z: f32,
+    /// Children with fewer visits aren't considered.
+    pub min_visits: u32,
+}
+
+impl Default for LcbParams {
+    fn default() -> Self {
+        Self {
+            z: 1.96,
+            min_visits: 2,
+        }
+    }
+}
+
+/// Plays the child whose Q is highest with confidence, i.e. maximizing Q - z * sqrt(Var / N).
+/// The variance of a single visit's value is W + L - Q^2 = 1 - D - Q^2. Falls back to the most
+/// visited child if no child has enough visits.
+struct LowerConfidenceBound;
+
+impl PlayMoveSelection for LowerConfidenceBound {
+    type AlgoParams = LcbParams;
+
+    fn best_move_index(&self, params: &LcbParams, children: &[MctsNodeData]) -> usize {
+        children
+            .iter()
+            .enumerate()
+            .filter(|(_, child)| child.n > 0 && child.n >= params.min_visits)
+            .map(|(idx, child)| {
+                let variance = child.q.mul_add(-child.q, 1.0 - child.d).max(0.0);
+                (
+                    idx,
+                    params
+                        .z
+                        .mul_add(-(variance / visits(child.n)).sqrt(), child.q),
+                )
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map_or_else(|| MostVisits.best_move_index(&(), children), |(idx, _)| idx)
+    }
+}
+
+/// The formula choosing which child to descend into while searching, along with its parameters.
+/// Every formula maximizes Q + U, they differ in the exploration term U.
+#[derive(Clone, Copy, Debug)]
+pub enum ExploreFormula {
+    AlphaZeroPuct(PuctParams),
+    Lc0Puct(Lc0PuctParams),
+    Uct(UctParams),
+}
+
+impl Default for ExploreFormula {
+    fn default() -> Self {
+        Self::AlphaZeroPuct(PuctParams::default())
+    }
+}
+
+impl ExploreFormula {
+    pub const NAMES: [&'static str; 3] = ["alphazero", "lc0", "uct"];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::AlphaZeroPuct(_) => "alphazero",
+            Self::Lc0Puct(_) => "lc0",
+            Self::Uct(_) => "uct",
+        }
+    }
+
+    /// The formula called `name`, with default parameters.
+    ///
+    /// # Errors
+    ///
+    /// If `name` isn't one of `NAMES`.
+    pub fn from_name(name: &str) -> Result<Self, SelectorError> {
+        match name.to_ascii_lowercase().as_str() {
+            "alphazero" => Ok(Self::AlphaZeroPuct(PuctParams::default())),
+            "lc0" => Ok(Self::Lc0Puct(Lc0PuctParams::default())),
+            "uct" => Ok(Self::Uct(UctParams::default())),
+            _ => Err(SelectorError::UnknownExploreFormula(name.to_string())),
+        }
+    }
+
+    fn exploration_term(&self, child: &MctsNodeData, parent_n: f32) -> f32 {
+        match self {
+            Self::AlphaZeroPuct(params) => AlphaZeroPuct.exploration_term(params, child, parent_n),
+            Self::Lc0Puct(params) => Lc0Puct.exploration_term(params, child, parent_n),
+            Self::Uct(params) => Uct.exploration_term(params, child, parent_n),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct PuctParams {
+    pub cpuct: f32,
+}
 
-#[derive(Default, Copy, Clone)]
-struct MctsParams {
-    cpuct: f32,
+impl Default for PuctParams {
+    fn default() -> Self {
+        Self { cpuct: 1.745 }
+    }
+}
+
+/// `U = cpuct * P * sqrt(N_parent) / (1 + N)`, as in `AlphaZero`.
+struct AlphaZeroPuct;
+
+impl ExploreMoveSelection for AlphaZeroPuct {
+    type AlgoParams = PuctParams;
+
+    fn exploration_term(&self, params: &PuctParams, child: &MctsNodeData, parent_n: f32) -> f32 {
+        params.cpuct * child.p * parent_n.sqrt() / (1.0 + child.n_started())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Lc0PuctParams {
+    pub cpuct: f32,
+    pub cpuct_base: f32,
+    pub cpuct_factor: f32,
+}
+
+impl Default for Lc0PuctParams {
+    // Same defaults as lc0.
+    fn default() -> Self {
+        Self {
+            cpuct: 1.745,
+            cpuct_base: 38739.0,
+            cpuct_factor: 3.894,
+        }
+    }
+}
+
+/// PUCT whose cpuct grows with the parent's visits as
+/// `cpuct + cpuct_factor * ln((N_parent + cpuct_base) / cpuct_base)`, so that deep searches keep
+/// exploring.
+struct Lc0Puct;
+
+impl ExploreMoveSelection for Lc0Puct {
+    type AlgoParams = Lc0PuctParams;
+
+    fn exploration_term(&self, params: &Lc0PuctParams, child: &MctsNodeData, parent_n: f32) -> f32 {
+        let cpuct = params.cpuct_factor.mul_add(
+            ((parent_n + params.cpuct_base) / params.cpuct_base).ln(),
+            params.cpuct,
+        );
+        cpuct * child.p * parent_n.sqrt() / (1.0 + child.n_started())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct UctParams {
+    pub c: f32,
+}
+
+impl Default for UctParams {
+    fn default() -> Self {
+        Self {
+            c: std::f32::consts::SQRT_2,
+        }
+    }
+}
+
+/// `U = c * sqrt(ln(1 + N_parent) / (1 + N))`, which ignores the priors. The visits are offset
+/// by one so unvisited children get a finite bonus on top of their FPU value.
+struct Uct;
+
+impl ExploreMoveSelection for Uct {
+    type AlgoParams = UctParams;
+
+    fn exploration_term(&self, params: &UctParams, child: &MctsNodeData, parent_n: f32) -> f32 {
+        params.c * (parent_n.ln_1p() / (1.0 + child.n_started())).sqrt()
+    }
+}
+
+/// Value an unvisited child is assumed to have (first play urgency), from the perspective of the
+/// player choosing the child.
+#[derive(Clone, Copy, Debug)]
+pub enum Fpu {
+    /// The parent's Q lowered by `reduction * sqrt(P of the visited children)`, so the more of
+    /// the policy is already explored, the less attractive the rest is.
+    Reduction(f32),
+    /// A fixed value, e.g. -1 to only explore unvisited children once the visited ones look
+    /// lost, or 1 to try every child once.
+    Absolute(f32),
+}
+
+impl Fpu {
+    fn value(self, parent: &MctsNodeData, children: &[MctsNodeData]) -> f32 {
+        match self {
+            Self::Reduction(reduction) => {
+                let visited_policy: f32 = children
+                    .iter()
+                    .filter(|child| child.n_started() > 0.0)
+                    .map(|child| child.p)
+                    .sum();
+                // The parent's Q is from the opponent's perspective.
+                reduction.mul_add(-visited_policy.sqrt(), -parent.q)
+            }
+            Self::Absolute(value) => value,
+        }
+    }
+}
+
+/// First play urgency at the root and everywhere else. The root often gets a more optimistic
+/// value, since all of its moves should get a look.
+#[derive(Clone, Copy, Debug)]
+pub struct FpuParams {
+    pub tree: Fpu,
+    pub root: Fpu,
+}
+
+impl Default for FpuParams {
+    fn default() -> Self {
+        Self {
+            tree: Fpu::Reduction(0.0),
+            root: Fpu::Reduction(0.0),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct MctsParams {
     dirichlet_alpha: f32,
-    play_selector: PlayMoveSelector,
-    explore_selector: ExploreMoveSelector,
+    /// Share of the root priors replaced by Dirichlet noise, 0 disables the noise.
+    dirichlet_epsilon: f32,
+    /// Number of leaves gathered and evaluated together
+    minibatch_size: usize,
+    moves_left: MovesLeftParams,
+    wdl_rescale: WdlRescale,
+    temperature: TemperatureParams,
+    /// Seed of the noise and temperature sampling, `None` to seed from the clock.
+    seed: Option<u64>,
+    play_selector: PlaySelector,
+    explore_formula: ExploreFormula,
+    fpu: FpuParams,
+    /// Whether proven results are propagated up the tree (MCTS-solver).
+    solver: bool,
 }
 
 impl MctsParams {
     pub fn new(
-        cpuct: f32,
         dirichlet_alpha: f32,
-        play_selector: PlayMoveSelector,
-        explore_selector: ExploreMoveSelector,
+        minibatch_size: usize,
+        moves_left: MovesLeftParams,
+        wdl_rescale: WdlRescale,
+        play_selector: PlaySelector,
+        explore_formula: ExploreFormula,
+        fpu: FpuParams,
     ) -> Self {
         Self {
-            cpuct,
             dirichlet_alpha,
+            dirichlet_epsilon: 0.0,
+            minibatch_size,
+            moves_left,
+            wdl_rescale,
+            temperature: TemperatureParams::default(),
+            seed: None,
             play_selector,
-            explore_selector,
+            explore_formula,
+            fpu,
+            solver: true,
         }
     }
+
+    /// Value part of the score of each child of `parent`, i.e. Q plus the moves-left utility.
+    /// Explore and play selectors add their own terms on top of this.
+    fn child_values(&self, parent: &MctsNodeData, children: &[MctsNodeData]) -> Vec<f32> {
+        let mlh = MovesLeftEvaluator::new(self.moves_left, parent);
+
+        children
+            .iter()
+            .map(|child| child.q + mlh.utility(child.m, child.q))
+            .collect()
+    }
+
+    /// E.g. the batch size recommended by `neural::benchmark_network`.
+    pub const fn set_minibatch_size(&mut self, minibatch_size: usize) {
+        self.minibatch_size = minibatch_size;
+    }
+
+    /// Mixes Dirichlet(`alpha`) noise into the root priors as `(1 - epsilon) * P + epsilon *
+    /// noise`. `AlphaZero` used 0.3 and 0.25 for chess self-play.
+    pub const fn set_dirichlet_noise(&mut self, alpha: f32, epsilon: f32) {
+        self.dirichlet_alpha = alpha;
+        self.dirichlet_epsilon = epsilon;
+    }
+
+    /// Samples the move to play before the play selector decides. The temperature selector
+    /// replaces this schedule with its own.
+    pub const fn set_temperature(&mut self, temperature: TemperatureParams) {
+        self.temperature = temperature;
+    }
+
+    pub const fn set_play_selector(&mut self, play_selector: PlaySelector) {
+        self.play_selector = play_selector;
+    }
+
+    /// Switches to the play selector called `name` (one of `PlaySelector::NAMES`) with default
+    /// parameters, e.g. from a UCI option.
+    ///
+    /// # Errors
+    ///
+    /// If there is no selector called `name`.
+    pub fn set_play_selector_by_name(&mut self, name: &str) -> Result<(), SelectorError> {
+        self.play_selector = PlaySelector::from_name(name)?;
+        Ok(())
+    }
+
+    pub const fn set_explore_formula(&mut self, explore_formula: ExploreFormula) {
+        self.explore_formula = explore_formula;
+    }
+
+    /// Switches to the explore formula called `name` (one of `ExploreFormula::NAMES`) with
+    /// default parameters.
+    ///
+    /// # Errors
+    ///
+    /// If there is no formula called `name`.
+    pub fn set_explore_formula_by_name(&mut self, name: &str) -> Result<(), SelectorError> {
+        self.explore_formula = ExploreFormula::from_name(name)?;
+        Ok(())
+    }
+
+    pub const fn set_fpu(&mut self, fpu: FpuParams) {
+        self.fpu = fpu;
+    }
+
+    pub const fn set_solver(&mut self, solver: bool) {
+        self.solver = solver;
+    }
+
+    /// Index of the child of `parent` to descend into, maximizing Q + U. Visited children's Q
+    /// includes the moves-left utility and virtual loss, unvisited ones get the FPU value.
+    /// Proven children have nothing left to explore and are never picked while others remain.
+    fn explore_index(&self, parent: &MctsNodeData, children: &[MctsNodeData], root: bool) -> usize {
+        let values = self.child_values(parent, children);
+        let fpu = if root { self.fpu.root } else { self.fpu.tree }.value(parent, children);
+        let parent_n = parent.n_started();
+
+        children
+            .iter()
+            .zip(values)
+            .map(|(child, value)| {
+                if child.certainty.is_proven() {
+                    return f32::NEG_INFINITY;
+                }
+                let q = if child.n > 0 {
+                    child.with_virtual_loss(value)
+                } else {
+                    fpu
+                };
+                q + self.explore_formula.exploration_term(child, parent_n)
+            })
+            .enumerate()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map_or(0, |(idx, _)| idx)
+    }
+
+    /// A fixed seed makes searches with the same evaluations play the same moves.
+    pub const fn set_seed(&mut self, seed: Option<u64>) {
+        self.seed = seed;
+    }
+}
+
+impl Default for MctsParams {
+    fn default() -> Self {
+        Self::new(
+            0.3,
+            32,
+            MovesLeftParams::default(),
+            WdlRescale::default(),
+            PlaySelector::default(),
+            ExploreFormula::default(),
+            FpuParams::default(),
+        )
+    }
+}
+
+/// Parameters of the moves-left utility (MLH). The utility is added to a child's Q when scoring
+/// it, so that a winning side prefers shorter wins and a losing side prefers longer games.
+#[derive(Clone, Copy, Debug)]
+pub struct MovesLeftParams {
+    /// Has no effect with evaluators without a moves-left estimate, e.g. networks without a
+    /// moves-left head.
+    pub enabled: bool,
+    /// Utility gained per ply of difference between the child's and the parent's M.
+    pub slope: f32,
+    /// Maximum absolute utility the moves-left term can add.
+    pub cap: f32,
+    /// The term only kicks in once |Q| of the parent is above this threshold.
+    pub threshold: f32,
+    /// Scale of the utility: `constant + linear * |q| + quadratic * q^2`.
+    pub constant_factor: f32,
+    pub linear_factor: f32,
+    pub quadratic_factor: f32,
+}
+
+impl Default for MovesLeftParams {
+    // Same defaults as lc0.
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            slope: 0.0027,
+            cap: 0.0345,
+            threshold: 0.0,
+            constant_factor: 0.0,
+            linear_factor: 1.6521,
+            quadratic_factor: -0.6521,
+        }
+    }
+}
+
+/// Schedule of the temperature used to sample the move to play, for varied self-play games. At
+/// temperature T a move is played with probability proportional to N^(1/T).
+#[derive(Clone, Copy, Debug)]
+pub struct TemperatureParams {
+    /// Temperature at the first move, 0 always plays the play selector's choice.
+    pub initial: f32,
+    /// Number of moves over which the temperature decays linearly to 0, 0 for no decay.
+    pub decay_moves: u32,
+    /// From this move on `endgame` is used instead, 0 to never switch.
+    pub cutoff_move: u32,
+    pub endgame: f32,
+    /// Moves whose Q is more than this below the best Q are never sampled, so the temperature
+    /// doesn't throw games away.
+    pub value_cutoff: f32,
+}
+
+impl Default for TemperatureParams {
+    fn default() -> Self {
+        Self {
+            initial: 0.0,
+            decay_moves: 0,
+            cutoff_move: 0,
+            endgame: 0.0,
+            value_cutoff: 1.0,
+        }
+    }
+}
+
+impl TemperatureParams {
+    /// Temperature at the given full move number, starting at 1.
+    // Move numbers are far below 2^24, exact as f32.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn at_move(&self, fullmove: u32) -> f32 {
+        if self.cutoff_move > 0 && fullmove >= self.cutoff_move {
+            return self.endgame;
+        }
+
+        let moves = fullmove.saturating_sub(1);
+        if self.decay_moves == 0 {
+            self.initial
+        } else {
+            self.initial * (1.0 - moves as f32 / self.decay_moves as f32).max(0.0)
+        }
+    }
+}
+
+impl TemperatureParams {
+    /// Index of a visited child sampled with probability proportional to N^(1/T), T being the
+    /// temperature at `fullmove`. `None` if T is 0 or no child has been visited, the play selector
+    /// decides then.
+    fn sample(&self, children: &[MctsNodeData], fullmove: u32, rng: &mut Rng) -> Option<usize> {
+        let temperature = self.at_move(fullmove);
+        if temperature <= 0.0 {
+            return None;
+        }
+        let visited = children.iter().filter(|child| child.n > 0);
+        let max_n = visited
+            .clone()
+            .map(|child| child.n)
+            .max()
+            .filter(|&n| n > 0)?;
+        let best_q = visited.map(|child| child.q).fold(f32::MIN, f32::max);
+
+        // Relative to the most visited child so large visit counts don't overflow.
+        let weights: Vec<f32> = children
+            .iter()
+            .map(|child| {
+                if child.n == 0 || child.q < best_q - self.value_cutoff {
+                    0.0
+                } else {
+                    (visits(child.n) / visits(max_n)).powf(1.0 / temperature)
+                }
+            })
+            .collect();
+
+        let mut target = rng.next_f32() * weights.iter().sum::<f32>();
+        for (idx, weight) in weights.iter().enumerate() {
+            if target < *weight {
+                return Some(idx);
+            }
+            target -= weight;
+        }
+
+        // Rounding can leave a sliver past the last weight.
+        weights.iter().rposition(|&weight| weight > 0.0)
+    }
+}
+
+/// Computes the moves-left utility of the children of a single parent node. Created once per
+/// parent since the parent's M and Q are shared between all children.
+#[derive(Clone, Copy)]
+struct MovesLeftEvaluator {
+    params: MovesLeftParams,
+    parent_m: f32,
+    enabled: bool,
+}
+
+impl MovesLeftEvaluator {
+    fn new(params: MovesLeftParams, parent: &MctsNodeData) -> Self {
+        Self {
+            params,
+            parent_m: parent.m,
+            enabled: params.enabled && parent.n > 0 && parent.q.abs() > params.threshold,
+        }
+    }
+
+    /// `child_q` is the Q of the child from the perspective of the player choosing the move.
+    fn utility(&self, child_m: f32, child_q: f32) -> f32 {
+        if !self.enabled {
+            return 0.0;
+        }
+
+        let params = &self.params;
+        // Winning (q > 0) penalizes longer games, losing rewards them.
+        let m = (params.slope * (child_m - self.parent_m)).clamp(-params.cap, params.cap)
+            * -child_q.signum();
+
+        let q = if params.threshold > 0.0 && params.threshold < 1.0 {
+            ((child_q.abs() - params.threshold) / (1.0 - params.threshold)).max(0.0)
+        } else {
+            child_q.abs()
+        };
+
+        m * (params.quadratic_factor * q)
+            .mul_add(q, params.linear_factor.mul_add(q, params.constant_factor))
+    }
 }
 
+/// Q, D and M are from the perspective of the player who made the move leading to the node.
+/// Kept small since every node of the tree carries one.
 #[derive(Default, Clone, Copy)]
-struct MctsNodeData {
+pub struct MctsNodeData {
     /// Q = W - L
     q: f32,
     /// Total visit count
-    n: f32,
+    n: u32,
     /// Predicted draw score
     d: f32,
     /// Prior probability of selecting this node (policy)
     p: f32,
+    /// Predicted number of plies left in the game (moves-left head)
+    m: f32,
+    /// Visits of the current minibatch that haven't been backpropagated yet
+    n_in_flight: u32,
+    /// Set once the node has been visited and found to end the game (or the search line).
+    terminal: Terminal,
+    /// Result proven by the solver, from the same perspective as Q.
+    certainty: Certainty,
 }
 
-#[derive(Clone, Copy, Default)]
-struct MctsEdgeData {
-    q: f32,
-    n: f32,
-    d: f32,
-    p: f32,
+/// Visit count as used in the score formulas.
+// Counts stay far below 2^24, so they are exact as f32.
+#[allow(clippy::cast_precision_loss)]
+const fn visits(n: u32) -> f32 {
+    n as f32
+}
+
+impl MctsNodeData {
+    pub fn wdl(&self) -> Wdl {
+        Wdl::from_q_d(self.q, self.d)
+    }
+
+    /// Visits including the ones still in flight.
+    pub const fn n_started(&self) -> f32 {
+        visits(self.n + self.n_in_flight)
+    }
+
+    /// `value` with every visit in flight counted as a loss, which steers the selection of the
+    /// rest of a minibatch away from this node.
+    pub fn with_virtual_loss(&self, value: f32) -> f32 {
+        value.mul_add(visits(self.n), -visits(self.n_in_flight)) / self.n_started()
+    }
+
+    /// Replaces Q and D of a proven node by their exact values. `draw` is the value of a draw for
+    /// the player who moved into the node, after the WDL rescaling.
+    fn pin_to_certainty(&mut self, draw: Wdl) {
+        if let Some(wdl) = self.certainty.wdl(draw) {
+            self.q = wdl.q();
+            self.d = wdl.d;
+        }
+    }
+}
+
+/// Distance given to results proven by the tablebases. They are reached by zeroing moves and not
+/// necessarily mate, so anything at or beyond it isn't reported as a mate score. It also makes
+/// the solver prefer actual mates.
+const TABLEBASE_PLIES: u16 = 1000;
+
+/// Game-theoretic value of a node, from the perspective of the player who moved into it. Wins and
+/// losses carry the number of plies from the node's position to the end of the game.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Certainty {
+    #[default]
+    Unproven,
+    Win(u16),
+    Loss(u16),
+    Draw,
+}
+
+impl Certainty {
+    /// Certainty of a node whose game ended with `winner`, `plies` before the actual end.
+    fn from_winner(winner: Option<Color>, turn: Color, plies: u16) -> Self {
+        match winner {
+            // The side to move wins, so the player who moved into the node loses.
+            Some(winner) if winner == turn => Self::Loss(plies),
+            Some(_) => Self::Win(plies),
+            None => Self::Draw,
+        }
+    }
+
+    fn is_proven(self) -> bool {
+        self != Self::Unproven
+    }
+
+    fn wdl(self, draw: Wdl) -> Option<Wdl> {
+        match self {
+            Self::Unproven => None,
+            Self::Win(_) => Some(Wdl::from_q_d(1.0, 0.0)),
+            Self::Loss(_) => Some(Wdl::from_q_d(-1.0, 0.0)),
+            Self::Draw => Some(draw),
+        }
+    }
+
+    /// UCI mate score of the move into the node for the player making it, if the node is proven
+    /// to end in mate.
+    fn mate_score(self) -> Option<uci::Score> {
+        match self {
+            // Counting the move into the node.
+            Self::Win(plies) if plies < TABLEBASE_PLIES => {
+                Some(uci::Score::Mate(i32::midpoint(i32::from(plies), 2)))
+            }
+            Self::Loss(plies) if plies < TABLEBASE_PLIES => {
+                Some(uci::Score::Mate(-(i32::from(plies) + 1) / 2))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// MCTS-solver: a node is lost for the player who moved into it as soon as one child wins for the
+/// side to move, taking the quickest win. Once every child is proven, the node is a draw if any
+/// child draws and won (as slowly as possible for the loser) otherwise.
+struct MctsSolver;
+
+impl ResultPropagation for MctsSolver {
+    type AlgoParams = ();
+
+    fn propagate_result(&self, (): &(), children: &[MctsNodeData]) -> Certainty {
+        let quickest_win = children
+            .iter()
+            .filter_map(|child| match child.certainty {
+                Certainty::Win(plies) => Some(plies),
+                _ => None,
+            })
+            .min();
+        if let Some(plies) = quickest_win {
+            return Certainty::Loss(plies.saturating_add(1));
+        }
+
+        if children.is_empty() || children.iter().any(|child| !child.certainty.is_proven()) {
+            return Certainty::Unproven;
+        }
+        if children
+            .iter()
+            .any(|child| child.certainty == Certainty::Draw)
+        {
+            return Certainty::Draw;
+        }
+
+        children
+            .iter()
+            .filter_map(|child| match child.certainty {
+                Certainty::Loss(plies) => Some(plies),
+                _ => None,
+            })
+            .max()
+            .map_or(Certainty::Unproven, |plies| {
+                Certainty::Win(plies.saturating_add(1))
+            })
+    }
 }
 
-struct Mcts {
+/// Edges only carry their move, all statistics live in the child node.
+#[derive(Clone, Copy, Default)]
+pub struct MctsEdgeData;
+
+type MctsGraph = GameGraph<MctsNodeData, MctsEdgeData>;
+
+pub struct Mcts<E: NNEvaluator> {
     params: MctsParams,
+    evaluator: E,
+    graph: Option<MctsGraph>,
+    stats: IterationStats,
+    /// Sum of the depths of all visited leaves, used for the average depth.
+    total_depth: usize,
+    signals: Arc<SearchSignals>,
+    tablebase: Option<Arc<SyzygyTablebase>>,
+    /// The game up to the root, which descents continue so repetitions of positions played
+    /// before the root are detected and the network sees the moves leading to it.
+    root_history: PositionHistory,
+    /// Restriction of the root moves of the next search, set by the caller.
+    root_filter: RootMoveFilter,
+    /// Moves the root is restricted to by the filter and the tablebases, `None` to search all
+    /// legal moves.
+    root_moves: Option<Vec<Move>>,
+    /// Rules of the game being searched, which decide how draws are valued.
+    rules: GameRules,
+    /// Side to move at the root.
+    root_turn: Color,
+    /// Source of the root noise and temperature sampling.
+    rng: Rng,
 }
 
-impl Mcts {
-    pub fn new(params: MctsParams) -> Self {
-        Self { params }
+impl<E: NNEvaluator> Mcts<E> {
+    pub fn new(params: MctsParams, evaluator: E) -> Self {
+        Self {
+            params,
+            evaluator,
+            graph: None,
+            stats: IterationStats::default(),
+            total_depth: 0,
+            signals: Arc::default(),
+            tablebase: None,
+            root_history: PositionHistory::default(),
+            root_filter: RootMoveFilter::default(),
+            root_moves: None,
+            rules: GameRules::Standard,
+            root_turn: Color::White,
+            rng: Rng::from_time(),
+        }
+    }
+
+    /// Tablebases to probe during search, e.g. loaded from the `SyzygyPath` option.
+    pub fn set_tablebase(&mut self, tablebase: Option<Arc<SyzygyTablebase>>) {
+        self.tablebase = tablebase;
+    }
+
+    fn start_search(&mut self, history: &PositionHistory, params: &MctsParams) {
+        let state = history.last();
+        self.root_history = history.recent();
+        self.params = *params;
+        // Without an estimate M only counts the plies from the leaves, which says nothing about
+        // the length of the game.
+        self.params.moves_left.enabled &= self.evaluator.has_moves_left();
+        match &mut self.graph {
+            Some(graph) => graph.reset(state.clone()),
+            None => self.graph = Some(GameGraph::new(state.clone())),
+        }
+        self.stats = IterationStats::default();
+        self.total_depth = 0;
+        self.signals.start_search();
+        self.rules = state.rules();
+        self.root_turn = state.position().turn();
+        if let Some(seed) = params.seed {
+            self.rng = Rng::new(seed);
+        }
+
+        let legal_moves = state.position().legal_moves().to_vec();
+        // The filter only applies to this search, as with UCI `go searchmoves`.
+        let mut root_moves = mem::take(&mut self.root_filter).apply(&legal_moves);
+        // With the root in the tablebases, only the allowed moves keeping its result are searched.
+        if let Some(tb) = &self.tablebase {
+            tb.reset_hits();
+            if let Some(tb_moves) = tb.root_moves(state) {
+                root_moves = RootMoveFilter::only(tb_moves).apply(&root_moves);
+            }
+            self.stats.tb_hits = tb.hits();
+        }
+        self.root_moves = (root_moves.len() < legal_moves.len()).then_some(root_moves);
+    }
+
+    fn children_data(graph: &MctsGraph, node: NodeIndex) -> Vec<MctsNodeData> {
+        graph
+            .children(node)
+            .map(|(_, child)| graph.node(child).data)
+            .collect()
+    }
+
+    /// Walks down the tree from the root with the explore selector until reaching a node that
+    /// hasn't been expanded (or has no children). Returns the leaf, its depth and the history
+    /// leading to it, which is updated move by move on the way down.
+    fn select_leaf(&self, graph: &MctsGraph) -> (NodeIndex, usize, PositionHistory) {
+        let mut node = graph.root();
+        let mut ply = 0;
+        let mut history = self.root_history.clone();
+
+        while graph.node(node).expanded {
+            let children_data = Self::children_data(graph, node);
+            if children_data.is_empty() {
+                break;
+            }
+
+            let idx = self
+                .params
+                .explore_index(&graph.node(node).data, &children_data, ply == 0);
+            let (edge, child) = graph.children(node).nth(idx).expect("index of a child");
+            history.append(&graph.edge(edge).move_);
+            node = child;
+            ply += 1;
+        }
+
+        (node, ply, history)
+    }
+
+    /// Gathers a minibatch of leaves, evaluates them together and backpropagates the results.
+    fn run_iteration(&mut self, graph: &mut MctsGraph) -> Result<(), NetworkError> {
+        let mut leaves: Vec<(NodeIndex, usize)> = Vec::new();
+        let mut histories = Vec::new();
+
+        while leaves.len() < self.params.minibatch_size.max(1) {
+            let (node, ply, history) = self.select_leaf(graph);
+            // Selecting the same leaf twice means virtual loss can't diversify the batch further.
+            if leaves.iter().any(|(leaf, _)| *leaf == node) {
+                break;
+            }
+
+            graph.backprop(node, |data, _| data.n_in_flight += 1);
+            leaves.push((node, ply));
+            histories.push(history);
+        }
+
+        let evals = match self.evaluate_leaves(graph, &leaves, &histories) {
+            Ok(evals) => evals,
+            Err(e) => {
+                // The leaves stay unvisited, so the tree remains usable.
+                for (node, _) in leaves {
+                    graph.backprop(node, |data, _| data.n_in_flight -= 1);
+                }
+                return Err(e);
+            }
+        };
+
+        for ((node, ply), (wdl, m)) in leaves.into_iter().zip(evals) {
+            // Node values are from the perspective of the player who moved into the node.
+            let mut wdl = wdl.flipped();
+            let leaf_draw = self.rescaled(Wdl::from_q_d(0.0, 1.0), ply).flipped();
+            let mut draw = leaf_draw;
+            let mut m = m;
+            graph.backprop(node, |data, _| {
+                data.n_in_flight -= 1;
+                data.n += 1;
+                let n = visits(data.n);
+                data.q += (wdl.q() - data.q) / n;
+                data.d += (wdl.d - data.d) / n;
+                data.m += (m - data.m) / n;
+                data.pin_to_certainty(draw);
+                wdl = wdl.flipped();
+                draw = draw.flipped();
+                m += 1.0;
+            });
+            if self.params.solver {
+                Self::propagate_certainty(graph, node, leaf_draw);
+            }
+
+            self.total_depth += ply;
+            self.stats.nodes += 1;
+            self.stats.depth = self.total_depth / self.stats.nodes;
+            self.stats.seldepth = self.stats.seldepth.max(ply);
+        }
+
+        if let Some(tb) = &self.tablebase {
+            self.stats.tb_hits = tb.hits();
+        }
+        Ok(())
+    }
+
+    /// WDL and moves left of each leaf from the perspective of its side to move. Leaves that are
+    /// terminal or proven by the tablebases are marked as such, the others are evaluated in a
+    /// single batch and expanded. `histories` are the ones `select_leaf` built for each leaf.
+    fn evaluate_leaves(
+        &mut self,
+        graph: &mut MctsGraph,
+        leaves: &[(NodeIndex, usize)],
+        histories: &[PositionHistory],
+    ) -> Result<Vec<(Wdl, f32)>, NetworkError> {
+        let mut results = vec![(Wdl::default(), 0.0); leaves.len()];
+        let mut pending = Vec::new();
+        let mut encoder_histories = Vec::new();
+        let mut moves = Vec::new();
+
+        for (idx, (&(node, ply), history)) in leaves.iter().zip(histories).enumerate() {
+            let state = history.last();
+            graph.node_mut(node).hash = history.hash();
+
+            // A draw that could be claimed at the root doesn't end the search, only a root
+            // without legal moves does.
+            let (mut terminal, mut result) = if ply > 0 || state.position().legal_moves().is_empty()
+            {
+                state.classify_terminal(Some(ply))
+            } else {
+                (Terminal::NonTerminal, None)
+            };
+            // The root is always searched, its result is kept by filtering its moves instead.
+            if result.is_none() && ply > 0 {
+                result = self
+                    .tablebase
+                    .as_ref()
+                    .and_then(|tb| tb.probe_result(state));
+                if result.is_some() {
+                    terminal = Terminal::Tablebase;
+                }
+            }
+
+            if let Some(result) = result {
+                let data = &mut graph.node_mut(node).data;
+                data.terminal = terminal;
+                // A repetition inside the tree is only scored as a draw, the game may still be
+                // won or lost from there.
+                if self.params.solver && terminal != Terminal::TwoFold {
+                    let plies = match terminal {
+                        Terminal::Tablebase => TABLEBASE_PLIES,
+                        _ => 0,
+                    };
+                    data.certainty = Certainty::from_winner(
+                        result.outcome.winner(),
+                        state.position().turn(),
+                        plies,
+                    );
+                }
+                let wdl = Self::terminal_wdl(result.outcome, state.position().turn());
+                results[idx] = (self.rescaled(wdl, ply), 0.0);
+            } else {
+                let mut legal_moves = state.position().legal_moves().to_vec();
+                if let (0, Some(root_moves)) = (ply, &self.root_moves) {
+                    legal_moves.retain(|m| root_moves.contains(m));
+                }
+                moves.push(legal_moves);
+                encoder_histories.push(history.encoder_history().to_vec());
+                pending.push(idx);
+            }
+        }
+
+        let evals = self.evaluator.evaluate_batch(&encoder_histories, &moves)?;
+
+        for ((idx, eval), moves) in pending.into_iter().zip(evals).zip(moves) {
+            let (node, ply) = leaves[idx];
+            let mut policy = eval.policy;
+            if ply == 0 {
+                self.add_root_noise(&mut policy);
+            }
+
+            // Added last to first, so that `children` lists them in move generation order.
+            for (move_, p) in moves.into_iter().zip(policy).rev() {
+                let data = MctsNodeData {
+                    p,
+                    ..MctsNodeData::default()
+                };
+                graph.add_unvisited_node(node, move_, data);
+            }
+            graph.node_mut(node).expanded = true;
+
+            results[idx] = (self.rescaled(Wdl::from_q_d(eval.q, eval.d), ply), eval.m);
+        }
+
+        Ok(results)
+    }
+
+    /// Mixes Dirichlet noise into the priors of the root's children, if enabled.
+    fn add_root_noise(&mut self, policy: &mut [f32]) {
+        let epsilon = self.params.dirichlet_epsilon;
+        if epsilon <= 0.0 || policy.is_empty() {
+            return;
+        }
+
+        let noise = self
+            .rng
+            .dirichlet(self.params.dirichlet_alpha, policy.len());
+        for (p, eta) in policy.iter_mut().zip(noise) {
+            *p = (1.0 - epsilon).mul_add(*p, epsilon * eta);
+        }
+    }
+
+    /// Updates the certainty of the ancestors of a newly proven `node`, stopping at the first one
+    /// that doesn't change. `draw` is the value of a draw for the player who moved into `node`.
+    fn propagate_certainty(graph: &mut MctsGraph, node: NodeIndex, draw: Wdl) {
+        let mut current = node;
+        let mut draw = draw;
+
+        while let Some((_, parent)) = graph.parent(current) {
+            let certainty = MctsSolver.propagate_result(&(), &Self::children_data(graph, parent));
+            let data = &mut graph.node_mut(parent).data;
+            if certainty == data.certainty {
+                break;
+            }
+
+            draw = draw.flipped();
+            data.certainty = certainty;
+            data.pin_to_certainty(draw);
+            current = parent;
+        }
+    }
+
+    fn terminal_wdl(outcome: Outcome, turn: Color) -> Wdl {
+        match outcome.winner() {
+            Some(winner) if winner == turn => Wdl::from_q_d(1.0, 0.0),
+            Some(_) => Wdl::from_q_d(-1.0, 0.0),
+            None => Wdl::from_q_d(0.0, 1.0),
+        }
+    }
+
+    /// The move to play. A proven win is played right away, the quickest one if there are
+    /// several, and proven losses only if every move loses. Otherwise one of the moves not proven
+    /// to lose is sampled with the temperature or, at temperature 0, picked by the play selector.
+    /// `None` if the root has no legal moves.
+    fn best_move(&mut self) -> Option<Move> {
+        let graph = self.graph.as_ref().expect("search has not been started");
+        let root = graph.root();
+        let children_data = Self::children_data(graph, root);
+        if children_data.is_empty() {
+            return None;
+        }
+
+        let idx = Self::proven_choice(&children_data).unwrap_or_else(|| {
+            let open = Self::not_lost(&children_data);
+            let open_data: Vec<_> = open.iter().map(|&idx| children_data[idx]).collect();
+            let fullmove = graph.root_state().position().fullmoves().get();
+            let sampled = self
+                .params
+                .play_selector
+                .temperature(self.params.temperature)
+                .sample(&open_data, fullmove, &mut self.rng);
+            open[sampled.unwrap_or_else(|| self.params.play_selector.select(&open_data))]
+        });
+        let (edge, _) = graph.children(root).nth(idx)?;
+        Some(graph.edge(edge).move_.clone())
+    }
+
+    /// Child the search considers best, which the PV follows: the move `best_move` plays at
+    /// temperature 0.
+    fn principal_child(&self, children: &[MctsNodeData]) -> usize {
+        Self::proven_choice(children).unwrap_or_else(|| {
+            let open = Self::not_lost(children);
+            let open_data: Vec<_> = open.iter().map(|&idx| children[idx]).collect();
+            open[self.params.play_selector.select(&open_data)]
+        })
+    }
+
+    /// Indices of the children not proven to lose.
+    fn not_lost(children: &[MctsNodeData]) -> Vec<usize> {
+        (0..children.len())
+            .filter(|&idx| !matches!(children[idx].certainty, Certainty::Loss(_)))
+            .collect()
+    }
+
+    /// Child the proven results decide on: the quickest win, or the slowest loss if all lose.
+    fn proven_choice(children: &[MctsNodeData]) -> Option<usize> {
+        let plies = |idx: &usize| match children[*idx].certainty {
+            Certainty::Win(plies) | Certainty::Loss(plies) => plies,
+            _ => 0,
+        };
+        let wins =
+            (0..children.len()).filter(|&idx| matches!(children[idx].certainty, Certainty::Win(_)));
+        if let Some(idx) = wins.min_by_key(plies) {
+            return Some(idx);
+        }
+
+        if children
+            .iter()
+            .all(|child| matches!(child.certainty, Certainty::Loss(_)))
+        {
+            return (0..children.len()).max_by_key(plies);
+        }
+        None
+    }
+
+    fn search_until(
+        &mut self,
+        mut should_stop: impl FnMut(&IterationStats) -> bool,
+    ) -> Result<(), NetworkError> {
+        let mut graph = self.graph.take().expect("search has not been started");
+        let start = Instant::now();
+
+        let result = loop {
+            if let Err(e) = self.run_iteration(&mut graph) {
+                break Err(e);
+            }
+            self.stats.elapsed = start.elapsed();
+
+            // Further search can't change a proven root.
+            let proven = graph.node(graph.root()).data.certainty.is_proven();
+            if proven || self.signals.is_stopped() || should_stop(&self.stats) {
+                break Ok(());
+            }
+        };
+
+        self.graph = Some(graph);
+        result
+    }
+
+    /// Evaluation or game result of a node `ply` half-moves below the root, from the perspective
+    /// of its side to move, adjusted by the WDL rescaling and the rules of the game. Every value
+    /// that is backpropagated passes through here so the whole tree agrees on the adjusted
+    /// evaluation.
+    fn rescaled(&self, wdl: Wdl, ply: usize) -> Wdl {
+        let perspective = Perspective::from_ply(ply);
+        let wdl = self.params.wdl_rescale.apply(wdl, perspective);
+
+        match self.rules {
+            GameRules::Standard => wdl,
+            GameRules::Armageddon => {
+                let turn = match perspective {
+                    Perspective::Us => self.root_turn,
+                    Perspective::Them => !self.root_turn,
+                };
+                wdl.armageddon(turn)
+            }
+        }
+    }
+
+    /// Children of `node` from best to worst: proven wins (quickest first), then the unproven and
+    /// drawn children in the order of the play selector, then proven losses (slowest first).
+    fn ranked_children(&self, graph: &MctsGraph, node: NodeIndex) -> Vec<(EdgeIndex, NodeIndex)> {
+        let children: Vec<_> = graph.children(node).collect();
+        let children_data = Self::children_data(graph, node);
+
+        let by_certainty = |win: bool| {
+            let mut proven: Vec<(usize, u16)> = children_data
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, child)| match child.certainty {
+                    Certainty::Win(plies) if win => Some((idx, plies)),
+                    Certainty::Loss(plies) if !win => Some((idx, u16::MAX - plies)),
+                    _ => None,
+                })
+                .collect();
+            proven.sort_by_key(|&(_, plies)| plies);
+            proven.into_iter().map(|(idx, _)| idx)
+        };
+        let open: Vec<usize> = (0..children.len())
+            .filter(|&idx| {
+                !matches!(
+                    children_data[idx].certainty,
+                    Certainty::Win(_) | Certainty::Loss(_)
+                )
+            })
+            .collect();
+        let open_data: Vec<_> = open.iter().map(|&idx| children_data[idx]).collect();
+        let ranked_open = self
+            .params
+            .play_selector
+            .rank(&open_data)
+            .into_iter()
+            .map(|idx| open[idx]);
+
+        by_certainty(true)
+            .chain(ranked_open)
+            .chain(by_certainty(false))
+            .map(|idx| children[idx])
+            .collect()
+    }
+
+    /// Principal variation starting with the move into `node`, following the principal child
+    /// at each step as long as it has been visited.
+    fn pv(&self, graph: &MctsGraph, edge: EdgeIndex, node: NodeIndex) -> Vec<Move> {
+        let mut pv = vec![graph.edge(edge).move_.clone()];
+        let mut current = node;
+
+        while let Some((edge, child)) = self.principal_edge(graph, current) {
+            if graph.node(child).data.n == 0 {
+                break;
+            }
+            pv.push(graph.edge(edge).move_.clone());
+            current = child;
+        }
+
+        pv
+    }
+
+    /// Edge to and principal child of `node`, `None` if it has no children.
+    fn principal_edge(&self, graph: &MctsGraph, node: NodeIndex) -> Option<(EdgeIndex, NodeIndex)> {
+        let children_data = Self::children_data(graph, node);
+        if children_data.is_empty() {
+            return None;
+        }
+        graph
+            .children(node)
+            .nth(self.principal_child(&children_data))
+    }
+
+    /// Statistics of the whole search, shared by all lines of the analysis.
+    fn search_info(&self, graph: &MctsGraph) -> uci::Info {
+        let nodes = graph.node(graph.root()).data.n as usize;
+        let millis = self.stats.elapsed.as_millis() as usize;
+
+        uci::Info {
+            depth: Some(self.stats.depth),
+            seldepth: Some(self.stats.seldepth),
+            time: Some(self.stats.elapsed),
+            nodes: Some(nodes),
+            nps: (millis > 0).then(|| nodes * 1000 / millis),
+            tbhits: self.tablebase.as_ref().map(|_| self.stats.tb_hits),
+            ..uci::Info::default()
+        }
+    }
+
+    /// One `info multipv k` line for each of the `multipv` best root moves, carrying its PV and
+    /// score from the root side to move's perspective. Moves proven to end in mate get a mate
+    /// score. Since nothing may follow the PV, each move's visits and prior come on an `info
+    /// string` line of their own afterwards, and a last line reports the memory taken up by the
+    /// tree.
+    fn analysis_lines(&self, multipv: usize) -> Vec<uci::Info> {
+        let graph = self.graph.as_ref().expect("search has not been started");
+        let mode = graph.root_state().castling_mode();
+        let search_info = self.search_info(graph);
+        let best = self
+            .ranked_children(graph, graph.root())
+            .into_iter()
+            .take(multipv.max(1));
+
+        let mut lines = Vec::new();
+        let mut move_stats = Vec::new();
+        for (k, (edge, child)) in best.enumerate() {
+            let data = graph.node(child).data;
+            let pv: Vec<String> = self
+                .pv(graph, edge, child)
+                .iter()
+                .map(|m| Uci::from_move(m, mode).to_string())
+                .collect();
+            move_stats.push(uci::Info {
+                string: Some(format!(
+                    "{} visits {} prior {:.2}%",
+                    pv[0],
+                    data.n,
+                    data.p * 100.0
+                )),
+                ..uci::Info::default()
+            });
+
+            let mut info = uci::Info {
+                multipv: Some(k + 1),
+                pv,
+                ..search_info.clone()
+            }
+            // Child values are already from the perspective of the player choosing the move.
+            .with_wdl(data.wdl());
+            if let Some(score) = data.certainty.mate_score() {
+                info.score = Some(score);
+            }
+            lines.push(info);
+        }
+        lines.extend(move_stats);
+
+        lines.push(uci::Info {
+            string: Some(format!(
+                "tree {} KiB, {} bytes per node",
+                graph.memory_usage() / 1024,
+                graph.bytes_per_node()
+            )),
+            ..uci::Info::default()
+        });
+        lines
     }
 }
 
-impl<TM: TimeManager> SearchStrategy<TM> for Mcts {
+impl<TM: TimeManager, E: NNEvaluator> SearchStrategy<TM> for Mcts<E> {
     type NodeData = MctsNodeData;
     type EdgeData = MctsEdgeData;
     type Params = MctsParams;
-    type Stats = ();
+    type Stats = IterationStats;
 
-    fn name(&self) -> &str {
+    fn name(&self) -> &'static str {
         "MCTS"
     }
 
     fn dynamic_time_search(
         &mut self,
-        state: &GameState,
+        history: &PositionHistory,
         time_manager: &TM,
+        mut limits: SearchLimits,
         params: &mut Self::Params,
-    ) -> Move {
-        todo!();
+    ) -> Result<Option<Move>, SearchError> {
+        self.start_search(history, params);
+        let signals = Arc::clone(&self.signals);
+        // Time spent on the opponent's time, which doesn't count against our limits.
+        let mut ponder_time = Duration::ZERO;
+        let mut was_pondering = false;
+
+        self.search_until(|stats| {
+            if signals.is_pondering() {
+                ponder_time = stats.elapsed;
+                was_pondering = true;
+                return false;
+            }
+            if was_pondering {
+                was_pondering = false;
+                time_manager.ponderhit(stats, &mut limits);
+            }
+
+            let own_stats = IterationStats {
+                elapsed: stats.elapsed.saturating_sub(ponder_time),
+                ..*stats
+            };
+            time_manager.adjust_time_limit(&own_stats, &mut limits);
+            limits.is_reached(&own_stats) || time_manager.should_stop(&own_stats, &limits)
+        })?;
+
+        // A ponder search that ended early, e.g. on a proven root, can't answer before the
+        // opponent has moved.
+        self.signals.wait_while_pondering();
+
+        Ok(self.best_move())
     }
 
     fn fixed_limit_search(
         &mut self,
-        state: &GameState,
+        history: &PositionHistory,
         limits: SearchLimits,
         params: &mut Self::Params,
-    ) -> Move {
-        todo!()
+    ) -> Result<Option<Move>, SearchError> {
+        self.start_search(history, params);
+        self.search_until(|stats| limits.is_reached(stats))?;
+        Ok(self.best_move())
     }
 
     fn parameters(&self) -> &Self::Params {
-        todo!();
+        &self.params
+    }
+
+    fn set_root_filter(&mut self, filter: RootMoveFilter) {
+        self.root_filter = filter;
     }
 
     fn all_stats(&self) -> &Self::Stats {
-        todo!();
+        &self.stats
+    }
+
+    fn analysis(&self, multipv: usize) -> Vec<uci::Info> {
+        self.analysis_lines(multipv)
+    }
+
+    fn signals(&self) -> Arc<SearchSignals> {
+        Arc::clone(&self.signals)
+    }
+
+    /// Reply the PV continues with after `best_move`.
+    fn ponder_move(&self, best_move: &Move) -> Option<Move> {
+        let graph = self.graph.as_ref()?;
+        let (_, node) = graph
+            .children(graph.root())
+            .find(|(edge, _)| graph.edge(*edge).move_ == *best_move)?;
+
+        let (edge, reply) = self.principal_edge(graph, node)?;
+        let data = graph.node(reply).data;
+        // There is nothing to ponder on after a reply that ends the game.
+        (data.n > 0 && data.terminal != Terminal::GameOver).then(|| graph.edge(edge).move_.clone())
+    }
+
+    fn discard_tree(&mut self) {
+        if let Some(graph) = &mut self.graph {
+            let root = graph.root_state().clone();
+            graph.reset(root);
+        }
     }
 }
 
-/// Selection of which child node of root to play:
-/// struct MaxNodes;
-/// impl PlayMoveSelection for MaxNodes {
+/// Selection of which child of the root to play once the search is done. Selectors are
+/// deterministic, so they also rank the moves of the analysis; randomness comes from the
+/// temperature, which is applied before them.
+///
+/// ```ignore
+/// struct MostVisits;
+/// impl PlayMoveSelection for MostVisits {
 ///   type AlgoParams = ();
 ///
-///   fn best_move_index(&self, params: &Self::AlgoParams, root_data: MctsNodeData) -> usize {
-///     // Iterate over all children from root and
+///   fn best_move_index(&self, (): &(), children: &[MctsNodeData]) -> usize {
+///     // Index of the child with the highest `n`
 ///   }
-///
-///   fn best_move(&self, params: &Self::AlgoParams, move_data: MctsNodeData) -> Move {
-///
 /// }
+/// ```
 ///
+/// New selectors are made selectable by adding them to `PlaySelector`.
 trait PlayMoveSelection {
     // includes formula specific parameters and any info needed from search
     type AlgoParams;
 
-    fn best_move_index(&self, params: &Self::AlgoParams, root_data: MctsNodeData) -> usize;
-    fn best_move(&self, params: &Self::AlgoParams, root_data: MctsNodeData) -> Move;
+    fn best_move_index(&self, params: &Self::AlgoParams, children: &[MctsNodeData]) -> usize;
 }
 
-// Selection of which move to explore
+// Selection of which move to explore. Formulas only provide the exploration term, the value
+// term and first play urgency are shared (see `MctsParams::explore_index`). New formulas are made
+// selectable by adding them to `ExploreFormula`.
 trait ExploreMoveSelection {
     type AlgoParams;
 
-    fn best_explore_move(&self, params: &Self::AlgoParams, move_data: MctsNodeData) -> Move;
-    fn best_explore_move_index(&self, params: &Self::AlgoParams, move_data: MctsNodeData) -> usize;
-    fn add_explore_scores_to_nodes(
+    /// U of `child`, where `parent_n` counts the visits started at the parent.
+    fn exploration_term(
         &self,
         params: &Self::AlgoParams,
-        move_data: &mut MctsNodeData,
-        move_data: &mut MctsEdgeData,
-    );
+        child: &MctsNodeData,
+        parent_n: f32,
+    ) -> f32;
 }
 
+// Propagation of proven results from the children of a node to the node itself.
 trait ResultPropagation {
     type AlgoParams;
 
-    fn propagate_result(
-        &self,
-        params: &Self::AlgoParams,
-        move_data: &mut MctsNodeData,
-        move_data: &mut MctsEdgeData,
-    );
+    /// Certainty of a node given the current certainty of its children.
+    fn propagate_result(&self, params: &Self::AlgoParams, children: &[MctsNodeData]) -> Certainty;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chess::{BookSelection, GameState, OpeningBook, PolyglotBook},
+        neural::{Evaluation, MaterialEvaluator},
+        search::{PonderState, SearchManager},
+    };
+    use shakmaty::CastlingMode;
+    use std::thread;
+
+    /// Stops after a fixed time, on our time as well as after a ponderhit.
+    #[derive(Default)]
+    struct FixedTime;
+
+    impl TimeManager for FixedTime {
+        fn should_stop(&self, stats: &IterationStats, _: &SearchLimits) -> bool {
+            stats.elapsed >= Duration::from_millis(100)
+        }
+
+        fn adjust_time_limit(&self, _: &IterationStats, _: &mut SearchLimits) {}
+    }
+
+    fn search(fen: &str, nodes: usize) -> Option<Move> {
+        let mut mcts = Mcts::new(MctsParams::default(), MaterialEvaluator);
+        let mut params = MctsParams::default();
+        SearchStrategy::<FixedTime>::fixed_limit_search(
+            &mut mcts,
+            &PositionHistory::new(GameState::from_fen(fen).unwrap()),
+            SearchLimits::Nodes(nodes),
+            &mut params,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn two_fold_repetitions_are_not_proven() {
+        let mut mcts = Mcts::new(MctsParams::default(), MaterialEvaluator);
+        let mut params = MctsParams::default();
+        SearchStrategy::<FixedTime>::fixed_limit_search(
+            &mut mcts,
+            // Blocked pawns, the kings can only shuffle around.
+            &PositionHistory::new(GameState::from_fen("k7/p7/P7/8/8/8/8/K7 w - - 0 1").unwrap()),
+            SearchLimits::Nodes(2_000),
+            &mut params,
+        )
+        .unwrap();
+
+        let graph = mcts.graph.as_ref().unwrap();
+        let two_folds: Vec<_> = (0..graph.node_count())
+            .map(|idx| graph.node(NodeIndex::new(idx)).data)
+            .filter(|data| data.terminal == Terminal::TwoFold)
+            .collect();
+        assert!(!two_folds.is_empty());
+        assert!(two_folds
+            .iter()
+            .all(|data| data.certainty == Certainty::Unproven));
+    }
+
+    /// Root priors and best move of a search from the starting position.
+    fn root_priors(mut params: MctsParams) -> (Vec<f32>, Option<Move>) {
+        let mut mcts = Mcts::new(params, MaterialEvaluator);
+        let best = SearchStrategy::<FixedTime>::fixed_limit_search(
+            &mut mcts,
+            &PositionHistory::default(),
+            SearchLimits::Nodes(100),
+            &mut params,
+        )
+        .unwrap();
+
+        let graph = mcts.graph.as_ref().unwrap();
+        let priors = graph
+            .children(graph.root())
+            .map(|(_, child)| graph.node(child).data.p)
+            .collect();
+        (priors, best)
+    }
+
+    #[test]
+    fn root_noise_is_mixed_into_the_priors() {
+        let mut params = MctsParams::default();
+        params.set_seed(Some(7));
+        let (plain, _) = root_priors(params);
+        params.set_dirichlet_noise(0.3, 0.25);
+        let (noisy, _) = root_priors(params);
+
+        assert_ne!(plain, noisy);
+        assert!((noisy.iter().sum::<f32>() - 1.0).abs() < 1e-4, "{noisy:?}");
+        // Noise replaces at most epsilon of each prior.
+        for (p, noisy) in plain.iter().zip(&noisy) {
+            assert!(*noisy >= 0.75 * p - 1e-6, "{p} {noisy}");
+        }
+    }
+
+    #[test]
+    fn seeded_searches_are_reproducible() {
+        let mut params = MctsParams::default();
+        params.set_dirichlet_noise(0.3, 0.25);
+        params.set_temperature(TemperatureParams {
+            initial: 1.0,
+            ..TemperatureParams::default()
+        });
+        params.set_seed(Some(42));
+        let first = root_priors(params);
+        assert_eq!(root_priors(params), first);
+
+        params.set_seed(Some(43));
+        assert_ne!(root_priors(params).0, first.0);
+    }
+
+    #[test]
+    fn visited_nodes_store_their_hash() {
+        let state = GameState::new();
+        let mut mcts = Mcts::new(MctsParams::default(), MaterialEvaluator);
+        let mut params = MctsParams::default();
+        SearchStrategy::<FixedTime>::fixed_limit_search(
+            &mut mcts,
+            &PositionHistory::new(state.clone()),
+            SearchLimits::Nodes(200),
+            &mut params,
+        )
+        .unwrap();
+
+        let graph = mcts.graph.as_ref().unwrap();
+        let legal_moves = state.position().legal_moves();
+        let root_moves: Vec<_> = graph
+            .children(graph.root())
+            .map(|(edge, _)| graph.edge(edge).move_.clone())
+            .collect();
+        assert_eq!(root_moves, legal_moves.to_vec());
+
+        let mut stack = vec![(graph.root(), PositionHistory::new(state))];
+        let mut visited = 0;
+        while let Some((node, history)) = stack.pop() {
+            let data = graph.node(node).data;
+            if data.n == 0 {
+                assert_eq!(graph.node(node).hash, 0);
+                continue;
+            }
+            assert_eq!(graph.node(node).hash, history.hash());
+            visited += 1;
+            for (edge, child) in graph.children(node) {
+                let mut history = history.clone();
+                history.append(&graph.edge(edge).move_);
+                stack.push((child, history));
+            }
+        }
+        assert!(visited > 1);
+    }
+
+    #[test]
+    fn root_filter_applies_to_one_search() {
+        let state = GameState::new();
+        let mut mcts = Mcts::new(MctsParams::default(), MaterialEvaluator);
+        let mut params = MctsParams::default();
+        let root_children = |mcts: &Mcts<MaterialEvaluator>| {
+            let graph = mcts.graph.as_ref().unwrap();
+            graph.children(graph.root()).count()
+        };
+
+        let e4 = Uci::from_ascii(b"e2e4")
+            .unwrap()
+            .to_move(state.position())
+            .unwrap();
+        SearchStrategy::<FixedTime>::set_root_filter(
+            &mut mcts,
+            RootMoveFilter::only(vec![e4.clone()]),
+        );
+        let best = SearchStrategy::<FixedTime>::fixed_limit_search(
+            &mut mcts,
+            &PositionHistory::new(state.clone()),
+            SearchLimits::Nodes(50),
+            &mut params,
+        )
+        .unwrap();
+        assert_eq!(best, Some(e4));
+        assert_eq!(root_children(&mcts), 1);
+
+        SearchStrategy::<FixedTime>::fixed_limit_search(
+            &mut mcts,
+            &PositionHistory::new(state),
+            SearchLimits::Nodes(50),
+            &mut params,
+        )
+        .unwrap();
+        assert_eq!(root_children(&mcts), 20);
+    }
+
+    fn manager_after_first_move() -> SearchManager<Mcts<MaterialEvaluator>, FixedTime> {
+        let mcts = Mcts::new(MctsParams::default(), MaterialEvaluator);
+        let mut manager =
+            SearchManager::new(mcts, GameState::new(), FixedTime, SearchLimits::Nodes(300));
+        manager.make_best_move().unwrap();
+        manager
+    }
+
+    /// A manager whose book only has a2a3 from the starting position.
+    fn manager_with_book() -> SearchManager<Mcts<MaterialEvaluator>, FixedTime> {
+        let key = GameState::new().polyglot_hash();
+        // a2a3: from square 8, to square 16
+        let mut bytes = key.to_be_bytes().to_vec();
+        bytes.extend([0x02, 0x10, 0x00, 0x01, 0, 0, 0, 0]);
+        let book = PolyglotBook::from_bytes(&bytes).unwrap();
+
+        let mcts = Mcts::new(MctsParams::default(), MaterialEvaluator);
+        let mut manager =
+            SearchManager::new(mcts, GameState::new(), FixedTime, SearchLimits::Nodes(300));
+        manager.set_book(Some(OpeningBook::new(book, BookSelection::Best, 10, 0)));
+        manager
+    }
+
+    fn root_move(manager: &SearchManager<Mcts<MaterialEvaluator>, FixedTime>, uci: &str) -> Move {
+        Uci::from_ascii(uci.as_bytes())
+            .unwrap()
+            .to_move(manager.root_state().position())
+            .unwrap()
+    }
+
+    #[test]
+    fn book_moves_are_played_without_searching() {
+        let mut manager = manager_with_book();
+
+        let m = manager.make_best_move().unwrap().unwrap();
+        assert_eq!(Uci::from_standard(&m).to_string(), "a2a3");
+        assert!(manager.strategy.graph.is_none());
+        assert_eq!(manager.ponder_state(), &PonderState::Idle);
+
+        // Out of book after the reply.
+        let reply = root_move(&manager, "a7a6");
+        manager.play_move(&reply);
+        manager.make_best_move().unwrap();
+        assert!(manager.strategy.graph.is_some());
+    }
+
+    #[test]
+    fn book_moves_outside_the_root_filter_are_searched() {
+        let mut manager = manager_with_book();
+        let e4 = root_move(&manager, "e2e4");
+        manager.set_root_filter(RootMoveFilter::only(vec![e4.clone()]));
+
+        assert_eq!(manager.make_best_move().unwrap(), Some(e4));
+        assert!(manager.strategy.graph.is_some());
+    }
+
+    #[test]
+    fn root_filter_is_cleared_by_book_moves() {
+        let mut manager = manager_with_book();
+        let e4 = root_move(&manager, "e2e4");
+        manager.set_root_filter(RootMoveFilter::excluding(vec![e4]));
+
+        let m = manager.make_best_move().unwrap().unwrap();
+        assert_eq!(Uci::from_standard(&m).to_string(), "a2a3");
+        assert!(manager.strategy.graph.is_none());
+
+        let reply = root_move(&manager, "a7a6");
+        manager.play_move(&reply);
+        manager.make_best_move().unwrap();
+        let graph = manager.strategy.graph.as_ref().unwrap();
+        let legal_moves = manager.history().states()[2].position().legal_moves();
+        assert_eq!(graph.children(graph.root()).count(), legal_moves.len());
+    }
+
+    #[test]
+    fn ponderhit_before_the_search_starts() {
+        let mut manager = manager_after_first_move();
+        let handle = manager.begin_ponder().unwrap();
+        handle.ponderhit();
+
+        let start = Instant::now();
+        assert!(manager.ponder().unwrap().is_some());
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(!handle.is_pondering());
+    }
+
+    #[test]
+    fn stop_before_the_search_starts_is_a_miss() {
+        let mut manager = manager_after_first_move();
+        let handle = manager.begin_ponder().unwrap();
+        handle.stop();
+
+        assert_eq!(manager.ponder().unwrap(), None);
+        assert_eq!(*manager.ponder_state(), PonderState::Idle);
+        assert!(!handle.is_pondering());
+    }
+
+    #[test]
+    fn pondering_on_a_proven_root_waits_for_the_ponderhit() {
+        let mut mcts = Mcts::new(MctsParams::default(), MaterialEvaluator);
+        let mut params = MctsParams::default();
+        let history =
+            PositionHistory::new(GameState::from_fen("7k/8/6K1/8/8/8/8/R7 w - - 0 1").unwrap());
+        let signals = SearchStrategy::<FixedTime>::signals(&mcts);
+        signals.arm_ponder();
+        let ponderhit = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            signals.ponderhit();
+        });
+
+        let start = Instant::now();
+        let best = SearchStrategy::<FixedTime>::dynamic_time_search(
+            &mut mcts,
+            &history,
+            &FixedTime,
+            SearchLimits::Infinite,
+            &mut params,
+        )
+        .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(200));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(
+            best.map(|m| Uci::from_move(&m, CastlingMode::Standard).to_string()),
+            Some("a1a8".into())
+        );
+        ponderhit.join().unwrap();
+    }
+
+    #[test]
+    fn timed_searches_respect_the_limits() {
+        let history = PositionHistory::new(GameState::new());
+        let nodes = |timed: bool| {
+            let mut mcts = Mcts::new(MctsParams::default(), MaterialEvaluator);
+            let mut params = MctsParams::default();
+            let limits = SearchLimits::Nodes(50);
+            if timed {
+                SearchStrategy::<FixedTime>::dynamic_time_search(
+                    &mut mcts,
+                    &history,
+                    &FixedTime,
+                    limits,
+                    &mut params,
+                )
+            } else {
+                SearchStrategy::<FixedTime>::fixed_limit_search(
+                    &mut mcts,
+                    &history,
+                    limits,
+                    &mut params,
+                )
+            }
+            .unwrap();
+            mcts.stats.nodes
+        };
+        assert_eq!(nodes(true), nodes(false));
+    }
+
+    fn child(n: u32, q: f32) -> MctsNodeData {
+        MctsNodeData {
+            q,
+            n,
+            ..MctsNodeData::default()
+        }
+    }
+
+    /// Material evaluation with a constant moves-left estimate, standing in for an MLH network.
+    #[derive(Clone)]
+    struct WithMovesLeft;
+
+    impl NNEvaluator for WithMovesLeft {
+        type Input = <MaterialEvaluator as NNEvaluator>::Input;
+
+        fn eval_by_info(&self, info: Self::Input) -> Result<f32, NetworkError> {
+            MaterialEvaluator.eval_by_info(info)
+        }
+
+        fn eval_state(&self, state: &GameState, moves: &[Move]) -> Result<f32, NetworkError> {
+            MaterialEvaluator.eval_state(state, moves)
+        }
+
+        fn evaluate(
+            &self,
+            history: &[GameState],
+            moves: &[Move],
+        ) -> Result<Evaluation, NetworkError> {
+            Ok(Evaluation {
+                m: 40.0,
+                ..MaterialEvaluator.evaluate(history, moves)?
+            })
+        }
+
+        fn has_moves_left(&self) -> bool {
+            true
+        }
+    }
+
+    fn with_m(q: f32, m: f32) -> MctsNodeData {
+        MctsNodeData { m, ..child(10, q) }
+    }
+
+    #[test]
+    fn moves_left_prefers_short_wins_and_long_losses() {
+        let params = MctsParams::default();
+        let parent = with_m(0.8, 20.0);
+
+        let wins = params.child_values(&parent, &[with_m(0.8, 10.0), with_m(0.8, 30.0)]);
+        assert!(wins[0] > 0.8 && wins[1] < 0.8, "{wins:?}");
+
+        let losses = params.child_values(&parent, &[with_m(-0.8, 10.0), with_m(-0.8, 30.0)]);
+        assert!(losses[0] < -0.8 && losses[1] > -0.8, "{losses:?}");
+    }
+
+    #[test]
+    fn moves_left_needs_an_estimate() {
+        let history =
+            PositionHistory::new(GameState::from_fen("8/8/8/4k3/8/8/8/4K2R w - - 0 1").unwrap());
+        let mut params = MctsParams::default();
+
+        let mut mcts = Mcts::new(params, MaterialEvaluator);
+        SearchStrategy::<FixedTime>::fixed_limit_search(
+            &mut mcts,
+            &history,
+            SearchLimits::Nodes(50),
+            &mut params,
+        )
+        .unwrap();
+        assert!(!mcts.params.moves_left.enabled);
+        let parent = with_m(0.8, 20.0);
+        let children = [with_m(0.8, 10.0), with_m(0.8, 30.0)];
+        assert_eq!(mcts.params.child_values(&parent, &children), [0.8, 0.8]);
+
+        let mut mcts = Mcts::new(params, WithMovesLeft);
+        SearchStrategy::<FixedTime>::fixed_limit_search(
+            &mut mcts,
+            &history,
+            SearchLimits::Nodes(50),
+            &mut params,
+        )
+        .unwrap();
+        assert!(mcts.params.moves_left.enabled);
+    }
+
+    #[test]
+    fn temperature_samples_before_the_play_selector() {
+        let children = [
+            child(10, 0.1),
+            child(0, 0.9),
+            child(30, 0.2),
+            child(5, -0.9),
+        ];
+        let mut rng = Rng::new(7);
+
+        let cold = TemperatureParams::default();
+        assert_eq!(cold.sample(&children, 1, &mut rng), None);
+        assert_eq!(PlaySelector::MostVisits.select(&children), 2);
+
+        let hot = TemperatureParams {
+            initial: 1.0,
+            value_cutoff: 0.5,
+            ..TemperatureParams::default()
+        };
+        let mut counts = [0; 4];
+        for _ in 0..1000 {
+            counts[hot.sample(&children, 1, &mut rng).unwrap()] += 1;
+        }
+        // Unvisited children and those far below the best Q are never played.
+        assert_eq!(counts[1], 0);
+        assert_eq!(counts[3], 0);
+        assert!(counts[0] > 150 && counts[2] > 600, "{counts:?}");
+    }
+
+    #[test]
+    fn play_selectors_are_found_by_name() {
+        for name in PlaySelector::NAMES {
+            assert_eq!(PlaySelector::from_name(name).unwrap().name(), name);
+        }
+        assert!(matches!(
+            PlaySelector::from_name("LCB"),
+            Ok(PlaySelector::LowerConfidenceBound(_))
+        ));
+        assert!(matches!(
+            PlaySelector::from_name("random"),
+            Err(SelectorError::UnknownPlaySelector(_))
+        ));
+
+        let Ok(PlaySelector::Temperature(temperature)) = PlaySelector::from_name("temperature")
+        else {
+            panic!("not the temperature selector");
+        };
+        assert!(temperature.at_move(1) > 0.0);
+        assert!(temperature.at_move(100) > 0.0);
+    }
+
+    #[test]
+    fn temperature_selector_samples_the_move() {
+        let best_moves = |selector: &str| {
+            (0..10)
+                .map(|seed| {
+                    let mut params = MctsParams::default();
+                    params.set_play_selector_by_name(selector).unwrap();
+                    params.set_seed(Some(seed));
+                    root_priors(params).1.unwrap()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let visits = best_moves("visits");
+        assert!(visits.iter().all(|m| *m == visits[0]), "{visits:?}");
+        let sampled = best_moves("temperature");
+        assert!(sampled.iter().any(|m| *m != sampled[0]), "{sampled:?}");
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 1e-4, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn exploration_terms_follow_their_formulas() {
+        // P = 0.5, N = 3
+        let child = MctsNodeData {
+            p: 0.5,
+            ..child(3, 0.0)
+        };
+
+        // 2 * 0.5 * sqrt(16) / (1 + 3)
+        let alphazero = ExploreFormula::AlphaZeroPuct(PuctParams { cpuct: 2.0 });
+        assert_close(alphazero.exploration_term(&child, 16.0), 1.0);
+
+        // (1 + 2 * ln((100 + 100) / 100)) * 0.5 * sqrt(100) / (1 + 3)
+        let lc0 = ExploreFormula::Lc0Puct(Lc0PuctParams {
+            cpuct: 1.0,
+            cpuct_base: 100.0,
+            cpuct_factor: 2.0,
+        });
+        assert_close(lc0.exploration_term(&child, 100.0), 2.982_868);
+
+        // 2 * sqrt(ln(1 + 15) / (1 + 3))
+        let uct = ExploreFormula::Uct(UctParams { c: 2.0 });
+        assert_close(uct.exploration_term(&child, 15.0), 1.665_109);
+
+        // Visits in flight count as started.
+        let in_flight = MctsNodeData {
+            n_in_flight: 4,
+            ..child
+        };
+        assert_close(alphazero.exploration_term(&in_flight, 16.0), 0.5);
+    }
+
+    #[test]
+    fn explore_formulas_are_found_by_name() {
+        for name in ExploreFormula::NAMES {
+            assert_eq!(ExploreFormula::from_name(name).unwrap().name(), name);
+        }
+        assert!(matches!(
+            ExploreFormula::from_name("ucb1"),
+            Err(SelectorError::UnknownExploreFormula(_))
+        ));
+    }
+
+    /// A parent with one visited child and one unvisited child.
+    fn half_explored() -> (MctsNodeData, [MctsNodeData; 2]) {
+        let visited = MctsNodeData {
+            p: 0.36,
+            ..child(10, 0.1)
+        };
+        let unvisited = MctsNodeData {
+            p: 0.64,
+            ..child(0, 0.0)
+        };
+        (child(20, 0.2), [visited, unvisited])
+    }
+
+    #[test]
+    fn fpu_values() {
+        let (parent, children) = half_explored();
+
+        // -0.2 - 0.5 * sqrt(0.36)
+        assert_close(Fpu::Reduction(0.5).value(&parent, &children), -0.5);
+        assert_close(Fpu::Reduction(0.0).value(&parent, &children), -0.2);
+        assert_close(Fpu::Absolute(1.0).value(&parent, &children), 1.0);
+    }
+
+    #[test]
+    fn root_and_tree_use_their_own_fpu() {
+        let (parent, children) = half_explored();
+        let mut params = MctsParams::default();
+        // Without exploration the choice is between the visited child's Q and the FPU value.
+        params.set_explore_formula(ExploreFormula::AlphaZeroPuct(PuctParams { cpuct: 0.0 }));
+
+        params.set_fpu(FpuParams {
+            tree: Fpu::Reduction(0.5),
+            root: Fpu::Absolute(1.0),
+        });
+        assert_eq!(params.explore_index(&parent, &children, true), 1);
+        assert_eq!(params.explore_index(&parent, &children, false), 0);
+
+        params.set_fpu(FpuParams {
+            tree: Fpu::Absolute(1.0),
+            root: Fpu::Reduction(0.5),
+        });
+        assert_eq!(params.explore_index(&parent, &children, true), 0);
+        assert_eq!(params.explore_index(&parent, &children, false), 1);
+    }
+
+    /// Searches `fen` and returns the root's certainty with the first analysis line.
+    fn solve(fen: &str, nodes: usize) -> (Certainty, uci::Info) {
+        let history = PositionHistory::new(GameState::from_fen(fen).unwrap());
+        let mut params = MctsParams::default();
+        let mut mcts = Mcts::new(params, MaterialEvaluator);
+        SearchStrategy::<FixedTime>::fixed_limit_search(
+            &mut mcts,
+            &history,
+            SearchLimits::Nodes(nodes),
+            &mut params,
+        )
+        .unwrap();
+
+        let graph = mcts.graph.as_ref().unwrap();
+        let certainty = graph.node(graph.root()).data.certainty;
+        (certainty, mcts.analysis_lines(1).remove(0))
+    }
+
+    #[test]
+    fn proven_wins_reach_the_root() {
+        // Ra8#, the root is lost for black who moved into it.
+        let (certainty, info) = solve("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 200);
+        assert_eq!(certainty, Certainty::Loss(1));
+        assert_eq!(info.score, Some(uci::Score::Mate(1)));
+        assert_eq!(info.pv, ["a1a8"]);
+
+        // Kb6 Kb8 Rh8#
+        let (certainty, info) = solve("k7/8/2K5/8/8/8/8/7R w - - 0 1", 2000);
+        assert_eq!(certainty, Certainty::Loss(3));
+        assert_eq!(info.score, Some(uci::Score::Mate(2)));
+    }
+
+    #[test]
+    fn proven_losses_reach_the_root() {
+        // Kb8 is forced, then Rh8#.
+        let (certainty, info) = solve("k7/8/1K6/8/8/8/8/7R b - - 0 1", 200);
+        assert_eq!(certainty, Certainty::Win(2));
+        assert_eq!(info.score, Some(uci::Score::Mate(-1)));
+        assert_eq!(info.pv, ["a8b8", "h1h8"]);
+    }
+
+    #[test]
+    fn multipv_lines_end_with_the_pv() {
+        let mut mcts = Mcts::new(MctsParams::default(), MaterialEvaluator);
+        let mut params = MctsParams::default();
+        SearchStrategy::<FixedTime>::fixed_limit_search(
+            &mut mcts,
+            &PositionHistory::default(),
+            SearchLimits::Nodes(500),
+            &mut params,
+        )
+        .unwrap();
+
+        let lines: Vec<String> = SearchStrategy::<FixedTime>::analysis(&mcts, 3)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        let (pv_lines, string_lines): (Vec<_>, Vec<_>) =
+            lines.iter().partition(|line| line.contains(" pv "));
+
+        assert_eq!(pv_lines.len(), 3);
+        assert!(pv_lines.iter().all(|line| !line.contains(" string ")));
+        assert_eq!(string_lines.len(), 4);
+        assert!(string_lines
+            .iter()
+            .all(|line| line.starts_with("info string ")));
+        assert!(string_lines[0].contains(" visits "));
+        assert!(string_lines[3].contains(" KiB"));
+    }
+
+    #[test]
+    fn repetitions_before_the_root_are_detected() {
+        let mut history = PositionHistory::default();
+        for m in [
+            "g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6", "f3g1", "f6g8", "g1f3", "g8f6",
+        ] {
+            let m = Uci::from_ascii(m.as_bytes())
+                .unwrap()
+                .to_move(history.last().position())
+                .unwrap();
+            history.append(&m);
+        }
+
+        let mut mcts = Mcts::new(MctsParams::default(), MaterialEvaluator);
+        let mut params = MctsParams::default();
+        SearchStrategy::<FixedTime>::fixed_limit_search(
+            &mut mcts,
+            &history,
+            SearchLimits::Nodes(100),
+            &mut params,
+        )
+        .unwrap();
+
+        // Going back to g1 repeats the position for the third time.
+        let graph = mcts.graph.as_ref().unwrap();
+        let (_, back) = graph
+            .children(graph.root())
+            .find(|(edge, _)| {
+                Uci::from_move(&graph.edge(*edge).move_, CastlingMode::Standard).to_string()
+                    == "f3g1"
+            })
+            .unwrap();
+        assert_eq!(graph.node(back).data.terminal, Terminal::GameOver);
+    }
+
+    #[test]
+    fn claimable_draws_at_the_root_are_searched() {
+        // Insufficient material and the 50-move rule only apply below the root.
+        assert!(search("8/8/3k4/8/8/3K4/8/8 w - - 0 1", 50).is_some());
+        assert!(search("8/8/3k4/8/8/3K4/8/6R1 w - - 100 80", 50).is_some());
+    }
+
+    #[test]
+    #[allow(clippy::float_cmp)] // A draw without contempt is exactly zero.
+    fn contempt_rescales_terminal_draws() {
+        // Qg6 stalemates.
+        let history =
+            PositionHistory::new(GameState::from_fen("7k/8/8/6Q1/8/8/8/K7 w - - 0 1").unwrap());
+        let stalemate_q = |wdl_rescale: WdlRescale| {
+            let mut params = MctsParams {
+                wdl_rescale,
+                ..MctsParams::default()
+            };
+            let mut mcts = Mcts::new(params, MaterialEvaluator);
+            SearchStrategy::<FixedTime>::fixed_limit_search(
+                &mut mcts,
+                &history,
+                SearchLimits::Nodes(200),
+                &mut params,
+            )
+            .unwrap();
+
+            let graph = mcts.graph.as_ref().unwrap();
+            let (_, node) = graph
+                .children(graph.root())
+                .find(|(edge, _)| {
+                    Uci::from_move(&graph.edge(*edge).move_, CastlingMode::Standard).to_string()
+                        == "g5g6"
+                })
+                .unwrap();
+            let data = &graph.node(node).data;
+            assert_eq!(data.terminal, Terminal::GameOver);
+            data.q
+        };
+
+        assert_eq!(stalemate_q(WdlRescale::default()), 0.0);
+        // Seen from white, who the contempt favours. Like an evaluated dead draw it gains far less
+        // than an open position would.
+        let q = stalemate_q(WdlRescale::from_contempt(200.0));
+        assert!(q > 0.0 && q < 0.01, "{q}");
+    }
+
+    #[test]
+    fn no_move_without_legal_moves() {
+        // Checkmated and stalemated.
+        assert_eq!(search("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1", 10), None);
+        assert_eq!(search("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1", 10), None);
+
+        let mcts = Mcts::new(MctsParams::default(), MaterialEvaluator);
+        let state = GameState::from_fen("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1").unwrap();
+        let mut manager = SearchManager::new(mcts, state, FixedTime, SearchLimits::Nodes(10));
+        assert_eq!(manager.make_best_move().unwrap(), None);
+    }
 }
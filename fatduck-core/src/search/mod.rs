@@ -1,9 +1,37 @@
 mod graph;
 mod mcts;
+mod wdl;
 
-use crate::{chess::GameState, time::TimeManager};
+pub use graph::{GameEdge, GameGraph, GameNode};
+pub use mcts::{
+    ExploreFormula, Fpu, FpuParams, Lc0PuctParams, LcbParams, Mcts, MctsEdgeData, MctsNodeData,
+    MctsParams, MovesLeftParams, PlaySelector, PuctParams, SelectorError, TemperatureParams,
+    UctParams,
+};
+pub use wdl::{Perspective, Wdl, WdlRescale};
+
+use crate::{
+    chess::{GameState, OpeningBook, PositionHistory},
+    neural::NetworkError,
+    time::TimeManager,
+    uci,
+};
 use shakmaty::Move;
-use std::time::Duration;
+use std::{
+    mem,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Condvar, Mutex, PoisonError,
+    },
+    time::Duration,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SearchError {
+    #[error("Evaluation failed: {0}")]
+    Evaluation(#[from] NetworkError),
+}
 
 #[derive(Clone, Copy, Debug)]
 pub enum SearchLimits {
@@ -15,7 +43,184 @@ pub enum SearchLimits {
 
 impl Default for SearchLimits {
     fn default() -> Self {
-        Self::Time(Duration::from_millis(1_000))
+        Self::Time(Duration::from_secs(1))
+    }
+}
+
+impl SearchLimits {
+    pub fn is_reached(&self, stats: &IterationStats) -> bool {
+        match *self {
+            Self::Time(time) => stats.elapsed >= time,
+            Self::Nodes(nodes) => stats.nodes >= nodes,
+            Self::Depth(depth) => stats.depth >= depth,
+            Self::Infinite => false,
+        }
+    }
+}
+
+/// Restriction of the moves searched at the root. Moves outside of it are never searched, so they
+/// can't be the best move or start the PV either.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RootMoveFilter {
+    /// Only these moves are searched if set, as with UCI `go searchmoves`.
+    pub search_moves: Option<Vec<Move>>,
+    /// Never searched, e.g. to find out what else there is besides the best move.
+    pub excluded: Vec<Move>,
+}
+
+impl RootMoveFilter {
+    /// Searches only `moves`.
+    pub const fn only(moves: Vec<Move>) -> Self {
+        Self {
+            search_moves: Some(moves),
+            excluded: Vec::new(),
+        }
+    }
+
+    /// Searches everything but `moves`.
+    pub const fn excluding(moves: Vec<Move>) -> Self {
+        Self {
+            search_moves: None,
+            excluded: moves,
+        }
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.search_moves.is_none() && self.excluded.is_empty()
+    }
+
+    pub fn allows(&self, m: &Move) -> bool {
+        self.search_moves
+            .as_ref()
+            .is_none_or(|moves| moves.contains(m))
+            && !self.excluded.contains(m)
+    }
+
+    /// The moves of `moves` the filter allows. A filter that would leave nothing is ignored, since
+    /// the root needs a move to play.
+    pub fn apply(&self, moves: &[Move]) -> Vec<Move> {
+        let allowed: Vec<Move> = moves.iter().filter(|m| self.allows(m)).cloned().collect();
+        if allowed.is_empty() {
+            moves.to_vec()
+        } else {
+            allowed
+        }
+    }
+}
+
+/// Statistics every search strategy keeps track of while searching.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IterationStats {
+    pub nodes: usize,
+    pub elapsed: Duration,
+    /// Average depth of the search
+    pub depth: usize,
+    /// Maximum depth reached
+    pub seldepth: usize,
+    /// Successful tablebase probes
+    pub tb_hits: usize,
+}
+
+/// Progress of pondering between searches of a `SearchManager`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum PonderState {
+    #[default]
+    Idle,
+    /// Our last move has been played and its search predicts `expected` as the reply.
+    Ready { expected: Move },
+    /// Searching the position after `expected` on the opponent's time, from the moment
+    /// `SearchManager::begin_ponder` is called.
+    Pondering { expected: Move },
+}
+
+/// Stop and ponder signals shared by a search and the `SearchHandle`s controlling it. The search
+/// polls them between iterations and can block until one of them changes.
+#[derive(Debug, Default)]
+pub struct SearchSignals {
+    stop: AtomicBool,
+    pondering: AtomicBool,
+    /// Held while a signal changes, so a search about to wait on `changed` can't miss it.
+    lock: Mutex<()>,
+    changed: Condvar,
+}
+
+impl SearchSignals {
+    /// Makes a running search return as soon as the current iteration is done.
+    pub fn stop(&self) {
+        self.set(&self.stop, true);
+    }
+
+    /// Ends pondering, which starts the clock of a ponder search.
+    pub fn ponderhit(&self) {
+        self.set(&self.pondering, false);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.stop.load(Ordering::Relaxed)
+    }
+
+    pub fn is_pondering(&self) -> bool {
+        self.pondering.load(Ordering::Relaxed)
+    }
+
+    /// Clears the stop signal, unless a ponder search has been armed: a stop sent since then has
+    /// to be kept.
+    pub fn start_search(&self) {
+        if !self.is_pondering() {
+            self.stop.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Sets up a ponder search before it is started.
+    pub fn arm_ponder(&self) {
+        let _guard = self.lock.lock().unwrap_or_else(PoisonError::into_inner);
+        self.stop.store(false, Ordering::Relaxed);
+        self.pondering.store(true, Ordering::Relaxed);
+    }
+
+    /// Ends pondering after the search returned, `true` if there was no ponderhit.
+    pub fn end_ponder(&self) -> bool {
+        let _guard = self.lock.lock().unwrap_or_else(PoisonError::into_inner);
+        self.pondering.swap(false, Ordering::Relaxed)
+    }
+
+    /// Blocks until a ponderhit or stop, returns at once when not pondering.
+    pub fn wait_while_pondering(&self) {
+        let guard = self.lock.lock().unwrap_or_else(PoisonError::into_inner);
+        let _guard = self
+            .changed
+            .wait_while(guard, |()| self.is_pondering() && !self.is_stopped())
+            .unwrap_or_else(PoisonError::into_inner);
+    }
+
+    fn set(&self, signal: &AtomicBool, value: bool) {
+        let _guard = self.lock.lock().unwrap_or_else(PoisonError::into_inner);
+        signal.store(value, Ordering::Relaxed);
+        self.changed.notify_all();
+    }
+}
+
+/// Controls a running search from another thread, e.g. the one reading UCI commands while the
+/// search blocks.
+#[derive(Clone, Debug)]
+pub struct SearchHandle {
+    signals: Arc<SearchSignals>,
+}
+
+impl SearchHandle {
+    /// UCI `stop`. Stopping a ponder search counts as a miss.
+    pub fn stop(&self) {
+        self.signals.stop();
+    }
+
+    /// UCI `ponderhit`: the opponent played the expected move, so the ponder search goes on as a
+    /// timed search on our time.
+    pub fn ponderhit(&self) {
+        self.signals.ponderhit();
+    }
+
+    pub fn is_pondering(&self) -> bool {
+        self.signals.is_pondering()
     }
 }
 
@@ -27,37 +232,208 @@ impl Default for SearchLimits {
 // let limits = SearchLimits::default(); // searches for 1 second by default
 // let mcts_manager = SearchManager::new(strat, starting_board, time_manager, limits);
 //
-// loop {
-//   // searches for 1 second then sends move
-//   let best_move: Move = mcts_manager.make_best_move();
+// // searches for 1 second then sends move, until the game is over
+// while let Some(best_move) = mcts_manager.make_best_move()? {
 //   Uci::send_move(best_move);
 // }
 #[derive(Default)]
-struct SearchManager<T: SearchStrategy<TM>, TM: TimeManager> {
+pub struct SearchManager<T: SearchStrategy<TM>, TM: TimeManager> {
     strategy: T,
-    root_state: GameState,
+    /// The game so far, its last state being the position to search.
+    history: PositionHistory,
     // tree: Dag<T::NodeData, T::EdgeData>,
     time_manager: TM,
     limits: SearchLimits,
+    /// Number of root moves `analysis` reports on, 0 counts as 1.
+    multipv: usize,
+    ponder: PonderState,
+    /// Consulted before every search of our own move.
+    book: Option<OpeningBook>,
+    /// Root moves of the next search, book probe or ponder search.
+    root_filter: RootMoveFilter,
 }
 
 impl<T: SearchStrategy<TM>, TM: TimeManager> SearchManager<T, TM> {
     pub fn new(strategy: T, root_state: GameState, time_manager: TM, limits: SearchLimits) -> Self {
         Self {
             strategy,
-            root_state,
+            history: PositionHistory::new(root_state),
             time_manager,
             limits,
+            multipv: 1,
+            ponder: PonderState::Idle,
+            book: None,
+            root_filter: RootMoveFilter::default(),
         }
     }
 
-    // Returns best move found within `SearchLimits`
-    pub fn make_best_move(&mut self) -> Move {
-        todo!();
+    /// The `MultiPV` option.
+    pub const fn set_multipv(&mut self, multipv: usize) {
+        self.multipv = multipv;
+    }
+
+    /// Restricts the root moves of the next `make_best_move` or `ponder` only, like UCI
+    /// `go searchmoves`. A book move outside of the filter isn't played.
+    pub fn set_root_filter(&mut self, filter: RootMoveFilter) {
+        self.root_filter = filter;
+    }
+
+    /// Plays from `book` while it has moves, `None` always searches.
+    pub fn set_book(&mut self, book: Option<OpeningBook>) {
+        self.book = book;
+    }
+
+    /// The book, e.g. to disable it for a game.
+    pub const fn book_mut(&mut self) -> Option<&mut OpeningBook> {
+        self.book.as_mut()
+    }
+
+    /// Returns the book move if there is one, otherwise the best move found within `SearchLimits`
+    /// as adjusted by the time manager.
+    /// `None` if the game is over.
+    ///
+    /// # Errors
+    ///
+    /// If the evaluator fails. No move is played then.
+    pub fn make_best_move(&mut self) -> Result<Option<Move>, SearchError> {
+        let filter = mem::take(&mut self.root_filter);
+        if let Some(book_move) = self
+            .book
+            .as_mut()
+            .and_then(|book| book.probe(self.history.last()))
+            .filter(|m| filter.allows(m))
+        {
+            // Nothing searched, so no reply to ponder on either.
+            self.ponder = PonderState::Idle;
+            self.history.append(&book_move);
+            return Ok(Some(book_move));
+        }
+
+        self.strategy.set_root_filter(filter);
+        let mut params = *self.strategy.parameters();
+        let best_move = self.strategy.dynamic_time_search(
+            &self.history,
+            &self.time_manager,
+            self.limits,
+            &mut params,
+        )?;
+        Ok(best_move.map(|m| self.play_own_move(m)))
+    }
+
+    /// Plays our move and remembers the reply the search expects, for pondering.
+    fn play_own_move(&mut self, best_move: Move) -> Move {
+        self.ponder = self
+            .strategy
+            .ponder_move(&best_move)
+            .map_or(PonderState::Idle, |expected| PonderState::Ready {
+                expected,
+            });
+        self.history.append(&best_move);
+        best_move
+    }
+
+    /// Plays the opponent's move when it wasn't pondered on.
+    pub fn play_move(&mut self, m: &Move) {
+        self.history.append(m);
+        self.ponder = PonderState::Idle;
+    }
+
+    /// Replaces the game, e.g. for UCI `position ... moves ...`.
+    pub fn set_history(&mut self, history: PositionHistory) {
+        self.history = history;
+        self.ponder = PonderState::Idle;
+    }
+
+    pub const fn history(&self) -> &PositionHistory {
+        &self.history
+    }
+
+    pub fn root_state(&self) -> &GameState {
+        self.history.last()
+    }
+
+    pub const fn ponder_state(&self) -> &PonderState {
+        &self.ponder
+    }
+
+    /// The reply to ponder on, i.e. the second move of the last search's PV.
+    pub const fn ponder_move(&self) -> Option<&Move> {
+        match &self.ponder {
+            PonderState::Ready { expected } | PonderState::Pondering { expected } => Some(expected),
+            PonderState::Idle => None,
+        }
+    }
+
+    /// Handle to stop the search or signal a ponderhit from another thread.
+    pub fn handle(&self) -> SearchHandle {
+        SearchHandle {
+            signals: self.strategy.signals(),
+        }
     }
 
+    /// Arms pondering on the expected reply and returns the handle to end it. Called on the thread
+    /// reading UCI commands before `ponder` is run elsewhere, so a `ponderhit` or `stop` that
+    /// arrives before the search has started is not lost. `None` if there is nothing to ponder on.
+    pub fn begin_ponder(&mut self) -> Option<SearchHandle> {
+        let PonderState::Ready { expected } = &self.ponder else {
+            return None;
+        };
+        self.ponder = PonderState::Pondering {
+            expected: expected.clone(),
+        };
+
+        let handle = self.handle();
+        handle.signals.arm_ponder();
+        Some(handle)
+    }
+
+    /// Searches the position after the reply armed by `begin_ponder` until
+    /// `SearchHandle::ponderhit` or `SearchHandle::stop`. On a ponderhit the search turns into a
+    /// timed one under the `SearchLimits` and time manager like `make_best_move`, keeping its tree,
+    /// and its best move is played and returned. When stopped first, the opponent is assumed to
+    /// have played something else: the tree is discarded and `None` returned, the actual move is
+    /// then played with `play_move`.
+    ///
+    /// # Errors
+    ///
+    /// If the evaluator fails. The tree is discarded as on a miss.
+    pub fn ponder(&mut self) -> Result<Option<Move>, SearchError> {
+        let filter = mem::take(&mut self.root_filter);
+        let PonderState::Pondering { expected } = &self.ponder else {
+            return Ok(None);
+        };
+        let mut history = self.history.clone();
+        history.append(expected);
+
+        self.strategy.set_root_filter(filter);
+        let signals = self.strategy.signals();
+        let mut params = *self.strategy.parameters();
+        let result = self.strategy.dynamic_time_search(
+            &history,
+            &self.time_manager,
+            self.limits,
+            &mut params,
+        );
+
+        if signals.end_ponder() || result.is_err() {
+            self.strategy.discard_tree();
+            self.ponder = PonderState::Idle;
+            return result.map(|_| None);
+        }
+
+        self.history = history;
+        Ok(result?.map(|m| self.play_own_move(m)))
+    }
+
+    /// UCI info lines of the last search, one `info multipv k` line per analyzed root move
+    /// followed by any extra lines of the strategy.
     pub fn analysis(&self) -> String {
-        todo!();
+        self.strategy
+            .analysis(self.multipv.max(1))
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n")
     }
 
     pub fn iteration_stats(&self) -> String {
@@ -65,7 +441,7 @@ impl<T: SearchStrategy<TM>, TM: TimeManager> SearchManager<T, TM> {
     }
 
     pub fn stop_search(&mut self) {
-        todo!();
+        self.strategy.signals().stop();
     }
 }
 
@@ -77,22 +453,49 @@ pub trait SearchStrategy<TM: TimeManager> {
     type Stats: Default + Copy + Clone;
 
     fn name(&self) -> &str;
-    // Returns the best move given a state and time manager which can dynamically adjust the time
-    // limit.
+    /// Returns the best move, `None` without legal moves, in the last state of `history` given a
+    /// time manager which can dynamically adjust the time limit, starting from `limits`. The
+    /// earlier states count for repetitions and are seen by the evaluator. Time spent pondering
+    /// doesn't count against the limits.
+    ///
+    /// While pondering the search doesn't return before a ponderhit or stop, even when it has
+    /// nothing left to search.
+    ///
+    /// # Errors
+    ///
+    /// If the evaluator fails.
     fn dynamic_time_search(
         &mut self,
-        state: &GameState,
+        history: &PositionHistory,
         time_manager: &TM,
+        limits: SearchLimits,
         params: &mut Self::Params,
-    ) -> Move;
-    // Returns the best move given a state and a fixed search limit.
+    ) -> Result<Option<Move>, SearchError>;
+    /// Returns the best move in the last state of `history` given a fixed search limit.
+    ///
+    /// # Errors
+    ///
+    /// If the evaluator fails.
     fn fixed_limit_search(
         &mut self,
-        state: &GameState,
+        history: &PositionHistory,
         limits: SearchLimits,
         params: &mut Self::Params,
-    ) -> Move;
+    ) -> Result<Option<Move>, SearchError>;
     fn parameters(&self) -> &Self::Params;
+    /// Restricts the root moves of the next search of either kind. Later searches consider all
+    /// legal moves again.
+    fn set_root_filter(&mut self, filter: RootMoveFilter);
     // fn iteration_stats(&self) -> &IterationStats;
     fn all_stats(&self) -> &Self::Stats;
+    /// UCI info lines of the last search for its `multipv` best root moves, best first. Lines
+    /// without `multipv` set carry other information.
+    fn analysis(&self, multipv: usize) -> Vec<uci::Info>;
+    /// Signals of the running search. While pondering, `dynamic_time_search` runs on the
+    /// opponent's time and ignores its limits until a ponderhit.
+    fn signals(&self) -> Arc<SearchSignals>;
+    /// The reply the last search expects after `best_move`.
+    fn ponder_move(&self, best_move: &Move) -> Option<Move>;
+    /// Drops the tree of the last search, e.g. after a ponder miss.
+    fn discard_tree(&mut self);
 }
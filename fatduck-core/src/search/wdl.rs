@@ -0,0 +1,338 @@
+use shakmaty::Color;
+use std::f32::consts::LN_10;
+
+/// Win/draw/loss probabilities from the perspective of the side to move.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Wdl {
+    pub w: f32,
+    pub d: f32,
+    pub l: f32,
+}
+
+impl Wdl {
+    /// Builds the distribution from the value head's Q (= W - L) and draw probability.
+    pub fn from_q_d(q: f32, d: f32) -> Self {
+        Self {
+            w: (1.0 + q - d) / 2.0,
+            d,
+            l: (1.0 - q - d) / 2.0,
+        }
+    }
+
+    pub fn q(&self) -> f32 {
+        self.w - self.l
+    }
+
+    /// The same distribution from the opponent's point of view.
+    #[must_use]
+    pub const fn flipped(&self) -> Self {
+        Self {
+            w: self.l,
+            d: self.d,
+            l: self.w,
+        }
+    }
+
+    /// The distribution in an armageddon game with `turn` to move, where draws are won by black.
+    #[must_use]
+    pub fn armageddon(&self, turn: Color) -> Self {
+        match turn {
+            Color::White => Self {
+                w: self.w,
+                d: 0.0,
+                l: self.l + self.d,
+            },
+            Color::Black => Self {
+                w: self.w + self.d,
+                d: 0.0,
+                l: self.l,
+            },
+        }
+    }
+
+    /// Rounded to permille, as UCI `info score ... wdl` expects. The three values always add up
+    /// to 1000.
+    // Clamped to 0..=1000 before the conversion.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn permille(&self) -> (u32, u32, u32) {
+        let w = (self.w.clamp(0.0, 1.0) * 1000.0).round() as u32;
+        let l = (self.l.clamp(0.0, 1.0) * 1000.0).round() as u32;
+        let l = l.min(1000 - w);
+        (w, 1000 - w - l, l)
+    }
+
+    /// Centipawn score equivalent of Q, using lc0's conversion.
+    // Q is clamped, so the score stays within about ±5700.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn centipawns(&self) -> i32 {
+        (90.0 * (1.563_754_2 * self.q().clamp(-0.99, 0.99)).tan()).round() as i32
+    }
+}
+
+/// Whose point of view an evaluation is from, relative to the side to move at the search root.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Perspective {
+    Us,
+    Them,
+}
+
+impl Perspective {
+    /// Perspective of the side to move `ply` half-moves below the root.
+    pub const fn from_ply(ply: usize) -> Self {
+        if ply.is_multiple_of(2) {
+            Self::Us
+        } else {
+            Self::Them
+        }
+    }
+
+    const fn sign(self) -> f32 {
+        match self {
+            Self::Us => 1.0,
+            Self::Them => -1.0,
+        }
+    }
+}
+
+/// Shifts and rescales the network's WDL with a logistic model, W = σ((μ - 1) / s) and
+/// L = σ((-1 - μ) / s).
+///
+/// A positive `diff` moves μ in favour of the side to move at the root, a `ratio` above 1 makes
+/// draws less likely. Applying it from `Them`'s perspective is the mirror of applying it from
+/// `Us`'s perspective, so both sides see the same adjusted game.
+///
+/// Certain draws (terminal, repetitions, tablebase) are rescaled as a dead drawn position with
+/// tiny win and loss chances, so they agree with the network's evaluation of such positions.
+/// Certain wins and losses are kept.
+#[derive(Clone, Copy, Debug)]
+pub struct WdlRescale {
+    /// Multiplier of the logistic scale s.
+    pub ratio: f32,
+    /// Shift of μ, in units of s.
+    pub diff: f32,
+    /// Upper bound of s, guards against unrealistically broad distributions from the network.
+    pub max_reasonable_s: f32,
+}
+
+impl Default for WdlRescale {
+    fn default() -> Self {
+        Self {
+            ratio: 1.0,
+            diff: 0.0,
+            max_reasonable_s: 1.4,
+        }
+    }
+}
+
+impl WdlRescale {
+    /// Contempt in Elo: positive values make the engine avoid draws, negative values make it
+    /// seek them.
+    pub fn from_contempt(contempt_elo: f32) -> Self {
+        Self {
+            diff: contempt_elo * LN_10 / 400.0,
+            ..Self::default()
+        }
+    }
+
+    /// Models a game against an opponent `elo_diff` Elo weaker (positive) or stronger (negative).
+    /// `draw_ratio` scales the width of the distribution, e.g. to account for a different time
+    /// control than the one the net was trained at.
+    pub fn from_rating_difference(elo_diff: f32, draw_ratio: f32) -> Self {
+        Self {
+            ratio: draw_ratio,
+            ..Self::from_contempt(elo_diff)
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        (self.ratio - 1.0).abs() < f32::EPSILON && self.diff == 0.0
+    }
+
+    // Names follow the logistic model above.
+    #[allow(clippy::many_single_char_names)]
+    pub fn apply(&self, wdl: Wdl, perspective: Perspective) -> Wdl {
+        const EPS: f32 = 0.0001;
+        // A dead drawn position, with tiny win and loss chances.
+        const DEAD_DRAW: Wdl = Wdl {
+            w: 0.001,
+            d: 0.998,
+            l: 0.001,
+        };
+
+        if self.is_identity() {
+            return wdl;
+        }
+
+        let wdl = if wdl.d >= 1.0 - EPS { DEAD_DRAW } else { wdl };
+
+        // The logistic model can't represent certain outcomes.
+        let in_range = |x: f32| x > EPS && x < 1.0 - EPS;
+        if !(in_range(wdl.w) && in_range(wdl.d) && in_range(wdl.l)) {
+            return wdl;
+        }
+
+        let a = (1.0 / wdl.l - 1.0).ln();
+        let b = (1.0 / wdl.w - 1.0).ln();
+        let s = (2.0 / (a + b)).min(self.max_reasonable_s);
+        let mu = (a - b) / (a + b);
+
+        let s_new = s * self.ratio;
+        let mu_new = (perspective.sign() * s).mul_add(self.diff, mu);
+
+        let w = logistic((mu_new - 1.0) / s_new);
+        let l = logistic((-1.0 - mu_new) / s_new);
+
+        Wdl {
+            w,
+            d: (1.0 - w - l).max(0.0),
+            l,
+        }
+    }
+}
+
+fn logistic(x: f32) -> f32 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Wdl, b: Wdl) {
+        let close = |x: f32, y: f32| (x - y).abs() < 1e-5;
+        assert!(
+            close(a.w, b.w) && close(a.d, b.d) && close(a.l, b.l),
+            "{a:?} != {b:?}"
+        );
+    }
+
+    #[test]
+    fn q_and_draws_round_trip() {
+        let wdl = Wdl::from_q_d(0.3, 0.4);
+        assert_close(
+            wdl,
+            Wdl {
+                w: 0.45,
+                d: 0.4,
+                l: 0.15,
+            },
+        );
+        assert!((wdl.q() - 0.3).abs() < 1e-6);
+        assert_close(wdl.flipped(), Wdl::from_q_d(-0.3, 0.4));
+    }
+
+    #[test]
+    fn permille_adds_up_to_1000() {
+        assert_eq!(
+            Wdl {
+                w: 0.3335,
+                d: 0.333,
+                l: 0.3335
+            }
+            .permille(),
+            (334, 332, 334)
+        );
+        assert_eq!(Wdl::from_q_d(1.0, 0.0).permille(), (1000, 0, 0));
+        assert_eq!(
+            Wdl {
+                w: 0.7,
+                d: -0.1,
+                l: 0.4
+            }
+            .permille(),
+            (700, 0, 300)
+        );
+    }
+
+    #[test]
+    fn centipawns_follow_the_sign_of_q() {
+        assert_eq!(Wdl::from_q_d(0.0, 0.5).centipawns(), 0);
+        assert_eq!(
+            Wdl::from_q_d(0.5, 0.2).centipawns(),
+            -Wdl::from_q_d(-0.5, 0.2).centipawns()
+        );
+        assert!(Wdl::from_q_d(1.0, 0.0).centipawns() > 1000);
+    }
+
+    #[test]
+    fn armageddon_gives_draws_to_black() {
+        let wdl = Wdl {
+            w: 0.2,
+            d: 0.5,
+            l: 0.3,
+        };
+        assert_close(
+            wdl.armageddon(Color::White),
+            Wdl {
+                w: 0.2,
+                d: 0.0,
+                l: 0.8,
+            },
+        );
+        assert_close(
+            wdl.armageddon(Color::Black),
+            Wdl {
+                w: 0.7,
+                d: 0.0,
+                l: 0.3,
+            },
+        );
+    }
+
+    #[test]
+    fn default_rescale_is_the_identity() {
+        let wdl = Wdl::from_q_d(0.2, 0.3);
+        assert!(WdlRescale::default().is_identity());
+        assert_eq!(WdlRescale::default().apply(wdl, Perspective::Us), wdl);
+        assert_close(
+            WdlRescale::from_rating_difference(0.0, 1.0).apply(wdl, Perspective::Them),
+            wdl,
+        );
+    }
+
+    #[test]
+    fn contempt_favours_the_root() {
+        let rescale = WdlRescale::from_contempt(100.0);
+        let wdl = Wdl::from_q_d(0.1, 0.5);
+
+        assert!(rescale.apply(wdl, Perspective::Us).q() > wdl.q());
+        assert!(rescale.apply(wdl, Perspective::Them).q() < wdl.q());
+    }
+
+    #[test]
+    fn rescaling_is_symmetric() {
+        for rescale in [
+            WdlRescale::from_contempt(150.0),
+            WdlRescale::from_rating_difference(-80.0, 1.3),
+        ] {
+            for wdl in [
+                Wdl::from_q_d(0.3, 0.4),
+                Wdl::from_q_d(-0.6, 0.2),
+                Wdl::from_q_d(0.0, 1.0),
+            ] {
+                assert_close(
+                    rescale.apply(wdl, Perspective::Us),
+                    rescale.apply(wdl.flipped(), Perspective::Them).flipped(),
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn narrower_scale_makes_draws_more_likely() {
+        let wdl = Wdl::from_q_d(0.2, 0.4);
+        let rescaled = WdlRescale::from_rating_difference(0.0, 0.8).apply(wdl, Perspective::Us);
+        assert!(rescaled.d > wdl.d);
+    }
+
+    #[test]
+    fn certain_draws_are_rescaled_but_certain_results_are_kept() {
+        let rescale = WdlRescale::from_contempt(200.0);
+
+        let draw = rescale.apply(Wdl::from_q_d(0.0, 1.0), Perspective::Us);
+        assert!(draw.q() > 0.0 && draw.d < 1.0, "{draw:?}");
+
+        let win = Wdl::from_q_d(1.0, 0.0);
+        assert_eq!(rescale.apply(win, Perspective::Them), win);
+    }
+}
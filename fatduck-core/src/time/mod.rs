@@ -1,14 +1,19 @@
 use crate::search::{IterationStats, SearchLimits};
 
 /// Responsible for deciding when to stop the search based off of `SearchStats` and `SearchLimits`.
+///
 /// Each `TimeManager` implementation will have the ability to work off of the common base stats in
 /// `SearchStats` but can also specialize for a particular `SearchStats` implementation.
 pub trait TimeManager: Default + Sized {
     fn should_stop(&self, search_info: &IterationStats, search_limits: &SearchLimits) -> bool;
     fn adjust_time_limit(&self, search_info: &IterationStats, search_limits: &mut SearchLimits);
+    /// Called once when a ponder search becomes a timed search, with the work done on the
+    /// opponent's time in `search_info`. Since that tree is kept, a manager may want to spend
+    /// less of our own time.
+    fn ponderhit(&self, _search_info: &IterationStats, _search_limits: &mut SearchLimits) {}
 }
 
-pub(crate) enum TimeUsageHint {
+pub enum TimeUsageHint {
     Normal,
     NeedMoreTime,
     ImmediateMove,
@@ -0,0 +1,286 @@
+use crate::{
+    chess::{GameRules, GameState, SyzygyTablebase, TablebaseError},
+    search::Wdl,
+};
+use shakmaty::{uci::Uci, CastlingMode, Move};
+use std::{fmt, time::Duration};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum UciError {
+    #[error("No such option: '{0}'")]
+    UnknownOption(String),
+    #[error("Invalid value '{value}' for option '{name}'")]
+    InvalidValue { name: String, value: String },
+    #[error("Illegal move: '{0}'")]
+    IllegalMove(String),
+}
+
+/// Most root moves the `MultiPV` option reports on.
+pub const MAX_MULTIPV: usize = 500;
+
+/// Engine options set with `setoption`.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// `UCI_Chess960`: castling is sent and received as the king taking its own rook.
+    pub chess960: bool,
+    /// `Armageddon`: draws count as wins for black.
+    pub armageddon: bool,
+    /// `SyzygyPath`: directories with Syzygy tables, separated like `PATH`. `None` when unset.
+    pub syzygy_path: Option<String>,
+    /// `MultiPV`: number of root moves reported, for `SearchManager::set_multipv`.
+    pub multipv: usize,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            chess960: false,
+            armageddon: false,
+            syzygy_path: None,
+            multipv: 1,
+        }
+    }
+}
+
+impl Options {
+    /// `option` lines sent in reply to `uci`.
+    pub fn declarations() -> Vec<String> {
+        vec![
+            "option name UCI_Chess960 type check default false".to_string(),
+            "option name Armageddon type check default false".to_string(),
+            "option name SyzygyPath type string default <empty>".to_string(),
+            format!("option name MultiPV type spin default 1 min 1 max {MAX_MULTIPV}"),
+        ]
+    }
+
+    /// Handles `setoption name <name> value <value>`. Option names are case insensitive.
+    ///
+    /// # Errors
+    ///
+    /// If there is no such option or the value doesn't fit its type.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), UciError> {
+        let invalid = || UciError::InvalidValue {
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+
+        match name.to_ascii_lowercase().as_str() {
+            "uci_chess960" => self.chess960 = value.parse().map_err(|_| invalid())?,
+            "armageddon" => self.armageddon = value.parse().map_err(|_| invalid())?,
+            "syzygypath" => {
+                self.syzygy_path = match value.trim() {
+                    "" | "<empty>" => None,
+                    path => Some(path.to_string()),
+                }
+            }
+            "multipv" => {
+                self.multipv = value
+                    .trim()
+                    .parse()
+                    .ok()
+                    .filter(|multipv| (1..=MAX_MULTIPV).contains(multipv))
+                    .ok_or_else(invalid)?;
+            }
+            _ => return Err(UciError::UnknownOption(name.to_string())),
+        }
+        Ok(())
+    }
+
+    /// Rules to set up positions from `position` commands with.
+    pub const fn rules(&self) -> GameRules {
+        if self.armageddon {
+            GameRules::Armageddon
+        } else {
+            GameRules::Standard
+        }
+    }
+
+    /// Loads the tables of `SyzygyPath` to pass to the search, `None` if it isn't set.
+    ///
+    /// # Errors
+    ///
+    /// If a directory can't be read or none of them contains any tables.
+    pub fn tablebase(&self) -> Result<Option<SyzygyTablebase>, TablebaseError> {
+        self.syzygy_path
+            .as_deref()
+            .map(SyzygyTablebase::from_paths)
+            .transpose()
+    }
+
+    pub const fn castling_mode(&self) -> CastlingMode {
+        CastlingMode::from_chess960(self.chess960)
+    }
+
+    /// `m` in UCI notation, castling as king takes rook in Chess960 mode and as the king's two
+    /// square move otherwise.
+    pub fn format_move(&self, m: &Move) -> String {
+        Uci::from_move(m, self.castling_mode()).to_string()
+    }
+
+    /// Parses a move of `state` in either castling notation.
+    ///
+    /// # Errors
+    ///
+    /// If `uci` isn't a legal move of `state`.
+    pub fn parse_move(&self, state: &GameState, uci: &str) -> Result<Move, UciError> {
+        uci.parse::<Uci>()
+            .ok()
+            .and_then(|m| m.to_move(state.position()).ok())
+            .ok_or_else(|| UciError::IllegalMove(uci.to_string()))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Score {
+    Centipawns(i32),
+    /// Mate in the given number of moves, negative if the side to move gets mated.
+    Mate(i32),
+}
+
+/// A UCI `info` line. Unset fields are omitted from the output.
+#[derive(Clone, Debug, Default)]
+pub struct Info {
+    pub depth: Option<usize>,
+    pub seldepth: Option<usize>,
+    /// Rank of the line's first move when reporting several (`MultiPV`), starting at 1.
+    pub multipv: Option<usize>,
+    pub time: Option<Duration>,
+    pub nodes: Option<usize>,
+    pub nps: Option<usize>,
+    pub tbhits: Option<usize>,
+    pub score: Option<Score>,
+    pub wdl: Option<Wdl>,
+    pub pv: Vec<String>,
+    /// Free-form text, always printed last since it runs to the end of the line.
+    pub string: Option<String>,
+}
+
+impl Info {
+    /// Score and WDL of an evaluation from the side to move's perspective.
+    #[must_use]
+    pub fn with_wdl(mut self, wdl: Wdl) -> Self {
+        self.score = Some(Score::Centipawns(wdl.centipawns()));
+        self.wdl = Some(wdl);
+        self
+    }
+}
+
+impl fmt::Display for Info {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "info")?;
+
+        if let Some(depth) = self.depth {
+            write!(f, " depth {depth}")?;
+        }
+        if let Some(seldepth) = self.seldepth {
+            write!(f, " seldepth {seldepth}")?;
+        }
+        if let Some(multipv) = self.multipv {
+            write!(f, " multipv {multipv}")?;
+        }
+        if let Some(time) = self.time {
+            write!(f, " time {}", time.as_millis())?;
+        }
+        if let Some(nodes) = self.nodes {
+            write!(f, " nodes {nodes}")?;
+        }
+        match self.score {
+            Some(Score::Centipawns(cp)) => write!(f, " score cp {cp}")?,
+            Some(Score::Mate(moves)) => write!(f, " score mate {moves}")?,
+            None => {}
+        }
+        if let Some(wdl) = self.wdl {
+            let (w, d, l) = wdl.permille();
+            write!(f, " wdl {w} {d} {l}")?;
+        }
+        if let Some(nps) = self.nps {
+            write!(f, " nps {nps}")?;
+        }
+        if let Some(tbhits) = self.tbhits {
+            write!(f, " tbhits {tbhits}")?;
+        }
+        if !self.pv.is_empty() {
+            write!(f, " pv {}", self.pv.join(" "))?;
+        }
+        if let Some(string) = &self.string {
+            write!(f, " string {string}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    #[test]
+    fn options_are_declared() {
+        let declarations = Options::declarations();
+        for name in ["UCI_Chess960", "Armageddon", "SyzygyPath", "MultiPV"] {
+            let declared = format!("option name {name} type");
+            assert!(
+                declarations.iter().any(|d| d.starts_with(&declared)),
+                "{name}"
+            );
+        }
+    }
+
+    #[test]
+    fn syzygy_path_is_set_and_cleared() {
+        let mut options = Options::default();
+        assert!(options.tablebase().unwrap().is_none());
+
+        let empty = env::temp_dir().join(format!("fatduck-uci-syzygy-{}", process::id()));
+        fs::create_dir_all(&empty).unwrap();
+        let path = empty.to_string_lossy().to_string();
+        options.set("syzygypath", &path).unwrap();
+        assert_eq!(options.syzygy_path.as_deref(), Some(path.as_str()));
+        let result = options.tablebase();
+        fs::remove_dir(&empty).unwrap();
+        assert!(matches!(result, Err(TablebaseError::NoTables(_))));
+
+        options.set("SyzygyPath", "<empty>").unwrap();
+        assert_eq!(options.syzygy_path, None);
+        assert!(options.tablebase().unwrap().is_none());
+    }
+
+    #[test]
+    fn multipv_is_limited_to_its_range() {
+        let mut options = Options::default();
+        assert_eq!(options.multipv, 1);
+        assert!(Options::declarations()
+            .contains(&"option name MultiPV type spin default 1 min 1 max 500".to_string()));
+
+        options.set("MultiPV", "3").unwrap();
+        assert_eq!(options.multipv, 3);
+        options.set("multipv", "500").unwrap();
+        assert_eq!(options.multipv, 500);
+
+        for value in ["0", "501", "-1", "all"] {
+            assert!(
+                matches!(
+                    options.set("MultiPV", value),
+                    Err(UciError::InvalidValue { .. })
+                ),
+                "{value}"
+            );
+        }
+        assert_eq!(options.multipv, 500);
+    }
+
+    #[test]
+    fn unknown_options_are_rejected() {
+        let mut options = Options::default();
+        assert!(matches!(
+            options.set("Hash", "16"),
+            Err(UciError::UnknownOption(_))
+        ));
+        assert!(matches!(
+            options.set("Armageddon", "maybe"),
+            Err(UciError::InvalidValue { .. })
+        ));
+    }
+}
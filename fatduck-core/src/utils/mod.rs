@@ -1,3 +1,7 @@
+mod rng;
+
+pub use rng::Rng;
+
 pub fn get_version_string(
     major: u32,
     minor: u32,
@@ -18,6 +22,6 @@ pub fn get_version_string(
     v
 }
 
-pub fn get_version_int(major: u32, minor: u32, patch: u32) -> u32 {
+pub const fn get_version_int(major: u32, minor: u32, patch: u32) -> u32 {
     major * 1_000_000 + minor * 1_000 + patch
 }
@@ -0,0 +1,94 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// `SplitMix64` generator. Small and fast, and the same seed always gives the same sequence, so
+/// anything drawing from it is reproducible.
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub const fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// Seeded from the clock, for when reproducibility doesn't matter.
+    // Only the low bits of the time vary, the rest can be cut off.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn from_time() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos() as u64);
+        Self::new(nanos)
+    }
+
+    pub const fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut x = self.state;
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        x ^ (x >> 31)
+    }
+
+    /// Uniform in [0, 1).
+    // 24 random bits, exact as f32.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1_u64 << 24) as f32
+    }
+
+    /// Uniform in [0, `bound`), `bound` must not be 0.
+    pub fn below(&mut self, bound: u64) -> u64 {
+        // Widening multiply, the bias is negligible for the bounds used here.
+        ((u128::from(self.next_u64()) * u128::from(bound)) >> 64) as u64
+    }
+
+    /// Standard normal sample (Box-Muller).
+    pub fn next_normal(&mut self) -> f32 {
+        // 1 - u is in (0, 1], keeping the logarithm finite.
+        let u1 = 1.0 - self.next_f32();
+        let u2 = self.next_f32();
+        (-2.0 * u1.ln()).sqrt() * (std::f32::consts::TAU * u2).cos()
+    }
+
+    /// Gamma(`alpha`, 1) sample using Marsaglia and Tsang's method, `alpha` must be positive.
+    // Names follow the paper.
+    #[allow(clippy::many_single_char_names)]
+    pub fn gamma(&mut self, alpha: f32) -> f32 {
+        if alpha < 1.0 {
+            // Boost to alpha + 1 and scale back down.
+            let u = 1.0 - self.next_f32();
+            return self.gamma(alpha + 1.0) * u.powf(1.0 / alpha);
+        }
+
+        let d = alpha - 1.0 / 3.0;
+        let c = 1.0 / (9.0 * d).sqrt();
+        loop {
+            let x = self.next_normal();
+            let v = 1.0 + c * x;
+            if v <= 0.0 {
+                continue;
+            }
+
+            let v = v * v * v;
+            let u = 1.0 - self.next_f32();
+            if u < 0.0331f32.mul_add(-x.powi(4), 1.0)
+                || u.ln() < (0.5 * x).mul_add(x, d * (1.0 - v + v.ln()))
+            {
+                return d * v;
+            }
+        }
+    }
+
+    /// Sample of a symmetric Dirichlet distribution over `len` outcomes.
+    pub fn dirichlet(&mut self, alpha: f32, len: usize) -> Vec<f32> {
+        let mut samples: Vec<f32> = (0..len).map(|_| self.gamma(alpha)).collect();
+        let sum: f32 = samples.iter().sum();
+        if sum > 0.0 {
+            for x in &mut samples {
+                *x /= sum;
+            }
+        }
+        samples
+    }
+}
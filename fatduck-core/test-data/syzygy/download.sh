@@ -0,0 +1,19 @@
+#!/bin/sh
+# Downloads the Syzygy tables the tablebase tests probe into this directory. Pass `--all` for the
+# complete 3-4-5 piece set (about 1 GB) instead of the 3-piece tables.
+set -eu
+
+BASE_URL="https://tablebase.lichess.ovh/tables/standard/3-4-5"
+cd "$(dirname "$0")"
+
+if [ "${1:-}" = "--all" ]; then
+    wget --no-verbose --recursive --no-parent --no-directories --no-clobber \
+        --accept "*.rtbw,*.rtbz" "$BASE_URL/"
+    exit 0
+fi
+
+for table in KPvK KNvK KBvK KRvK KQvK; do
+    for ext in rtbw rtbz; do
+        [ -f "$table.$ext" ] || wget --no-verbose "$BASE_URL/$table.$ext"
+    done
+done